@@ -4,7 +4,9 @@ extern crate criterion;
 use criterion::{criterion_group, criterion_main, Criterion};
 
 use cave::grid::{Grid, GridPosition};
-use cave::pathfinding::{AStar, Locomotion, NoOpCost, NoOpLocomotion, Pathfinder, GO_ANYWHERE};
+use cave::pathfinding::{
+    AStar, BidirectionalAStar, Locomotion, NoOpCost, NoOpLocomotion, Pathfinder, GO_ANYWHERE,
+};
 
 fn pathfinding_benchmark(c: &mut Criterion) {
     c.bench_function("Bench Pathfinding 16x16x16 grid", |b| {
@@ -38,6 +40,71 @@ fn pathfinding_benchmark(c: &mut Criterion) {
     });
 }
 
+fn bidirectional_benchmark(c: &mut Criterion) {
+    c.bench_function("Bench Bidirectional Pathfinding 16x16x16 grid", |b| {
+        b.iter(|| {
+            let grid = Grid::with_size(16, 16, 16);
+            let pathfinder = BidirectionalAStar::new();
+            pathfinder.find_path(
+                &grid,
+                &Locomotion::new(&[GO_ANYWHERE]),
+                &GridPosition::new(0, 0, 0),
+                &GridPosition::new(10, 10, 0),
+                &NoOpCost,
+                &NoOpLocomotion,
+            );
+        })
+    });
+
+    c.bench_function("Bench Bidirectional Pathfinding 128x128x128 grid", |b| {
+        b.iter(|| {
+            let grid = Grid::with_size(128, 128, 128);
+            let pathfinder = BidirectionalAStar::new();
+            pathfinder.find_path(
+                &grid,
+                &Locomotion::new(&[GO_ANYWHERE]),
+                &GridPosition::new(0, 0, 0),
+                &GridPosition::new(10, 10, 0),
+                &NoOpCost,
+                &NoOpLocomotion,
+            );
+        })
+    });
+
+    c.bench_function("Bench Cross-map Pathfinding 128x128x128 grid", |b| {
+        b.iter(|| {
+            let grid = Grid::with_size(128, 128, 128);
+            let pathfinder = AStar::new();
+            pathfinder.find_path(
+                &grid,
+                &Locomotion::new(&[GO_ANYWHERE]),
+                &GridPosition::new(0, 0, 0),
+                &GridPosition::new(127, 127, 127),
+                &NoOpCost,
+                &NoOpLocomotion,
+            );
+        })
+    });
+
+    c.bench_function(
+        "Bench Cross-map Bidirectional Pathfinding 128x128x128 grid",
+        |b| {
+            b.iter(|| {
+                let grid = Grid::with_size(128, 128, 128);
+                let pathfinder = BidirectionalAStar::new();
+                pathfinder.find_path(
+                    &grid,
+                    &Locomotion::new(&[GO_ANYWHERE]),
+                    &GridPosition::new(0, 0, 0),
+                    &GridPosition::new(127, 127, 127),
+                    &NoOpCost,
+                    &NoOpLocomotion,
+                );
+            })
+        },
+    );
+}
+
 fn depth_sort_benchmark(c: &mut Criterion) {
     use cave::pigeon::*;
 
@@ -66,6 +133,11 @@ fn depth_sort_benchmark(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, pathfinding_benchmark, depth_sort_benchmark);
+criterion_group!(
+    benches,
+    pathfinding_benchmark,
+    bidirectional_benchmark,
+    depth_sort_benchmark
+);
 
 criterion_main!(benches);
@@ -1,27 +1,35 @@
 use specs::prelude::*;
 
 use crate::common::DeltaTime;
+use crate::grid::Grid;
 use crate::pathfinding::components::Pather;
+use crate::pathfinding::{
+    Cost, CostStrategy, Credentials, DoorCost, Locomotion, LocomotionStrategy, TilemapCost,
+    TilemapLocomotion,
+};
 use crate::position::Position;
+use crate::spatial::SpatialIndex;
+use crate::steering::{is_occupied, steer, Neighbour, Steering, AVOID_RANGE};
+use crate::tilemap::Tilemap;
 
+/// Walks the path its `Pather` finds, at the speed of its `Locomotion`
+///
+/// The `Locomotion` also decides where steering may push the actor, so
+/// actors without one stand still.
 #[derive(Component)]
 #[storage(DenseVecStorage)]
-pub struct Actor {
-    /// In 3D units
-    walk_speed: f64,
-}
+pub struct Actor;
 
 impl Actor {
     pub fn new() -> Self {
-        Actor::with_speed(4.0)
-    }
-
-    pub fn with_speed(walk_speed: f64) -> Self {
-        Actor { walk_speed }
+        Actor
     }
 }
 
 /// Moves actors
+///
+/// Actors steer around the crowd as the `SpatialIndex` last saw it, so every
+/// one of them avoids the same picture regardless of the order they move in.
 pub struct WalkerSystem;
 
 impl WalkerSystem {
@@ -32,43 +40,287 @@ impl WalkerSystem {
 
 impl<'a> System<'a> for WalkerSystem {
     type SystemData = (
+        Entities<'a>,
         Read<'a, DeltaTime>,
+        Read<'a, Grid>,
+        Read<'a, Tilemap>,
+        Read<'a, SpatialIndex>,
         ReadStorage<'a, Actor>,
+        ReadStorage<'a, Credentials>,
+        ReadStorage<'a, Locomotion>,
         WriteStorage<'a, Pather>,
         WriteStorage<'a, Position>,
+        WriteStorage<'a, Steering>,
     );
 
-    fn run(&mut self, (dt, actors, mut pathers, mut positions): Self::SystemData) {
+    fn run(
+        &mut self,
+        (
+            entities,
+            dt,
+            grid,
+            tilemap,
+            index,
+            actors,
+            credentials,
+            locomotions,
+            mut pathers,
+            mut positions,
+            mut steerings,
+        ): Self::SystemData,
+    ) {
         use specs::Join;
 
-        (&actors, &mut pathers, &mut positions)
+        let cost_strat = TilemapCost::new(&tilemap);
+        let loco_strat = TilemapLocomotion::new(&tilemap, &grid);
+
+        let neighbours =
+            |center: &na::Vector3<f64>, range: f64, steerings: &WriteStorage<'a, Steering>| {
+                index
+                    .within_radius(center, range)
+                    .into_iter()
+                    .filter_map(|n| {
+                        let steering = steerings.get(n)?;
+                        Some(Neighbour {
+                            id: n.id(),
+                            pos: *index.position_of(n)?,
+                            velocity: *steering.velocity(),
+                            radius: steering.radius(),
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+        // Velocities are only updated once everyone has moved
+        let mut velocities = vec![];
+
+        for (e, _actor, locomotion, maybe_credentials, pather, pos, steering) in (
+            &entities,
+            &actors,
+            &locomotions,
+            credentials.maybe(),
+            &mut pathers,
+            &mut positions,
+            steerings.maybe(),
+        )
             .join()
+        {
+            // Where the actor wants to go, standing still unless walking a path
+            let mut preferred = na::Vector3::zeros();
+
             // resetting all pathers would result in strange behaviour
-            .filter(|(_actor, pather, _pos)| pather.has_path())
-            .for_each(|(actor, pather, pos)| {
+            if pather.has_path() {
                 if let Some(node) = pather.current() {
-                    let proximity = dt.0 * actor.walk_speed + 0.001;
+                    let proximity = dt.0 * locomotion.walk_speed() + 0.001;
 
-                    let target = na::Vector3::<f64>::new(
-                        node.pos.x() as f64,
-                        node.pos.y() as f64,
-                        node.pos.z() as f64,
-                    );
+                    let target = *node.pos.center().to_vector();
 
                     let diff = target - pos.to_vector();
-                    if diff.magnitude() <= proximity {
+
+                    // Someone else is standing on the goal, close enough is good enough
+                    let crowded = pather.is_last_step()
+                        && steering
+                            .as_ref()
+                            .map(|s| {
+                                diff.magnitude() <= s.radius() * 2.
+                                    && is_occupied(
+                                        e.id(),
+                                        &target,
+                                        &neighbours(&target, AVOID_RANGE, &steerings),
+                                    )
+                            })
+                            .unwrap_or(false);
+
+                    if pather.is_wait_step() {
+                        // Holding position while another actor passes
+                        if pather.wait(dt.0, 1.0 / locomotion.walk_speed()) {
+                            pather.next();
+                        }
+                    } else if diff.magnitude() <= proximity || crowded {
                         // also avoids normalised NaN when diff is [0, 0, 0]
                         pather.next();
                     } else {
-                        let walk_vector = diff.normalize() * actor.walk_speed * dt.0;
-                        let new_pos = pos.to_vector() + walk_vector;
-                        pos.set_x(new_pos.x);
-                        pos.set_y(new_pos.y);
-                        pos.set_z(new_pos.z);
+                        preferred = diff.normalize() * locomotion.walk_speed();
                     }
                 } else {
-                    pather.reset();
+                    pather.finish(pos.to_grid());
+                }
+            }
+
+            // Idle and waiting actors still make room for those pushing past
+            let velocity = match steering {
+                Some(s) => {
+                    let me = Neighbour {
+                        id: e.id(),
+                        pos: *pos.to_vector(),
+                        velocity: *s.velocity(),
+                        radius: s.radius(),
+                    };
+                    steer(&me, &preferred, &neighbours(&me.pos, s.range(), &steerings))
                 }
-            });
+                None => preferred,
+            };
+
+            let door_cost = DoorCost::new(&tilemap, &cost_strat, maybe_credentials);
+            let moved = walk(&door_cost, &loco_strat, locomotion, pos, &(velocity * dt.0));
+            let velocity = if dt.0 > 0. { moved / dt.0 } else { velocity };
+
+            if steering.is_some() {
+                velocities.push((e, velocity));
+            }
+        }
+
+        for (e, velocity) in velocities {
+            if let Some(steering) = steerings.get_mut(e) {
+                steering.set_velocity(velocity);
+            }
+        }
+    }
+}
+
+/// Moves by the given offset, as far as the pathing rules allow.
+///
+/// Steering can push an actor off the line between path nodes. Moves into
+/// cells the actor couldn't path into, such as walls, thin air or doors it
+/// may not open, are rejected, sliding along the wall when only one axis is
+/// blocked. Returns the offset actually moved.
+fn walk<C, L>(
+    cost_strat: &C,
+    loco_strat: &L,
+    locomotion: &Locomotion,
+    pos: &mut Position,
+    offset: &na::Vector3<f64>,
+) -> na::Vector3<f64>
+where
+    C: CostStrategy,
+    L: LocomotionStrategy,
+{
+    let from = pos.to_grid();
+    let attempts = [
+        *offset,
+        na::Vector3::new(offset.x, 0., offset.z),
+        na::Vector3::new(0., offset.y, offset.z),
+    ];
+
+    for attempt in attempts.iter() {
+        let new_pos = pos.to_vector() + attempt;
+        let moved = Position::new(new_pos.x, new_pos.y, new_pos.z);
+        let cell = moved.to_grid();
+        let passable = cost_strat.is_passable(&from, &cell) != Cost::Blocked
+            && loco_strat.is_passable(locomotion, &from, &cell);
+        if cell == from || passable {
+            *pos = moved;
+            return *attempt;
+        }
+    }
+
+    na::Vector3::zeros()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::grid::GridPosition;
+    use crate::pathfinding::GROUND_WALK;
+    use crate::spatial::SpatialIndexSystem;
+    use crate::tilemap::{Door, DoorAccess, DoorState, Tile, TileObj};
+
+    #[test]
+    fn test_idle_actors_separate_without_entering_walls() {
+        let mut world = World::new();
+        world.add_resource(DeltaTime(0.1));
+        world.add_resource(Grid::with_size(4, 4, 2));
+        let mut tilemap = Tilemap::with_size(4, 4, 2);
+        for x in 0..4 {
+            for y in 0..4 {
+                tilemap.set_tile(&GridPosition::new(x, y, 0), Tile::GreyBlock);
+            }
+        }
+        for y in 0..4 {
+            tilemap.set_tile(&GridPosition::new(0, y, 1), Tile::GreyBlock);
+            tilemap.set_tile(&GridPosition::new(2, y, 1), Tile::GreyBlock);
+        }
+        world.add_resource(tilemap);
+        world.add_resource(SpatialIndex::new());
+        world.register::<Actor>();
+        world.register::<Credentials>();
+        world.register::<GridPosition>();
+        world.register::<TileObj>();
+        world.register::<Locomotion>();
+        world.register::<Pather>();
+        world.register::<Position>();
+        world.register::<Steering>();
+
+        // Two idle actors on top of each other, in a tunnel along y
+        let mut actor = |x, y| {
+            world
+                .create_entity()
+                .with(Actor::new())
+                .with(Locomotion::new(&[GROUND_WALK]))
+                .with(Pather::new())
+                .with(Position::new(x, y, 1.))
+                .with(Steering::new())
+                .build()
+        };
+        let a = actor(1.1, 1.0);
+        let b = actor(0.9, 1.1);
+
+        for _ in 0..20 {
+            SpatialIndexSystem::new().run_now(&world.res);
+            WalkerSystem::new().run_now(&world.res);
+        }
+
+        let positions = world.read_storage::<Position>();
+        let (a, b) = (positions.get(a).unwrap(), positions.get(b).unwrap());
+        assert!(
+            (a.to_vector() - b.to_vector()).magnitude() > 0.5,
+            "Expected idle actors to move apart, got {:?} and {:?}",
+            a.to_vector(),
+            b.to_vector()
+        );
+        assert_eq!(1, a.to_grid().x());
+        assert_eq!(1, b.to_grid().x());
+    }
+
+    #[test]
+    fn test_pushes_stop_at_ledges_and_locked_doors() {
+        // A ledge ending at x = 1 along y = 0, and a floor with a locked
+        // door at x = 2 along y = 1
+        let grid = Grid::with_size(4, 2, 2);
+        let mut tilemap = Tilemap::with_size(4, 2, 2);
+        for x in 0..4 {
+            tilemap.set_tile(&GridPosition::new(x, 1, 0), Tile::GreyBlock);
+        }
+        for x in 0..2 {
+            tilemap.set_tile(&GridPosition::new(x, 0, 0), Tile::GreyBlock);
+        }
+        tilemap.set_door(
+            &GridPosition::new(2, 1, 1),
+            Door::new(DoorState::Locked, DoorAccess::Faction(7)),
+        );
+
+        let cost_strat = TilemapCost::new(&tilemap);
+        let loco_strat = TilemapLocomotion::new(&tilemap, &grid);
+        let locomotion = Locomotion::new(&[GROUND_WALK]);
+        let push = na::Vector3::new(0.2, 0., 0.);
+        let stranger = Credentials::new(Some(2), &[]);
+        let member = Credentials::new(Some(7), &[]);
+
+        let mut pos = Position::new(1.4, 0., 1.);
+        let door_cost = DoorCost::new(&tilemap, &cost_strat, Some(&member));
+        walk(&door_cost, &loco_strat, &locomotion, &mut pos, &push);
+        assert_eq!(1.4, pos.x());
+
+        let mut pos = Position::new(1.4, 1., 1.);
+        let door_cost = DoorCost::new(&tilemap, &cost_strat, Some(&stranger));
+        walk(&door_cost, &loco_strat, &locomotion, &mut pos, &push);
+        assert_eq!(1.4, pos.x());
+
+        let door_cost = DoorCost::new(&tilemap, &cost_strat, Some(&member));
+        assert_eq!(
+            push,
+            walk(&door_cost, &loco_strat, &locomotion, &mut pos, &push)
+        );
+        assert_eq!(GridPosition::new(2, 1, 1), pos.to_grid());
     }
 }
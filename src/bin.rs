@@ -1,6 +1,7 @@
 extern crate fps_counter;
 extern crate glutin_window;
 extern crate graphics;
+extern crate image;
 extern crate nalgebra as na;
 extern crate num_traits as nt;
 extern crate opengl_graphics;
@@ -12,6 +13,8 @@ extern crate specs_derive;
 extern crate threadpool;
 
 use glutin_window::GlutinWindow as Window;
+use graphics::types::Color;
+use graphics::ImageSize;
 use opengl_graphics::{GlGraphics, OpenGL, Texture, TextureSettings};
 use piston::event_loop::*;
 use piston::input::*;
@@ -19,117 +22,229 @@ use piston::window::WindowSettings;
 use specs::prelude::*;
 use specs::Entity;
 
+use std::fs::File;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Instant;
 
 mod actor;
+mod canvas;
+mod cli;
+mod clock;
+mod command;
 mod common;
 mod depthsort;
+#[cfg(test)]
+mod golden;
 mod grid;
+mod input;
 mod isometric;
 mod option;
 mod pathfinding;
+mod picking;
 mod pigeon;
 mod position;
+mod replay;
+mod selection;
 mod settings;
+mod spatial;
 mod sprite;
+mod steering;
 mod tilemap;
 mod view;
+mod xray;
 
 use actor::{Actor, WalkerSystem};
+use canvas::{Canvas, CpuTexture};
+use cli::{Args, USAGE};
+use clock::{FixedTimestep, Interpolation, PreviousPosition, SnapshotSystem};
+use command::{CommandQueue, CommandSystem};
 use common::DeltaTime;
 use depthsort::{DepthBuffer, IsometricSorter};
 use grid::{Grid, GridPosition};
+use input::{Action, CameraInputSystem, Input, KeyBindings};
+use isometric::Isometric;
 use pathfinding::{
-    components::Pather, systems::PathfindingSystem, AStar, Locomotion, CLIMB_LADDERS, GROUND_WALK,
+    components::{PathRequest, Pather},
+    systems::{CooperativePathfindingSystem, DoorRepathSystem},
+    CooperativeAStar, Credentials, LandmarkOracle, Locomotion, PathCache, PathfindingStats,
+    ReservationTable, SearchOptions, CLIMB_LADDERS, GROUND_WALK,
 };
+use picking::{Cursor, PickingSystem};
 use position::Position;
-use sprite::{OnRender, Sprite, SpriteRenderer};
+use replay::{state_hash, CommandLog, Recorder};
+use selection::{Selected, SelectionSystem};
+use spatial::{SpatialIndex, SpatialIndexSystem};
+use sprite::{Directional, DirectionalSystem, OnRender, Sprite, SpriteRenderer};
+use steering::Steering;
 use tilemap::{Tile, TileObj, Tilemap};
-use view::{components::IsometricCamera, CutMode, ViewCutMode};
+use view::components::{CameraZoomSystem, IsometricCamera};
+use view::{ViewCutMode, Viewport};
+use xray::{Ghosted, Important, XRayStyle, XRaySystem};
 
-fn create_block(
+// Map Size
+const MAP_WIDTH: u32 = 16;
+const MAP_HEIGHT: u32 = 16;
+const MAP_DEPTH: u32 = 16;
+
+/// Update rate of the simulation, in seconds per tick
+const FIXED_DT: f64 = 1. / 60.;
+
+/// Config file binding keys to actions
+const KEY_BINDINGS: &str = "resources/keybindings.cfg";
+
+/// Seconds in a tick of the reservation table, fine enough that
+/// diagonal steps take close to their true length
+const RESERVATION_TICK: f64 = 0.2;
+
+/// Size of the window when it opens
+const WINDOW_SIZE: (u32, u32) = (640, 480);
+
+/// Size of screenshots taken in headless mode
+const SCREENSHOT_SIZE: (u32, u32) = (640, 480);
+
+/// Colours standing in for the textures when zoomed far out
+const BLOCK_LOD_COLOR: Color = [0.6, 0.6, 0.6, 1.0];
+const LADDER_LOD_COLOR: Color = [0.6, 0.4, 0.2, 1.0];
+const ACTOR_LOD_COLOR: Color = [0.3, 0.4, 1.0, 1.0];
+
+/// Textures of the scene, in the texture type of the graphics backend
+struct Art<I> {
+    block: Arc<I>,
+    man: Arc<I>,
+    ladder: Arc<I>,
+}
+
+impl<I> Art<I> {
+    fn load_with<F>(load: F) -> Art<I>
+    where
+        F: Fn(&str) -> I,
+    {
+        Art {
+            block: Arc::new(load("resources/greybox.png")),
+            man: Arc::new(load("resources/blueman.png")),
+            ladder: Arc::new(load("resources/ladder.png")),
+        }
+    }
+}
+
+fn create_block<I>(
     world: &mut World,
     tile: Tile,
-    block_tex: Arc<Texture>,
+    block_tex: Option<&Arc<I>>,
     grid_pos: &GridPosition,
-) -> Entity {
+) -> Entity
+where
+    I: ImageSize + Send + Sync + 'static,
+{
+    let lod_color = match tile {
+        Tile::Ladder => LADDER_LOD_COLOR,
+        _ => BLOCK_LOD_COLOR,
+    };
     world.write_resource::<Tilemap>().set_tile(&grid_pos, tile);
+    let (anchor_x, anchor_y) = world.read_resource::<Isometric>().block_anchor();
 
-    let mut sprite = Sprite::from_texture(block_tex);
-    sprite.set_anchor(0.5, 70. / 90.);
-
-    // Lower blocks are darker
-    let c = 0.8 + (grid_pos.z() as f32 / 50.);
-    sprite.set_color([c, c, c, 1.0]);
-
-    world
+    let builder = world
         .create_entity()
-        .with(Position::new(
-            grid_pos.x() as f64,
-            grid_pos.y() as f64,
-            grid_pos.z() as f64,
-        ))
-        .with(sprite)
-        .build()
-}
+        .with(grid_pos.center())
+        .with(grid_pos.clone())
+        .with(TileObj::new(grid_pos.clone()));
 
-fn main() {
-    // Change this to OpenGL::V2_1 if not working.
-    let opengl = OpenGL::V3_2;
+    match block_tex {
+        Some(block_tex) => {
+            let mut sprite = Sprite::from_texture(block_tex.clone());
+            sprite.set_anchor(anchor_x, anchor_y);
 
-    // Create an Glutin window.
-    let mut window: Window = WindowSettings::new("cave", [640, 480])
-        .opengl(opengl)
-        .exit_on_esc(true)
-        .build()
-        .unwrap();
+            // Lower blocks are darker
+            let c = 0.8 + (grid_pos.z() as f32 / 50.);
+            sprite.set_color([c, c, c, 1.0]);
+            sprite.set_lod_color(lod_color);
 
-    // Map Size
-    const MAP_WIDTH: u32 = 16;
-    const MAP_HEIGHT: u32 = 16;
-    const MAP_DEPTH: u32 = 16;
+            builder.with(sprite).build()
+        }
+        None => builder.build(),
+    }
+}
 
-    // Setup ECS
-    let mut world = World::new();
+/// Adds the resources and component storages every system needs
+fn setup_world<I>(world: &mut World)
+where
+    I: ImageSize + Send + Sync + 'static,
+{
     world.add_resource(Grid::with_size(MAP_WIDTH, MAP_HEIGHT, MAP_DEPTH));
     world.add_resource(Tilemap::with_size(MAP_WIDTH, MAP_HEIGHT, MAP_DEPTH));
-    world.add_resource(AStar::new());
+    world.add_resource(CooperativeAStar::with_options(
+        80,
+        SearchOptions {
+            max_iterations: Some(50_000),
+            timeout: None,
+            allow_partial: true,
+        },
+    ));
+    world.add_resource(ReservationTable::new(RESERVATION_TICK));
+    world.add_resource(PathCache::default());
+    world.add_resource(LandmarkOracle::default());
+    world.add_resource(PathfindingStats::new());
+    world.add_resource(Isometric::default());
+    world.add_resource(DeltaTime(FIXED_DT));
+    world.add_resource(Interpolation::default());
+    world.add_resource(CommandQueue::default());
+    world.add_resource(Input::default());
+    world.add_resource(Cursor::default());
     world.add_resource(DepthBuffer::new());
+    world.add_resource(Viewport::default());
+    world.add_resource(XRayStyle::default());
     world.add_resource(ViewCutMode::default());
+    world.add_resource(SpatialIndex::new());
     world.register::<Actor>();
     world.register::<IsometricCamera>();
     world.register::<Locomotion>();
+    world.register::<Credentials>();
     world.register::<TileObj>();
-    world.register::<Sprite<Texture>>();
+    world.register::<Sprite<I>>();
+    world.register::<Directional>();
     world.register::<Pather>();
     world.register::<Position>();
+    world.register::<PreviousPosition>();
     world.register::<GridPosition>();
+    world.register::<Selected>();
+    world.register::<Important>();
+    world.register::<Ghosted>();
+    world.register::<Steering>();
+}
 
-    let mut update_dispatcher = DispatcherBuilder::new()
-        .with(PathfindingSystem::new(), "pathfinder", &[])
+fn build_update_dispatcher<'a, 'b, I>() -> Dispatcher<'a, 'b>
+where
+    I: ImageSize + Send + Sync + 'static,
+{
+    DispatcherBuilder::new()
+        .with(CameraInputSystem::new(), "camera_input", &[])
+        .with(CommandSystem::new(), "commands", &["camera_input"])
+        .with(SnapshotSystem::new(), "snapshot", &["commands"])
+        .with(DoorRepathSystem::new(), "door_repath", &[])
+        .with(
+            CooperativePathfindingSystem::new(),
+            "pathfinder",
+            &["door_repath"],
+        )
+        .with(WalkerSystem::new(), "walker", &["snapshot"])
+        .with(DirectionalSystem::<I>::new(), "directional", &["walker"])
+        .with(CameraZoomSystem::new(), "camera_zoom", &["commands"])
+        .with(SpatialIndexSystem::new(), "spatial_index", &["walker"])
+        // Culls with the zoom and positions the frame is drawn with
         .with(
-            IsometricSorter::with_size(MAP_WIDTH, MAP_HEIGHT, MAP_DEPTH),
+            IsometricSorter::<I>::with_size(MAP_WIDTH, MAP_HEIGHT, MAP_DEPTH),
             "isometric_sorter",
-            &[],
+            &["commands", "camera_zoom", "walker", "directional"],
         )
-        .with(WalkerSystem::new(), "walker", &[])
-        .build();
-    let mut render_dispatcher = DispatcherBuilder::new()
-        .with_thread_local(SpriteRenderer::from_graphics(GlGraphics::new(opengl)))
-        .build();
-
-    let sprite_settings = TextureSettings::new();
-    let block_tex = Arc::new(
-        Texture::from_path(PathBuf::from("resources/greybox.png"), &sprite_settings).unwrap(),
-    );
-    let man_tex = Arc::new(
-        Texture::from_path(PathBuf::from("resources/blueman.png"), &sprite_settings).unwrap(),
-    );
-    let ladder_tex = Arc::new(
-        Texture::from_path(PathBuf::from("resources/ladder.png"), &sprite_settings).unwrap(),
-    );
+        .build()
+}
 
+/// Creates the camera, map and actors, with sprites when art is given
+fn build_scene<I>(world: &mut World, art: Option<&Art<I>>)
+where
+    I: ImageSize + Send + Sync + 'static,
+{
     // Build Camera
     world
         .create_entity()
@@ -157,36 +272,20 @@ fn main() {
                 //     continue;
                 // }
                 let grid_pos = GridPosition::new(x, y, z);
-                create_block(&mut world, Tile::GreyBlock, block_tex.clone(), &grid_pos);
+                create_block(world, Tile::GreyBlock, art.map(|a| &a.block), &grid_pos);
             }
         }
     }
 
     // Build Ladders
-    create_block(
-        &mut world,
-        Tile::Ladder,
-        ladder_tex.clone(),
-        &GridPosition::new(5, 5, 8),
-    );
-    create_block(
-        &mut world,
-        Tile::Ladder,
-        ladder_tex.clone(),
-        &GridPosition::new(5, 5, 7),
-    );
-    create_block(
-        &mut world,
-        Tile::Ladder,
-        ladder_tex.clone(),
-        &GridPosition::new(5, 5, 6),
-    );
-    create_block(
-        &mut world,
-        Tile::Ladder,
-        ladder_tex.clone(),
-        &GridPosition::new(5, 5, 5),
-    );
+    for z in (5..9).rev() {
+        create_block(
+            world,
+            Tile::Ladder,
+            art.map(|a| &a.ladder),
+            &GridPosition::new(5, 5, z),
+        );
+    }
 
     // create_block(&mut world, block_tex.clone(), &GridPosition::new(0, 0, 0));
     // create_block(&mut world, block_tex.clone(), &GridPosition::new(1, 1, 1));
@@ -206,100 +305,277 @@ fn main() {
             let z = 9;
             let grid_pos = GridPosition::new(x, y, z);
 
-            let mut sprite = Sprite::from_texture(man_tex.clone());
-            // sprite.set_position(pos.x, pos.y - pos.z);
-            sprite.set_anchor(0.5, 0.9);
-
-            world
+            let mut builder = world
                 .create_entity()
-                .with(Position::new(x as f64, y as f64, z as f64))
-                .with(sprite)
-                .with(Actor::with_speed(1.0))
+                .with(grid_pos.center())
+                .with(PreviousPosition(grid_pos.center()))
+                .with(Actor::new())
+                .with(Important)
                 .with(Pather::with_request(grid_pos, GridPosition::new(9, 9, 5)))
                 .with(Locomotion::new(&[GROUND_WALK, CLIMB_LADDERS]))
-                .build();
+                .with(Steering::new());
+
+            if let Some(art) = art {
+                let mut sprite = Sprite::from_texture(art.man.clone());
+                // sprite.set_position(pos.x, pos.y - pos.z);
+                sprite.set_anchor(0.5, 0.9);
+                sprite.set_lod_color(ACTOR_LOD_COLOR);
+                builder = builder.with(sprite);
+            }
+
+            builder.build();
         }
     }
+}
+
+fn main() {
+    let args = match Args::parse(::std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            ::std::process::exit(2);
+        }
+    };
+
+    if args.headless || args.replay.is_some() {
+        run_headless(&args);
+    } else {
+        run_windowed(&args);
+    }
+}
+
+/// Steps the simulation at a fixed rate without a window, then prints
+/// where everyone ended up.
+///
+/// A replay runs from the start to the recorded end, applying each command
+/// on its tick. With a screenshot path, the final frame is drawn by
+/// the software rasteriser and saved as PNG.
+fn run_headless(args: &Args) {
+    let log = match &args.replay {
+        Some(path) => CommandLog::load(path).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            ::std::process::exit(1);
+        }),
+        None => CommandLog {
+            commands: vec![],
+            end: Some(args.ticks),
+        },
+    };
+    let ticks = log.last_tick();
+    let screenshot = args.screenshot.as_ref();
+
+    let mut world = World::new();
+    setup_world::<CpuTexture>(&mut world);
+    let (width, height) = SCREENSHOT_SIZE;
+    world.add_resource(Viewport::new(f64::from(width), f64::from(height)));
+
+    let art = screenshot.map(|_| Art::load_with(|path| CpuTexture::from_path(path).unwrap()));
+    build_scene(&mut world, art.as_ref());
+
+    let mut update_dispatcher = build_update_dispatcher::<CpuTexture>();
+
+    for tick in 0..ticks {
+        {
+            let mut queue = world.write_resource::<CommandQueue>();
+            for command in log.at(tick) {
+                queue.push(command.clone());
+            }
+        }
+
+        update_dispatcher.dispatch(&world.res);
+        world.maintain();
+    }
+
+    if let Some(path) = screenshot {
+        let (width, height) = SCREENSHOT_SIZE;
+        let mut renderer = SpriteRenderer::from_graphics(Canvas::new(width, height));
+        world.add_resource(OnRender::with_size(width, height));
+        XRaySystem::<CpuTexture>::new().run_now(&world.res);
+        renderer.run_now(&world.res);
+
+        match renderer.target().save(path) {
+            Ok(_) => println!("Wrote {}", path.display()),
+            Err(err) => eprintln!("Failed to write {}: {}", path.display(), err),
+        }
+    }
+
+    println!(
+        "Simulated {} ticks ({:.2}s)",
+        ticks,
+        ticks as f64 * FIXED_DT
+    );
+    print_actors(&world);
+    println!("{}", *world.read_resource::<PathfindingStats>());
+
+    let hash = state_hash(&world, ticks);
+    println!("State hash: {:016x}", hash);
+    if let Some(expected) = args.expect_hash {
+        if hash != expected {
+            eprintln!("State hash differs from the expected {:016x}", expected);
+            ::std::process::exit(1);
+        }
+    }
+}
+
+fn print_actors(world: &World) {
+    use specs::Join;
+
+    let entities = world.entities();
+    let actors = world.read_storage::<Actor>();
+    let positions = world.read_storage::<Position>();
+    let pathers = world.read_storage::<Pather>();
+
+    for (e, _actor, pos, maybe_pather) in (&entities, &actors, &positions, pathers.maybe()).join() {
+        let state = match maybe_pather.map(|p| p.request()) {
+            Some(PathRequest::Request(_, _)) => "searching".to_string(),
+            Some(PathRequest::Ready(result)) if result.is_partial() => {
+                "walking a partial path".to_string()
+            }
+            Some(PathRequest::Ready(_)) => "walking".to_string(),
+            Some(PathRequest::Failed(failure)) => format!("stuck ({:?})", failure),
+            Some(PathRequest::Nothing) | None => "idle".to_string(),
+        };
+
+        let v = pos.to_vector();
+        println!(
+            "Actor {}: ({:.2}, {:.2}, {:.2}) {:?}, {}",
+            e.id(),
+            v.x,
+            v.y,
+            v.z,
+            pos.to_grid(),
+            state
+        );
+    }
+}
+
+/// Reads the key bindings, falling back on the defaults when there are none.
+fn load_bindings() -> KeyBindings {
+    match KeyBindings::load(KEY_BINDINGS) {
+        Ok(Ok(bindings)) => bindings,
+        Ok(Err(err)) => {
+            eprintln!("Invalid key bindings in {}: {}", KEY_BINDINGS, err);
+            KeyBindings::default()
+        }
+        Err(_) => KeyBindings::default(),
+    }
+}
+
+/// Hands an event to the input resource, returning any debug action.
+fn handle_input(world: &World, bindings: &KeyBindings, event: &Event) -> Option<Action> {
+    world
+        .write_resource::<Input>()
+        .handle_event(event, bindings)
+}
+
+fn run_windowed(args: &Args) {
+    // Change this to OpenGL::V2_1 if not working.
+    let opengl = OpenGL::V3_2;
+
+    // Create an Glutin window.
+    let mut window: Window = WindowSettings::new("cave", [WINDOW_SIZE.0, WINDOW_SIZE.1])
+        .opengl(opengl)
+        .exit_on_esc(true)
+        .build()
+        .unwrap();
+
+    // Setup ECS
+    let mut world = World::new();
+    setup_world::<Texture>(&mut world);
+
+    let (width, height) = WINDOW_SIZE;
+    world.add_resource(Viewport::new(f64::from(width), f64::from(height)));
+
+    let mut update_dispatcher = build_update_dispatcher::<Texture>();
+    let mut render_dispatcher = DispatcherBuilder::new()
+        .with(PickingSystem::new(), "picking", &[])
+        .with(SelectionSystem::<Texture>::new(), "selection", &[])
+        .with(XRaySystem::<Texture>::new(), "xray", &["selection"])
+        .with_thread_local(SpriteRenderer::from_graphics(GlGraphics::new(opengl)))
+        .build();
+
+    let sprite_settings = TextureSettings::new();
+    let art =
+        Art::load_with(|path| Texture::from_path(PathBuf::from(path), &sprite_settings).unwrap());
+    build_scene(&mut world, Some(&art));
 
     let settings = EventSettings::new().max_fps(60).ups(60);
+    let mut clock = FixedTimestep::new(FIXED_DT);
+    let mut last_frame = Instant::now();
+
+    let bindings = load_bindings();
+    let mut recorder = args
+        .record
+        .as_ref()
+        .and_then(|path| match Recorder::create(path) {
+            Ok(recorder) => Some(recorder),
+            Err(err) => {
+                eprintln!("Failed to record to {}: {}", path.display(), err);
+                None
+            }
+        });
 
     let mut events = Events::new(settings);
     while let Some(e) = events.next(&mut window) {
-        if let Some(Button::Keyboard(key)) = e.release_args() {
-            use specs::Join;
-
-            // TODO: Move input handling to systems
-            let entities = world.entities();
-            let cameras = world.read_storage::<IsometricCamera>();
-            let mut positions = world.write_storage::<Position>();
-            let mut view_cut = world.write_resource::<ViewCutMode>();
-            let maybe_camera = (&entities, &cameras, &positions)
-                .join()
-                .find(|(_entity, camera, _position)| camera.is_current());
-            if let Some((entity, _camera, pos)) = maybe_camera {
-                match key {
-                    Key::Up => {
-                        positions
-                            .insert(entity, pos + &Position::new(-1., -1., 0.))
-                            .unwrap();
-                    }
-                    Key::Down => {
-                        positions
-                            .insert(entity, pos + &Position::new(1., 1., 0.))
-                            .unwrap();
-                    }
-                    Key::Left => {
-                        positions
-                            .insert(entity, pos + &Position::new(-1., 1., 0.))
-                            .unwrap();
-                    }
-                    Key::Right => {
-                        positions
-                            .insert(entity, pos + &Position::new(1., -1., 0.))
-                            .unwrap();
-                    }
-                    Key::Q => {
-                        positions
-                            .insert(entity, pos + &Position::new(0., 0., 1.))
-                            .unwrap();
-                    }
-                    Key::A => {
-                        positions
-                            .insert(entity, pos + &Position::new(0., 0., -1.))
-                            .unwrap();
-                    }
-                    Key::D1 => {
-                        println!("View from Top");
-                        view_cut.set_mode(CutMode::Top);
-                    }
-                    Key::D2 => {
-                        println!("View from Left");
-                        view_cut.set_mode(CutMode::Left);
-                    }
-                    Key::D3 => {
-                        println!("View from Right");
-                        view_cut.set_mode(CutMode::Right);
-                    }
-                    _ => {}
+        match handle_input(&world, &bindings, &e) {
+            Some(Action::ReportStats) => {
+                println!("{}", *world.read_resource::<PathfindingStats>());
+            }
+            Some(Action::ExportStats) => {
+                let stats = world.read_resource::<PathfindingStats>();
+                match File::create("pathfinding_stats.csv")
+                    .and_then(|mut file| stats.write_csv(&mut file))
+                {
+                    Ok(_) => println!("Wrote pathfinding_stats.csv"),
+                    Err(err) => println!("Failed to write pathfinding stats: {}", err),
                 }
             }
+            _ => {}
         }
 
-        if let Some(u) = e.update_args() {
-            world.add_resource(DeltaTime(u.dt));
-            // pathfinding_system.run_now(&world.res);
-            // walker_system.run_now(&world.res);
-            // isometric_sorter_system.run_now(&world.res);
-            update_dispatcher.dispatch(&mut world.res);
-            world.maintain();
+        if let Some([width, height]) = e.resize_args() {
+            world.write_resource::<Viewport>().resize(width, height);
+        }
+
+        if let Some(pos) = e.mouse_cursor_args() {
+            world.write_resource::<Cursor>().screen = Some(na::Vector2::new(pos[0], pos[1]));
+        }
+
+        if e.update_args().is_some() {
+            // The simulation runs on its own clock, however often updates come
+            let now = Instant::now();
+            let elapsed = now.duration_since(last_frame);
+            last_frame = now;
+
+            let steps = clock.advance(elapsed.as_secs_f64());
+            let first_tick = clock.tick() - u64::from(steps);
+            for tick in first_tick..clock.tick() {
+                update_dispatcher.dispatch(&world.res);
+                world.maintain();
+
+                if let Some(recorder) = recorder.as_mut() {
+                    for command in world.read_resource::<CommandQueue>().applied() {
+                        if let Err(err) = recorder.record(tick, command) {
+                            eprintln!("Failed to record command: {}", err);
+                        }
+                    }
+                }
+            }
         }
 
         if let Some(r) = e.render_args() {
             // app.render(&r);
+            world.add_resource(Interpolation(clock.alpha()));
             world.add_resource(OnRender::new(r));
             // sprite_render_system.run_now(&world.res);
             render_dispatcher.dispatch(&mut world.res);
             world.maintain();
         }
     }
+
+    if let Some(recorder) = recorder {
+        if let Err(err) = recorder.end(clock.tick()) {
+            eprintln!("Failed to finish recording: {}", err);
+        }
+    }
 }
@@ -0,0 +1,340 @@
+//! Software Rasteriser
+//!
+//! A `graphics::Graphics` backend that draws into an image in memory, so
+//! the isometric view can be rendered without a GPU, and saved as PNG.
+
+use std::path::Path;
+
+use graphics::types::Color;
+use graphics::{Context, DrawState, Graphics, ImageSize, Viewport};
+use image::{ImageResult, Rgba, RgbaImage};
+
+use crate::sprite::RenderTarget;
+
+/// Texture kept in memory, for drawing onto a `Canvas`
+pub struct CpuTexture {
+    image: RgbaImage,
+}
+
+impl CpuTexture {
+    pub fn from_image(image: RgbaImage) -> Self {
+        CpuTexture { image }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> ImageResult<Self> {
+        Ok(CpuTexture::from_image(image::open(path)?.to_rgba()))
+    }
+
+    /// Colour at the texture coordinates, which range from 0 to 1
+    fn sample(&self, u: f64, v: f64) -> [f32; 4] {
+        let (w, h) = self.image.dimensions();
+        let x = ((u * f64::from(w)) as i64).max(0).min(i64::from(w) - 1) as u32;
+        let y = ((v * f64::from(h)) as i64).max(0).min(i64::from(h) - 1) as u32;
+
+        to_color(*self.image.get_pixel(x, y))
+    }
+}
+
+impl ImageSize for CpuTexture {
+    fn get_size(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+}
+
+/// Image in memory that can be drawn onto with `graphics`
+///
+/// Triangles are filled by sampling each pixel's centre, with the top-left
+/// rule deciding the pixels on shared edges, so the two triangles of a
+/// sprite never draw a pixel twice. Textures are sampled nearest neighbour,
+/// and any blend mode is treated as alpha blending.
+pub struct Canvas {
+    image: RgbaImage,
+}
+
+impl Canvas {
+    pub fn new(width: u32, height: u32) -> Self {
+        Canvas {
+            image: RgbaImage::new(width, height),
+        }
+    }
+
+    #[inline(always)]
+    pub fn width(&self) -> u32 {
+        self.image.width()
+    }
+
+    #[inline(always)]
+    pub fn height(&self) -> u32 {
+        self.image.height()
+    }
+
+    #[inline(always)]
+    pub fn image(&self) -> &RgbaImage {
+        &self.image
+    }
+
+    pub fn viewport(&self) -> Viewport {
+        let (w, h) = self.image.dimensions();
+        Viewport {
+            rect: [0, 0, w as i32, h as i32],
+            draw_size: [w, h],
+            window_size: [f64::from(w), f64::from(h)],
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> ::std::io::Result<()> {
+        self.image.save(path)
+    }
+
+    /// Fills a triangle, given in normalised device coordinates, with the
+    /// colour returned for each covered pixel's barycentric weights.
+    fn fill_triangle<F>(&mut self, draw_state: &DrawState, verts: [[f32; 2]; 3], shade: F)
+    where
+        F: Fn([f64; 3]) -> [f32; 4],
+    {
+        let (w, h) = self.image.dimensions();
+
+        // Device coordinates point y up, pixels point it down
+        let to_pixel = |v: [f32; 2]| {
+            (
+                (f64::from(v[0]) + 1.) / 2. * f64::from(w),
+                (1. - f64::from(v[1])) / 2. * f64::from(h),
+            )
+        };
+        let mut p = [to_pixel(verts[0]), to_pixel(verts[1]), to_pixel(verts[2])];
+        let mut order = [0, 1, 2];
+
+        let area = edge(p[0], p[1], p[2]);
+        if area == 0. {
+            return;
+        }
+        if area < 0. {
+            // Wind every triangle the same way, for the edge tests
+            p.swap(1, 2);
+            order.swap(1, 2);
+        }
+        let area = area.abs();
+
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (0, 0, w, h);
+        if let Some([sx, sy, sw, sh]) = draw_state.scissor {
+            min_x = sx;
+            min_y = sy;
+            max_x = max_x.min(sx + sw);
+            max_y = max_y.min(sy + sh);
+        }
+
+        let left = p.iter().map(|v| v.0).fold(f64::INFINITY, f64::min);
+        let right = p.iter().map(|v| v.0).fold(f64::NEG_INFINITY, f64::max);
+        let top = p.iter().map(|v| v.1).fold(f64::INFINITY, f64::min);
+        let bottom = p.iter().map(|v| v.1).fold(f64::NEG_INFINITY, f64::max);
+
+        let x0 = (left.floor().max(0.) as u32).max(min_x);
+        let x1 = (right.ceil().max(0.) as u32).min(max_x);
+        let y0 = (top.floor().max(0.) as u32).max(min_y);
+        let y1 = (bottom.ceil().max(0.) as u32).min(max_y);
+
+        let blend = draw_state.blend.is_some();
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let c = (f64::from(x) + 0.5, f64::from(y) + 0.5);
+                let weights = [
+                    edge(p[1], p[2], c),
+                    edge(p[2], p[0], c),
+                    edge(p[0], p[1], c),
+                ];
+
+                let inside = weights
+                    .iter()
+                    .zip(&[(p[1], p[2]), (p[2], p[0]), (p[0], p[1])])
+                    .all(|(w, (a, b))| *w > 0. || (*w == 0. && is_top_left(*a, *b)));
+                if !inside {
+                    continue;
+                }
+
+                // Weights back in the order the vertices were given
+                let mut bary = [0.; 3];
+                for (i, vertex) in order.iter().enumerate() {
+                    bary[*vertex] = weights[i] / area;
+                }
+
+                let src = shade(bary);
+                let pixel = self.image.get_pixel_mut(x, y);
+                *pixel = if blend {
+                    to_rgba(alpha_blend(src, to_color(*pixel)))
+                } else {
+                    to_rgba(src)
+                };
+            }
+        }
+    }
+}
+
+impl Graphics for Canvas {
+    type Texture = CpuTexture;
+
+    fn clear_color(&mut self, color: Color) {
+        let rgba = to_rgba(color);
+        for pixel in self.image.pixels_mut() {
+            *pixel = rgba;
+        }
+    }
+
+    fn clear_stencil(&mut self, _value: u8) {}
+
+    fn tri_list<F>(&mut self, draw_state: &DrawState, color: &[f32; 4], mut f: F)
+    where
+        F: FnMut(&mut dyn FnMut(&[[f32; 2]])),
+    {
+        let color = *color;
+        f(&mut |verts: &[[f32; 2]]| {
+            for tri in verts.chunks(3).filter(|tri| tri.len() == 3) {
+                self.fill_triangle(draw_state, [tri[0], tri[1], tri[2]], |_| color);
+            }
+        });
+    }
+
+    fn tri_list_uv<F>(
+        &mut self,
+        draw_state: &DrawState,
+        color: &[f32; 4],
+        texture: &CpuTexture,
+        mut f: F,
+    ) where
+        F: FnMut(&mut dyn FnMut(&[[f32; 2]], &[[f32; 2]])),
+    {
+        let color = *color;
+        f(&mut |verts: &[[f32; 2]], uvs: &[[f32; 2]]| {
+            for (tri, uv) in verts.chunks(3).zip(uvs.chunks(3)) {
+                if tri.len() < 3 || uv.len() < 3 {
+                    continue;
+                }
+
+                self.fill_triangle(draw_state, [tri[0], tri[1], tri[2]], |bary| {
+                    let lerp = |axis: usize| {
+                        (0..3)
+                            .map(|i| bary[i] * f64::from(uv[i][axis]))
+                            .sum::<f64>()
+                    };
+                    let texel = texture.sample(lerp(0), lerp(1));
+                    [
+                        texel[0] * color[0],
+                        texel[1] * color[1],
+                        texel[2] * color[2],
+                        texel[3] * color[3],
+                    ]
+                });
+            }
+        });
+    }
+}
+
+impl RenderTarget for Canvas {
+    type Graphics = Canvas;
+
+    fn draw_frame<F>(&mut self, viewport: Viewport, f: F)
+    where
+        F: FnOnce(Context, &mut Canvas),
+    {
+        f(Context::new_viewport(viewport), self);
+    }
+}
+
+/// Which side of the edge from `a` to `b` the point lies on, scaled by
+/// twice the area of the triangle they form.
+#[inline]
+fn edge(a: (f64, f64), b: (f64, f64), p: (f64, f64)) -> f64 {
+    (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)
+}
+
+/// Edges that own the pixels lying exactly on them
+///
+/// Two triangles sharing an edge walk it in opposite directions, so exactly
+/// one of them owns it.
+#[inline]
+fn is_top_left(a: (f64, f64), b: (f64, f64)) -> bool {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    dy < 0. || (dy == 0. && dx > 0.)
+}
+
+fn alpha_blend(src: [f32; 4], dst: [f32; 4]) -> [f32; 4] {
+    let a = src[3];
+    let out_a = a + dst[3] * (1. - a);
+    let mix = |s: f32, d: f32| s * a + d * (1. - a);
+
+    [
+        mix(src[0], dst[0]),
+        mix(src[1], dst[1]),
+        mix(src[2], dst[2]),
+        out_a,
+    ]
+}
+
+fn to_color(pixel: Rgba<u8>) -> [f32; 4] {
+    let c = |v: u8| f32::from(v) / 255.;
+    [c(pixel[0]), c(pixel[1]), c(pixel[2]), c(pixel[3])]
+}
+
+fn to_rgba(color: [f32; 4]) -> Rgba<u8> {
+    let c = |v: f32| (v.clamp(0., 1.) * 255.).round() as u8;
+    Rgba([c(color[0]), c(color[1]), c(color[2]), c(color[3])])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use graphics::Transformed;
+
+    #[test]
+    fn test_rectangle_covers_pixels_once() {
+        let mut canvas = Canvas::new(8, 8);
+        let viewport = canvas.viewport();
+        canvas.draw_frame(viewport, |c, g| {
+            graphics::clear([0., 0., 0., 1.], g);
+
+            // Half transparent, so drawing a pixel twice would show
+            graphics::rectangle([1., 1., 1., 0.5], [2., 2., 4., 4.], c.transform, g);
+        });
+
+        let gray = canvas.image().get_pixel(2, 2)[0];
+        assert_eq!(128, gray);
+        for y in 0..8 {
+            for x in 0..8 {
+                let inside = (2..6).contains(&x) && (2..6).contains(&y);
+                let expected = if inside { gray } else { 0 };
+                assert_eq!(
+                    expected,
+                    canvas.image().get_pixel(x, y)[0],
+                    "({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_textured_image() {
+        let mut image = RgbaImage::new(2, 2);
+        image.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        image.put_pixel(1, 0, Rgba([0, 255, 0, 255]));
+        image.put_pixel(0, 1, Rgba([0, 0, 255, 255]));
+        image.put_pixel(1, 1, Rgba([0, 0, 0, 0]));
+        let texture = CpuTexture::from_image(image);
+
+        let mut canvas = Canvas::new(4, 4);
+        let viewport = canvas.viewport();
+        canvas.draw_frame(viewport, |c, g| {
+            graphics::clear([1., 1., 1., 1.], g);
+            graphics::image(&texture, c.transform.scale(2., 2.), g);
+        });
+
+        let img = canvas.image();
+        assert_eq!(Rgba([255, 0, 0, 255]), *img.get_pixel(0, 0));
+        assert_eq!(Rgba([0, 255, 0, 255]), *img.get_pixel(3, 1));
+        assert_eq!(Rgba([0, 0, 255, 255]), *img.get_pixel(1, 2));
+
+        // Transparent texels leave the background be
+        assert_eq!(Rgba([255, 255, 255, 255]), *img.get_pixel(3, 3));
+    }
+}
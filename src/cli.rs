@@ -0,0 +1,117 @@
+//! Command line arguments of the cave binary
+
+use std::path::PathBuf;
+
+/// Ticks simulated in headless mode when not given
+const DEFAULT_TICKS: u64 = 600;
+
+pub const USAGE: &str = "Usage: cave [--record PATH] [--headless [--ticks N] [--screenshot PATH]]\n       cave --replay PATH [--expect-hash HASH] [--screenshot PATH]";
+
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    /// Run the simulation without opening a window
+    pub headless: bool,
+
+    /// Number of fixed updates to run in headless mode
+    pub ticks: u64,
+
+    /// Where to save a PNG of the final frame in headless mode
+    pub screenshot: Option<PathBuf>,
+
+    /// Where to record the player's commands
+    pub record: Option<PathBuf>,
+
+    /// Recording to replay headless, instead of running a new session
+    pub replay: Option<PathBuf>,
+
+    /// State hash the headless run must end with
+    pub expect_hash: Option<u64>,
+}
+
+impl Args {
+    /// Parses the arguments, excluding the program name.
+    pub fn parse<I>(args: I) -> Result<Args, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut result = Args::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => result.headless = true,
+                "--ticks" => {
+                    let value = args.next().ok_or("--ticks needs a value")?;
+                    result.ticks = value
+                        .parse()
+                        .map_err(|_| format!("Invalid tick count: {}", value))?;
+                }
+                "--record" => {
+                    let value = args.next().ok_or("--record needs a path")?;
+                    result.record = Some(PathBuf::from(value));
+                }
+                "--replay" => {
+                    let value = args.next().ok_or("--replay needs a path")?;
+                    result.replay = Some(PathBuf::from(value));
+                }
+                "--expect-hash" => {
+                    let value = args.next().ok_or("--expect-hash needs a value")?;
+                    let hash = u64::from_str_radix(value.trim_start_matches("0x"), 16)
+                        .map_err(|_| format!("Invalid hash: {}", value))?;
+                    result.expect_hash = Some(hash);
+                }
+                "--screenshot" => {
+                    let value = args.next().ok_or("--screenshot needs a path")?;
+                    result.screenshot = Some(PathBuf::from(value));
+                }
+                _ => return Err(format!("Unknown argument: {}", arg)),
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Args {
+            headless: false,
+            ticks: DEFAULT_TICKS,
+            screenshot: None,
+            record: None,
+            replay: None,
+            expect_hash: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Ok(Args::default()), parse(&[]));
+        assert_eq!(
+            Ok(Args {
+                headless: true,
+                ticks: 42,
+                screenshot: Some(PathBuf::from("out.png")),
+                ..Args::default()
+            }),
+            parse(&["--headless", "--ticks", "42", "--screenshot", "out.png"])
+        );
+        assert!(parse(&["--ticks"]).is_err());
+        assert!(parse(&["--ticks", "many"]).is_err());
+        assert!(parse(&["--fast"]).is_err());
+
+        let args = parse(&["--replay", "bug.log", "--expect-hash", "0x00ff"]).unwrap();
+        assert_eq!(Some(PathBuf::from("bug.log")), args.replay);
+        assert_eq!(Some(255), args.expect_hash);
+        assert!(parse(&["--expect-hash", "xyz"]).is_err());
+    }
+}
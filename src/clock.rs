@@ -0,0 +1,156 @@
+//! Fixed Timestep Simulation
+//!
+//! The simulation always advances by the same step, however long frames
+//! take to draw, so the same inputs give the same results on every machine.
+//! Frames are drawn between the last two ticks, with moving entities placed
+//! by how far the clock has run into the next one.
+
+use specs::prelude::*;
+
+use crate::position::Position;
+
+/// Longest frame the clock catches up on, so a stall doesn't snowball
+/// into ever more ticks per frame
+const MAX_FRAME_TIME: f64 = 0.25;
+
+/// Accumulates real time, and hands it out as whole simulation ticks
+pub struct FixedTimestep {
+    dt: f64,
+    accumulator: f64,
+    tick: u64,
+}
+
+impl FixedTimestep {
+    pub fn new(dt: f64) -> Self {
+        FixedTimestep {
+            dt,
+            accumulator: 0.,
+            tick: 0,
+        }
+    }
+
+    /// Ticks simulated so far
+    #[inline(always)]
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Adds the real time that passed, and returns how many ticks to
+    /// simulate to catch up.
+    pub fn advance(&mut self, elapsed: f64) -> u32 {
+        self.accumulator += elapsed.clamp(0., MAX_FRAME_TIME);
+
+        let mut steps = 0;
+        while self.accumulator >= self.dt {
+            self.accumulator -= self.dt;
+            self.tick += 1;
+            steps += 1;
+        }
+
+        steps
+    }
+
+    /// How far, from 0 to 1, the clock is between the last tick and the next
+    #[inline]
+    pub fn alpha(&self) -> f64 {
+        self.accumulator / self.dt
+    }
+}
+
+/// Position of a moving entity at the previous tick
+#[derive(Component, Debug)]
+#[storage(DenseVecStorage)]
+pub struct PreviousPosition(pub Position);
+
+/// Resource for the renderer, holding how far between ticks a frame is
+#[derive(Default)]
+pub struct Interpolation(pub f64);
+
+impl Interpolation {
+    /// Where an entity is drawn, between its last and current position
+    pub fn lerp(&self, previous: &Position, current: &Position) -> Position {
+        let v = previous.to_vector().lerp(current.to_vector(), self.0);
+        Position::new(v.x, v.y, v.z)
+    }
+}
+
+/// Remembers where entities were before the tick moves them
+///
+/// Must run before any system that moves entities.
+pub struct SnapshotSystem;
+
+impl SnapshotSystem {
+    pub fn new() -> Self {
+        SnapshotSystem
+    }
+}
+
+impl<'a> System<'a> for SnapshotSystem {
+    type SystemData = (
+        ReadStorage<'a, Position>,
+        WriteStorage<'a, PreviousPosition>,
+    );
+
+    fn run(&mut self, (positions, mut previous): Self::SystemData) {
+        use specs::Join;
+
+        for (pos, previous) in (&positions, &mut previous).join() {
+            previous.0 = pos.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::actor::Actor;
+    use crate::canvas::CpuTexture;
+
+    /// Actor positions after simulating the game's scene
+    fn simulate(ticks: u32) -> Vec<Position> {
+        let mut world = World::new();
+        crate::setup_world::<CpuTexture>(&mut world);
+        crate::build_scene::<CpuTexture>(&mut world, None);
+
+        let mut dispatcher = crate::build_update_dispatcher::<CpuTexture>();
+        for _ in 0..ticks {
+            dispatcher.dispatch(&world.res);
+            world.maintain();
+        }
+
+        let actors = world.read_storage::<Actor>();
+        let positions = world.read_storage::<Position>();
+        (&actors, &positions)
+            .join()
+            .map(|(_, pos)| pos.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_accumulator() {
+        let mut clock = FixedTimestep::new(0.0625);
+
+        assert_eq!(0, clock.advance(0.03125));
+        assert_eq!(0.5, clock.alpha());
+
+        assert_eq!(2, clock.advance(0.09375));
+        assert_eq!(0., clock.alpha());
+        assert_eq!(2, clock.tick());
+
+        // Long stalls are only caught up on in part
+        assert_eq!(4, clock.advance(10.));
+        assert_eq!(6, clock.tick());
+    }
+
+    #[test]
+    fn test_simulation_is_deterministic() {
+        let first = simulate(120);
+        let second = simulate(120);
+
+        assert!(!first.is_empty());
+        assert_eq!(
+            first.iter().map(|p| *p.to_vector()).collect::<Vec<_>>(),
+            second.iter().map(|p| *p.to_vector()).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,287 @@
+//! Player Commands
+//!
+//! Everything the player does to the simulation goes through a `Command`,
+//! applied at the start of a tick. That keeps input out of the event loop,
+//! and lets a session be recorded and replayed tick for tick.
+
+use std::fmt;
+use std::str::FromStr;
+
+use specs::prelude::*;
+
+use crate::actor::Actor;
+use crate::grid::GridPosition;
+use crate::isometric::Isometric;
+use crate::pathfinding::components::Pather;
+use crate::position::Position;
+use crate::view::components::IsometricCamera;
+use crate::view::{CutMode, ViewCutMode};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Moves the current camera by whole cells
+    MoveCamera(i32, i32, i32),
+    SetCutMode(CutMode),
+
+    /// Turns the view by quarter turns
+    RotateView(i32),
+
+    /// Zooms the current camera in by steps, or out for negative steps
+    ZoomCamera(i32),
+
+    /// Sends the actors, by entity id, to the cell
+    MoveActors(Vec<u32>, GridPosition),
+
+    /// Has the actors head for the cell once they reach their goal
+    QueueWaypoint(Vec<u32>, GridPosition),
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::MoveCamera(x, y, z) => write!(f, "camera {} {} {}", x, y, z),
+            Command::SetCutMode(mode) => match mode {
+                CutMode::Top => write!(f, "cut top"),
+                CutMode::Left => write!(f, "cut left"),
+                CutMode::Right => write!(f, "cut right"),
+                CutMode::Bottom => write!(f, "cut bottom"),
+                CutMode::Slab(layers) => write!(f, "cut slab {}", layers),
+                CutMode::Plane { normal, offset } => write!(
+                    f,
+                    "cut plane {} {} {} {}",
+                    normal.x, normal.y, normal.z, offset
+                ),
+                CutMode::CrossSection => write!(f, "cut section"),
+            },
+            Command::RotateView(turns) => write!(f, "rotate {}", turns),
+            Command::ZoomCamera(steps) => write!(f, "zoom {}", steps),
+            Command::MoveActors(ids, cell) => write!(f, "move {} {}", Ids(ids), Cell(cell)),
+            Command::QueueWaypoint(ids, cell) => {
+                write!(f, "waypoint {} {}", Ids(ids), Cell(cell))
+            }
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words = s.split_whitespace().collect::<Vec<_>>();
+        let number = |word: &str| {
+            word.parse::<i32>()
+                .map_err(|_| format!("Invalid number in command: {}", s))
+        };
+        let real = |word: &str| {
+            word.parse::<f64>()
+                .map_err(|_| format!("Invalid number in command: {}", s))
+        };
+        let ids = |word: &str| {
+            word.split(',')
+                .map(|id| id.parse::<u32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| format!("Invalid actor ids in command: {}", s))
+        };
+        let cell = |x, y, z| -> Result<_, String> {
+            Ok(GridPosition::new(number(x)?, number(y)?, number(z)?))
+        };
+
+        match words.as_slice() {
+            ["camera", x, y, z] => Ok(Command::MoveCamera(number(x)?, number(y)?, number(z)?)),
+            ["cut", "top"] => Ok(Command::SetCutMode(CutMode::Top)),
+            ["cut", "left"] => Ok(Command::SetCutMode(CutMode::Left)),
+            ["cut", "right"] => Ok(Command::SetCutMode(CutMode::Right)),
+            ["cut", "bottom"] => Ok(Command::SetCutMode(CutMode::Bottom)),
+            ["cut", "slab", layers] => {
+                let layers = layers
+                    .parse::<u32>()
+                    .map_err(|_| format!("Invalid number of layers in command: {}", s))?;
+                Ok(Command::SetCutMode(CutMode::Slab(layers)))
+            }
+            ["cut", "plane", x, y, z, offset] => {
+                let mode = CutMode::Plane {
+                    normal: na::Vector3::new(real(x)?, real(y)?, real(z)?),
+                    offset: real(offset)?,
+                };
+                if !mode.is_valid() {
+                    return Err(format!("Cut plane needs a normal: {}", s));
+                }
+                Ok(Command::SetCutMode(mode))
+            }
+            ["cut", "section"] => Ok(Command::SetCutMode(CutMode::CrossSection)),
+            ["rotate", turns] => Ok(Command::RotateView(number(turns)?)),
+            ["zoom", steps] => Ok(Command::ZoomCamera(number(steps)?)),
+            ["move", actors, x, y, z] => Ok(Command::MoveActors(ids(actors)?, cell(x, y, z)?)),
+            ["waypoint", actors, x, y, z] => {
+                Ok(Command::QueueWaypoint(ids(actors)?, cell(x, y, z)?))
+            }
+            _ => Err(format!("Unknown command: {}", s)),
+        }
+    }
+}
+
+/// Writes ids as a comma separated list, without spaces
+struct Ids<'a>(&'a [u32]);
+
+impl<'a> fmt::Display for Ids<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, id) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", id)?;
+        }
+        Ok(())
+    }
+}
+
+struct Cell<'a>(&'a GridPosition);
+
+impl<'a> fmt::Display for Cell<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.0.x(), self.0.y(), self.0.z())
+    }
+}
+
+/// Commands waiting for the next tick, and those applied on the last one
+#[derive(Default)]
+pub struct CommandQueue {
+    pending: Vec<Command>,
+    applied: Vec<Command>,
+}
+
+impl CommandQueue {
+    pub fn push(&mut self, command: Command) {
+        self.pending.push(command);
+    }
+
+    /// Commands applied on the last tick, for recording
+    pub fn applied(&self) -> &[Command] {
+        &self.applied
+    }
+}
+
+/// Applies queued commands, in the order they were issued
+pub struct CommandSystem;
+
+impl CommandSystem {
+    pub fn new() -> Self {
+        CommandSystem
+    }
+}
+
+impl<'a> System<'a> for CommandSystem {
+    type SystemData = (
+        Entities<'a>,
+        Write<'a, CommandQueue>,
+        Write<'a, Isometric>,
+        Write<'a, ViewCutMode>,
+        ReadStorage<'a, Actor>,
+        WriteStorage<'a, IsometricCamera>,
+        WriteStorage<'a, Pather>,
+        WriteStorage<'a, Position>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            mut queue,
+            mut projection,
+            mut view_cut,
+            actors,
+            mut cameras,
+            mut pathers,
+            mut positions,
+        ): Self::SystemData,
+    ) {
+        use specs::Join;
+
+        let CommandQueue { pending, applied } = &mut *queue;
+        applied.clear();
+
+        for command in pending.drain(..) {
+            match &command {
+                Command::MoveCamera(x, y, z) => {
+                    let offset = Position::new(f64::from(*x), f64::from(*y), f64::from(*z));
+                    let maybe_camera = (&cameras, &mut positions)
+                        .join()
+                        .find(|(camera, _)| camera.is_current());
+                    if let Some((_camera, pos)) = maybe_camera {
+                        *pos = &*pos + &offset;
+                    }
+                }
+                Command::SetCutMode(mode) => view_cut.set_mode(mode.clone()),
+                Command::RotateView(turns) => {
+                    let rotation = projection.rotation().turn(*turns);
+                    projection.set_rotation(rotation);
+                    view_cut.set_rotation(rotation);
+                }
+                Command::ZoomCamera(steps) => {
+                    let maybe_camera = (&mut cameras).join().find(|camera| camera.is_current());
+                    if let Some(camera) = maybe_camera {
+                        camera.zoom_by(*steps);
+                    }
+                }
+                Command::MoveActors(ids, cell) | Command::QueueWaypoint(ids, cell) => {
+                    for id in ids {
+                        let e = entities.entity(*id);
+                        if !entities.is_alive(e) || !actors.contains(e) {
+                            continue;
+                        }
+
+                        if let (Some(pather), Some(pos)) = (pathers.get_mut(e), positions.get(e)) {
+                            let start = pos.to_grid();
+                            match command {
+                                Command::MoveActors(_, _) => pather.order(start, cell.clone()),
+                                _ => pather.queue_waypoint(start, cell.clone()),
+                            }
+                        }
+                    }
+                }
+            }
+
+            applied.push(command);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        let commands = [
+            Command::MoveCamera(-1, 1, 0),
+            Command::SetCutMode(CutMode::Top),
+            Command::SetCutMode(CutMode::Left),
+            Command::SetCutMode(CutMode::Right),
+            Command::SetCutMode(CutMode::Bottom),
+            Command::SetCutMode(CutMode::Slab(3)),
+            Command::SetCutMode(CutMode::Plane {
+                normal: na::Vector3::new(1., -0.5, 0.),
+                offset: 2.25,
+            }),
+            Command::SetCutMode(CutMode::CrossSection),
+            Command::RotateView(-1),
+            Command::ZoomCamera(2),
+            Command::MoveActors(vec![4], GridPosition::new(9, 9, 5)),
+            Command::QueueWaypoint(vec![4, 12, 7], GridPosition::new(0, -1, 2)),
+        ];
+
+        for command in commands.iter() {
+            assert_eq!(Ok(command.clone()), command.to_string().parse());
+        }
+
+        assert!("camera 1 2".parse::<Command>().is_err());
+        assert!("camera 1 2 x".parse::<Command>().is_err());
+        assert!("jump".parse::<Command>().is_err());
+        assert!("cut slab -1".parse::<Command>().is_err());
+        assert!("cut plane 0 0 0 1".parse::<Command>().is_err());
+        assert!("cut plane NaN 1 0 1".parse::<Command>().is_err());
+        assert!("cut plane 1 0 0 inf".parse::<Command>().is_err());
+        assert!("move 4, 9 9 5".parse::<Command>().is_err());
+        assert!("move x 9 9 5".parse::<Command>().is_err());
+    }
+}
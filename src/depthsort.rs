@@ -1,59 +1,74 @@
-use opengl_graphics::Texture;
+use std::marker::PhantomData;
+
+use graphics::ImageSize;
 use specs::prelude::*;
 
 use crate::isometric::Isometric;
 use crate::pigeon::PigeonholeSort;
 use crate::position::Position;
-use crate::settings::flatten_pos;
 use crate::sprite::Sprite;
 use crate::view::components::IsometricCamera;
-use crate::view::ViewCutMode;
+use crate::view::{overlaps, ViewCutMode, Viewport};
 
 /// Sorts objects according to the isometric projection
-pub struct IsometricSorter {
+///
+/// Generic over the texture type of the sprites, which depends on the
+/// graphics backend.
+pub struct IsometricSorter<I> {
     sort: PigeonholeSort<DepthItem>,
+    texture: PhantomData<I>,
 }
 
-impl IsometricSorter {
+impl<I> IsometricSorter<I> {
     pub fn with_size(x: u32, y: u32, z: u32) -> Self {
+        // Turning the view negates the x and y axes, so depths can go as
+        // far below zero as they go above
         IsometricSorter {
-            sort: PigeonholeSort::new(0, (x + y + z) as i32),
+            sort: PigeonholeSort::new(-((x + y) as i32), (x + y + z) as i32),
+            texture: PhantomData,
         }
     }
 }
 
-impl<'a> System<'a> for IsometricSorter {
+impl<'a, I> System<'a> for IsometricSorter<I>
+where
+    I: ImageSize + Send + Sync + 'static,
+{
     type SystemData = (
         Entities<'a>,
+        Read<'a, Isometric>,
         Read<'a, ViewCutMode>,
+        Read<'a, Viewport>,
         Write<'a, DepthBuffer>,
         ReadStorage<'a, Position>,
         ReadStorage<'a, IsometricCamera>,
-        WriteStorage<'a, Sprite<Texture>>,
+        WriteStorage<'a, Sprite<I>>,
     );
 
     fn run(
         &mut self,
-        (entities, view_cut, mut buffer, positions, cameras, mut sprites): Self::SystemData,
+        (
+            entities,
+            projection,
+            view_cut,
+            viewport,
+            mut buffer,
+            positions,
+            cameras,
+            mut sprites,
+        ): Self::SystemData,
     ) {
         use specs::Join;
 
-        let IsometricSorter { sort } = self;
+        let IsometricSorter { sort, .. } = self;
         let maybe_camera = (&cameras, &positions)
             .join()
             .find(|(camera, _)| camera.is_current());
 
-        if let Some((_camera, camera_pos)) = maybe_camera {
-            const VIEWPORT_WIDTH: f64 = 1600.;
-            const VIEWPORT_HEIGHT: f64 = 900.;
-            let camera_pos_iso = Isometric::cart_to_iso(&camera_pos.to_vector());
-            let camera_pos_2d = flatten_pos(&camera_pos_iso);
-            let tile_rect_2d = (
-                camera_pos_2d.x - (VIEWPORT_WIDTH / 2.),
-                camera_pos_2d.y - (VIEWPORT_HEIGHT / 2.),
-                VIEWPORT_WIDTH,
-                VIEWPORT_HEIGHT,
-            );
+        if let Some((camera, camera_pos)) = maybe_camera {
+            // Zooming out shows more of the world in the same window
+            let camera_pos_2d = projection.to_screen(camera_pos.to_vector());
+            let visible = viewport.visible_area(&camera_pos_2d, camera.zoom());
 
             let mut unsorted: Vec<DepthItem> = vec![];
 
@@ -62,27 +77,21 @@ impl<'a> System<'a> for IsometricSorter {
                     continue;
                 }
 
-                // Determine if the sprite is visible in 2D screen space
-                let tile_pos_iso = Isometric::cart_to_iso(&position.to_vector());
-                let tile_pos_2d = flatten_pos(&tile_pos_iso);
-
-                if !(tile_pos_2d.x >= tile_rect_2d.0
-                    && tile_pos_2d.x <= tile_rect_2d.0 + tile_rect_2d.2
-                    && tile_pos_2d.y >= tile_rect_2d.1
-                    && tile_pos_2d.y <= tile_rect_2d.1 + tile_rect_2d.3)
-                {
-                    // Not in viewport
+                // Determine if any of the sprite is visible in 2D screen space
+                let tile_pos_2d = projection.to_screen(position.to_vector());
+                if !overlaps(&visible, &sprite.bounds_at(&tile_pos_2d)) {
                     continue;
                 }
 
-                // Calculate depth
+                // Calculate depth, towards the viewer whichever way the view is turned
                 // TODO: Once we've defined anchor points and tile subdivions, this should be more intricate
-                let depth =
-                    (position.x().floor() + position.y().floor() + position.z().floor()) as i32;
+                let view_pos = projection.world_to_view(position.to_vector());
+                let depth = (view_pos.x.floor() + view_pos.y.floor() + view_pos.z.floor()) as i32;
                 sprite.set_depth(depth);
                 unsorted.push(DepthItem {
                     depth,
                     entity_id: e.id(),
+                    cut: view_cut.is_on_cut(camera_pos, position),
                 });
             }
 
@@ -101,12 +110,23 @@ impl<'a> System<'a> for IsometricSorter {
 pub struct DepthItem {
     entity_id: u32,
     depth: i32,
+
+    /// Whether the sprite lies on the cut of a cross section
+    cut: bool,
 }
 
 impl DepthItem {
     pub fn entity_id(&self) -> u32 {
         self.entity_id
     }
+
+    pub fn depth(&self) -> i32 {
+        self.depth
+    }
+
+    pub fn is_cut(&self) -> bool {
+        self.cut
+    }
 }
 
 #[derive(Default)]
@@ -121,3 +141,61 @@ impl DepthBuffer {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::canvas::CpuTexture;
+    use image::RgbaImage;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_culls_by_sprite_bounds() {
+        let mut world = World::new();
+        world.add_resource(Isometric::default());
+        world.add_resource(ViewCutMode::default());
+        world.add_resource(Viewport::new(320., 240.));
+        world.add_resource(DepthBuffer::new());
+        world.register::<IsometricCamera>();
+        world.register::<Position>();
+        world.register::<Sprite<CpuTexture>>();
+
+        world
+            .create_entity()
+            .with(IsometricCamera::new(true))
+            .with(Position::new(0., 0., 0.))
+            .build();
+
+        // Anchored at their feet, so a tall sprite reaches far up the screen
+        let tex = Arc::new(CpuTexture::from_image(RgbaImage::new(40, 400)));
+        let mut tall = |x, y| {
+            let mut sprite = Sprite::from_texture(tex.clone());
+            sprite.set_anchor(0.5, 1.);
+            world
+                .create_entity()
+                .with(Position::new(x, y, 0.))
+                .with(sprite)
+                .build()
+        };
+
+        // Its feet are below the window, but its head is in it
+        let below = tall(4., 4.);
+        let beside = tall(10., -10.);
+        let sorted = |world: &World| {
+            IsometricSorter::<CpuTexture>::with_size(16, 16, 16).run_now(&world.res);
+            world
+                .read_resource::<DepthBuffer>()
+                .contents()
+                .iter()
+                .map(|item| item.entity_id())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![below.id()], sorted(&world));
+
+        // A wider window brings the other one in
+        world.write_resource::<Viewport>().resize(2000., 240.);
+        let mut ids = sorted(&world);
+        ids.sort();
+        assert_eq!(vec![below.id(), beside.id()], ids);
+    }
+}
@@ -0,0 +1,419 @@
+//! Golden-image tests for the isometric renderer
+//!
+//! Small scenes are drawn with the software rasteriser, and compared against
+//! reference PNGs checked in under `tests/golden`. After an intended change
+//! to rendering, run the tests with `UPDATE_GOLDEN=1` to write new references.
+//!
+//! When a render doesn't match, it is written to `target/golden` together
+//! with a diff image, which shows the differing pixels in red over a faded
+//! copy of the reference.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use graphics::types::Color;
+use image::{Rgba, RgbaImage};
+use specs::prelude::*;
+
+use crate::actor::Actor;
+use crate::canvas::{Canvas, CpuTexture};
+use crate::clock::{Interpolation, PreviousPosition};
+use crate::depthsort::{DepthBuffer, IsometricSorter};
+use crate::grid::GridPosition;
+use crate::isometric::Isometric;
+use crate::position::Position;
+use crate::selection::Selected;
+use crate::sprite::{OnRender, Sprite, SpriteRenderer};
+use crate::view::{components::IsometricCamera, CutMode, ViewCutMode, Viewport};
+use crate::xray::{Ghosted, Important, XRayStyle, XRaySystem};
+use crate::{ACTOR_LOD_COLOR, BLOCK_LOD_COLOR, LADDER_LOD_COLOR};
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+
+/// Size of the depth sorting range, which must fit every scene
+const SCENE_SIZE: u32 = 16;
+
+/// Largest difference in a colour channel for pixels to still match
+const CHANNEL_TOLERANCE: u8 = 8;
+
+/// Share of pixels that may differ before the images count as different
+const PIXEL_TOLERANCE: f64 = 0.001;
+
+/// Builds a scene out of sprites, the way the game lays them out
+struct Scene {
+    world: World,
+    block: Arc<CpuTexture>,
+    ladder: Arc<CpuTexture>,
+    man: Arc<CpuTexture>,
+}
+
+impl Scene {
+    /// Empty scene looking at the given position
+    fn looking_at(camera: Position) -> Self {
+        let mut world = World::new();
+        world.add_resource(DepthBuffer::new());
+        world.add_resource(ViewCutMode::default());
+        world.add_resource(Interpolation::default());
+        world.add_resource(Isometric::default());
+        world.add_resource(XRayStyle::default());
+        world.register::<Actor>();
+        world.register::<Ghosted>();
+        world.register::<Important>();
+        world.register::<Selected>();
+        world.register::<IsometricCamera>();
+        world.register::<Position>();
+        world.register::<PreviousPosition>();
+        world.register::<Sprite<CpuTexture>>();
+
+        world
+            .create_entity()
+            .with(IsometricCamera::new(true))
+            .with(camera)
+            .build();
+
+        let load = |name: &str| {
+            let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("resources")
+                .join(name);
+            Arc::new(CpuTexture::from_path(path).unwrap())
+        };
+
+        Scene {
+            world,
+            block: load("greybox.png"),
+            ladder: load("ladder.png"),
+            man: load("blueman.png"),
+        }
+    }
+
+    fn with_cut_mode(self, mode: CutMode) -> Self {
+        self.world.write_resource::<ViewCutMode>().set_mode(mode);
+        self
+    }
+
+    fn with_zoom(self, zoom: f64) -> Self {
+        for camera in (&mut self.world.write_storage::<IsometricCamera>()).join() {
+            camera.set_zoom(zoom);
+        }
+        self
+    }
+
+    fn block(&mut self, x: i32, y: i32, z: i32) -> &mut Self {
+        let texture = self.block.clone();
+        self.tile(texture, BLOCK_LOD_COLOR, &GridPosition::new(x, y, z))
+    }
+
+    fn ladder(&mut self, x: i32, y: i32, z: i32) -> &mut Self {
+        let texture = self.ladder.clone();
+        self.tile(texture, LADDER_LOD_COLOR, &GridPosition::new(x, y, z))
+    }
+
+    fn actor(&mut self, x: i32, y: i32, z: i32) -> &mut Self {
+        self.create_actor(x, y, z).build();
+        self
+    }
+
+    /// Actor kept in sight through whatever is in front of it
+    fn important_actor(&mut self, x: i32, y: i32, z: i32) -> &mut Self {
+        self.create_actor(x, y, z)
+            .with(Actor::new())
+            .with(Important)
+            .build();
+        self
+    }
+
+    /// Actor the player has selected, drawn highlighted
+    fn selected_actor(&mut self, x: i32, y: i32, z: i32) -> &mut Self {
+        self.create_actor(x, y, z)
+            .with(Actor::new())
+            .with(Selected)
+            .build();
+        self
+    }
+
+    fn create_actor(&mut self, x: i32, y: i32, z: i32) -> EntityBuilder<'_> {
+        let mut sprite = Sprite::from_texture(self.man.clone());
+        sprite.set_anchor(0.5, 0.9);
+        sprite.set_lod_color(ACTOR_LOD_COLOR);
+
+        self.world
+            .create_entity()
+            .with(GridPosition::new(x, y, z).center())
+            .with(sprite)
+    }
+
+    fn with_xray_style(self, style: XRayStyle) -> Self {
+        *self.world.write_resource::<XRayStyle>() = style;
+        self
+    }
+
+    fn tile(
+        &mut self,
+        texture: Arc<CpuTexture>,
+        lod_color: Color,
+        grid_pos: &GridPosition,
+    ) -> &mut Self {
+        let (anchor_x, anchor_y) = self.world.read_resource::<Isometric>().block_anchor();
+        let mut sprite = Sprite::from_texture(texture);
+        sprite.set_anchor(anchor_x, anchor_y);
+        sprite.set_lod_color(lod_color);
+
+        // Lower blocks are darker
+        let c = 0.8 + (grid_pos.z() as f32 / 50.);
+        sprite.set_color([c, c, c, 1.0]);
+
+        self.world
+            .create_entity()
+            .with(grid_pos.center())
+            .with(sprite)
+            .build();
+        self
+    }
+
+    /// Sorts and draws the scene, as a frame of the game would.
+    fn render(&mut self) -> RgbaImage {
+        let viewport = Viewport::new(f64::from(WIDTH), f64::from(HEIGHT));
+        self.world.add_resource(viewport);
+
+        let mut sorter =
+            IsometricSorter::<CpuTexture>::with_size(SCENE_SIZE, SCENE_SIZE, SCENE_SIZE);
+        sorter.run_now(&self.world.res);
+        XRaySystem::<CpuTexture>::new().run_now(&self.world.res);
+
+        // Debug markers would tie the references to the camera
+        let mut renderer =
+            SpriteRenderer::from_graphics(Canvas::new(WIDTH, HEIGHT)).with_markers(false);
+        self.world.add_resource(OnRender::with_size(WIDTH, HEIGHT));
+        renderer.run_now(&self.world.res);
+
+        renderer.target().image().clone()
+    }
+}
+
+/// Counts the pixels differing beyond the channel tolerance, and draws
+/// them in red over a faded copy of the expected image.
+fn diff_images(expected: &RgbaImage, actual: &RgbaImage) -> (usize, RgbaImage) {
+    let mut diff = RgbaImage::new(expected.width(), expected.height());
+    let mut mismatched = 0;
+
+    for (x, y, pixel) in diff.enumerate_pixels_mut() {
+        let a = expected.get_pixel(x, y);
+        let b = actual.get_pixel(x, y);
+        let matches = (0..4).all(|i| {
+            let delta = (i16::from(a[i]) - i16::from(b[i])).abs();
+            delta <= i16::from(CHANNEL_TOLERANCE)
+        });
+
+        *pixel = if matches {
+            Rgba([a[0] / 4, a[1] / 4, a[2] / 4, 255])
+        } else {
+            mismatched += 1;
+            Rgba([255, 0, 0, 255])
+        };
+    }
+
+    (mismatched, diff)
+}
+
+/// Compares the image against its reference, or writes the reference when
+/// `UPDATE_GOLDEN` is set.
+fn assert_golden(name: &str, actual: &RgbaImage) {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let reference = root
+        .join("tests")
+        .join("golden")
+        .join(format!("{}.png", name));
+
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(reference.parent().unwrap()).unwrap();
+        actual.save(&reference).unwrap();
+        return;
+    }
+
+    let expected = match image::open(&reference) {
+        Ok(image) => image.to_rgba(),
+        Err(err) => panic!(
+            "Failed to load {}: {}, run with UPDATE_GOLDEN=1 to create it",
+            reference.display(),
+            err
+        ),
+    };
+
+    let total = (actual.width() * actual.height()) as usize;
+    let (mismatched, diff) = if expected.dimensions() == actual.dimensions() {
+        diff_images(&expected, actual)
+    } else {
+        (total, actual.clone())
+    };
+
+    if mismatched as f64 > total as f64 * PIXEL_TOLERANCE {
+        let out_dir = root.join("target").join("golden");
+        fs::create_dir_all(&out_dir).unwrap();
+        actual.save(out_dir.join(format!("{}.png", name))).unwrap();
+        diff.save(out_dir.join(format!("{}.diff.png", name)))
+            .unwrap();
+
+        panic!(
+            "{} differs from its reference in {} of {} pixels, see {}",
+            name,
+            mismatched,
+            total,
+            out_dir.display()
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_diff_tolerance() {
+        let expected = RgbaImage::from_pixel(4, 4, Rgba([100, 100, 100, 255]));
+        let mut actual = expected.clone();
+        actual.put_pixel(0, 0, Rgba([100 + CHANNEL_TOLERANCE, 100, 100, 255]));
+        actual.put_pixel(1, 0, Rgba([100, 100, 100 - CHANNEL_TOLERANCE - 1, 255]));
+
+        let (mismatched, diff) = diff_images(&expected, &actual);
+        assert_eq!(1, mismatched);
+        assert_eq!(Rgba([255, 0, 0, 255]), *diff.get_pixel(1, 0));
+        assert_eq!(Rgba([25, 25, 25, 255]), *diff.get_pixel(0, 0));
+    }
+
+    #[test]
+    fn test_stacked_blocks() {
+        let mut scene = Scene::looking_at(Position::new(1., 1., 1.));
+        for x in 0..3 {
+            for y in 0..3 {
+                scene.block(x, y, 0);
+            }
+        }
+        scene.block(1, 1, 1).block(1, 1, 2).block(2, 1, 1);
+
+        assert_golden("stacked_blocks", &scene.render());
+    }
+
+    #[test]
+    fn test_ladders() {
+        let mut scene = Scene::looking_at(Position::new(1., 1., 2.));
+        for x in 0..3 {
+            for y in 0..3 {
+                scene.block(x, y, 0);
+            }
+        }
+        scene.block(0, 1, 1).block(0, 1, 2).block(0, 1, 3);
+        scene.ladder(1, 1, 1).ladder(1, 1, 2).ladder(1, 1, 3);
+
+        assert_golden("ladders", &scene.render());
+    }
+
+    #[test]
+    fn test_actor_behind_wall() {
+        let mut scene = Scene::looking_at(Position::new(1., 1., 1.));
+        for x in 0..4 {
+            for y in 0..4 {
+                scene.block(x, y, 0);
+            }
+        }
+        for y in 0..4 {
+            scene.block(2, y, 1).block(2, y, 2);
+        }
+        scene.actor(1, 1, 1);
+
+        assert_golden("actor_behind_wall", &scene.render());
+    }
+
+    #[test]
+    fn test_selected_actor() {
+        let mut scene = Scene::looking_at(Position::new(1., 1., 1.));
+        for x in 0..3 {
+            for y in 0..3 {
+                scene.block(x, y, 0);
+            }
+        }
+        scene.actor(0, 1, 1).selected_actor(1, 0, 1);
+
+        assert_golden("selected_actor", &scene.render());
+    }
+
+    #[test]
+    fn test_xray() {
+        let styles = [
+            ("xray_ghost", XRayStyle::Ghost),
+            ("xray_outline", XRayStyle::Outline),
+        ];
+
+        for (name, style) in styles.iter() {
+            let mut scene = Scene::looking_at(Position::new(1., 1., 1.)).with_xray_style(*style);
+            for x in 0..4 {
+                for y in 0..4 {
+                    scene.block(x, y, 0);
+                }
+            }
+            for y in 0..4 {
+                scene.block(2, y, 1).block(2, y, 2);
+            }
+            scene.important_actor(1, 1, 1);
+
+            assert_golden(name, &scene.render());
+        }
+    }
+
+    #[test]
+    fn test_cut_modes() {
+        let modes = [
+            ("cut_top", CutMode::Top),
+            ("cut_left", CutMode::Left),
+            ("cut_right", CutMode::Right),
+            ("cut_bottom", CutMode::Bottom),
+            ("cut_slab", CutMode::Slab(1)),
+            ("cut_section", CutMode::CrossSection),
+            (
+                "cut_plane",
+                CutMode::Plane {
+                    normal: na::Vector3::new(-1., 1., 0.),
+                    offset: -1.,
+                },
+            ),
+        ];
+
+        for (name, mode) in modes.iter() {
+            let mut scene =
+                Scene::looking_at(Position::new(1.5, 1.5, 1.5)).with_cut_mode(mode.clone());
+            for x in 0..4 {
+                for y in 0..4 {
+                    for z in 0..4 {
+                        scene.block(x, y, z);
+                    }
+                }
+            }
+
+            assert_golden(name, &scene.render());
+        }
+    }
+
+    #[test]
+    fn test_zoom() {
+        let zooms = [("zoom_in", 2.), ("zoom_out_lod", 0.4)];
+
+        for (name, zoom) in zooms.iter() {
+            let mut scene = Scene::looking_at(Position::new(3., 3., 1.)).with_zoom(*zoom);
+            for x in 0..8 {
+                for y in 0..8 {
+                    scene.block(x, y, 0);
+                }
+            }
+            scene
+                .block(5, 2, 1)
+                .block(5, 2, 2)
+                .ladder(4, 2, 1)
+                .ladder(4, 2, 2);
+            scene.actor(2, 4, 1);
+
+            assert_golden(name, &scene.render());
+        }
+    }
+}
@@ -1,6 +1,10 @@
+use std::ops::{Add, Neg, Sub};
+
 use na::Vector3;
 use specs::prelude::*;
 
+use crate::position::Position;
+
 pub const NEIGHBOUR_COUNT_2D: usize = 8;
 pub const NEIGHBOUR_COUNT_3D: usize = 26;
 
@@ -131,6 +135,32 @@ impl Grid {
             && pos.z() >= 0
             && pos.z() < self.size.z as i32
     }
+
+    /// Region covering the whole grid
+    pub fn aabb(&self) -> GridAabb {
+        GridAabb {
+            min: GridPosition::new(0, 0, 0),
+            max: GridPosition::new(
+                self.size.x as i32 - 1,
+                self.size.y as i32 - 1,
+                self.size.z as i32 - 1,
+            ),
+        }
+    }
+
+    /// Nearest position that's inside the grid
+    pub fn clamp(&self, pos: &GridPosition) -> GridPosition {
+        GridPosition::new(
+            clamp_axis(pos.x(), self.size.x),
+            clamp_axis(pos.y(), self.size.y),
+            clamp_axis(pos.z(), self.size.z),
+        )
+    }
+}
+
+#[inline]
+fn clamp_axis(value: i32, size: u32) -> i32 {
+    ::std::cmp::max(0, ::std::cmp::min(value, size as i32 - 1))
 }
 
 impl Default for Grid {
@@ -172,6 +202,370 @@ impl GridPosition {
         let y = rhs.0.y - self.0.y;
         x != 0 && y != 0
     }
+
+    #[inline]
+    pub fn offset(&self, x: i32, y: i32, z: i32) -> GridPosition {
+        GridPosition::new(self.0.x + x, self.0.y + y, self.0.z + z)
+    }
+
+    /// The adjacent position in the given direction
+    #[inline]
+    pub fn step(&self, dir: Direction) -> GridPosition {
+        let (x, y, z) = dir.offset();
+        self.offset(x, y, z)
+    }
+
+    #[inline]
+    pub fn above(&self) -> GridPosition {
+        self.offset(0, 0, 1)
+    }
+
+    #[inline]
+    pub fn below(&self) -> GridPosition {
+        self.offset(0, 0, -1)
+    }
+
+    /// World position of the cell's anchor point, where entities stand
+    /// when they occupy it.
+    pub fn center(&self) -> Position {
+        Position::new(self.0.x as f64, self.0.y as f64, self.0.z as f64)
+    }
+}
+
+impl Add for GridPosition {
+    type Output = GridPosition;
+    fn add(self, rhs: GridPosition) -> GridPosition {
+        GridPosition(self.0 + rhs.0)
+    }
+}
+
+impl Add<&GridPosition> for &GridPosition {
+    type Output = GridPosition;
+    fn add(self, rhs: &GridPosition) -> GridPosition {
+        GridPosition(self.0 + rhs.0)
+    }
+}
+
+impl Sub for GridPosition {
+    type Output = GridPosition;
+    fn sub(self, rhs: GridPosition) -> GridPosition {
+        GridPosition(self.0 - rhs.0)
+    }
+}
+
+impl Sub<&GridPosition> for &GridPosition {
+    type Output = GridPosition;
+    fn sub(self, rhs: &GridPosition) -> GridPosition {
+        GridPosition(self.0 - rhs.0)
+    }
+}
+
+impl Neg for GridPosition {
+    type Output = GridPosition;
+    fn neg(self) -> GridPosition {
+        GridPosition(-self.0)
+    }
+}
+
+impl Add<Direction> for &GridPosition {
+    type Output = GridPosition;
+    fn add(self, rhs: Direction) -> GridPosition {
+        self.step(rhs)
+    }
+}
+
+/// Compass directions on the x, y plane
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Cardinal {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Cardinal {
+    pub const ALL: [Cardinal; 4] = [
+        Cardinal::North,
+        Cardinal::East,
+        Cardinal::South,
+        Cardinal::West,
+    ];
+
+    /// Next direction, turning clockwise
+    pub fn clockwise(self) -> Cardinal {
+        use Cardinal::*;
+
+        match self {
+            North => East,
+            East => South,
+            South => West,
+            West => North,
+        }
+    }
+
+    pub fn opposite(self) -> Cardinal {
+        self.clockwise().clockwise()
+    }
+}
+
+impl From<Cardinal> for Direction {
+    fn from(cardinal: Cardinal) -> Direction {
+        match cardinal {
+            Cardinal::North => Direction::North,
+            Cardinal::East => Direction::East,
+            Cardinal::South => Direction::South,
+            Cardinal::West => Direction::West,
+        }
+    }
+}
+
+/// Steps to any of the horizontally or vertically adjacent cells
+///
+/// North points towards negative y, and east towards positive x.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Direction {
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+    Up,
+    Down,
+}
+
+impl Direction {
+    /// Directions on the x, y plane, clockwise from north
+    pub const HORIZONTAL: [Direction; 8] = [
+        Direction::North,
+        Direction::NorthEast,
+        Direction::East,
+        Direction::SouthEast,
+        Direction::South,
+        Direction::SouthWest,
+        Direction::West,
+        Direction::NorthWest,
+    ];
+
+    pub const ALL: [Direction; 10] = [
+        Direction::North,
+        Direction::NorthEast,
+        Direction::East,
+        Direction::SouthEast,
+        Direction::South,
+        Direction::SouthWest,
+        Direction::West,
+        Direction::NorthWest,
+        Direction::Up,
+        Direction::Down,
+    ];
+
+    pub fn offset(self) -> (i32, i32, i32) {
+        use Direction::*;
+
+        match self {
+            North => (0, -1, 0),
+            NorthEast => (1, -1, 0),
+            East => (1, 0, 0),
+            SouthEast => (1, 1, 0),
+            South => (0, 1, 0),
+            SouthWest => (-1, 1, 0),
+            West => (-1, 0, 0),
+            NorthWest => (-1, -1, 0),
+            Up => (0, 0, 1),
+            Down => (0, 0, -1),
+        }
+    }
+
+    pub fn opposite(self) -> Direction {
+        use Direction::*;
+
+        match self {
+            North => South,
+            NorthEast => SouthWest,
+            East => West,
+            SouthEast => NorthWest,
+            South => North,
+            SouthWest => NorthEast,
+            West => East,
+            NorthWest => SouthEast,
+            Up => Down,
+            Down => Up,
+        }
+    }
+
+    pub fn is_diagonal(self) -> bool {
+        let (x, y, _) = self.offset();
+        x != 0 && y != 0
+    }
+
+    /// Direction of a single step between two adjacent positions
+    pub fn between(from: &GridPosition, to: &GridPosition) -> Option<Direction> {
+        let diff = to - from;
+        Direction::ALL
+            .iter()
+            .find(|dir| dir.offset() == (diff.x(), diff.y(), diff.z()))
+            .cloned()
+    }
+}
+
+/// Axis aligned box of grid cells, inclusive of both corners
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct GridAabb {
+    min: GridPosition,
+    max: GridPosition,
+}
+
+impl GridAabb {
+    /// Box spanning two corners, given in any order
+    pub fn new(a: &GridPosition, b: &GridPosition) -> Self {
+        GridAabb {
+            min: GridPosition::new(
+                ::std::cmp::min(a.x(), b.x()),
+                ::std::cmp::min(a.y(), b.y()),
+                ::std::cmp::min(a.z(), b.z()),
+            ),
+            max: GridPosition::new(
+                ::std::cmp::max(a.x(), b.x()),
+                ::std::cmp::max(a.y(), b.y()),
+                ::std::cmp::max(a.z(), b.z()),
+            ),
+        }
+    }
+
+    /// Box of the given size, with its minimum corner at the origin
+    pub fn with_size(origin: &GridPosition, x: u32, y: u32, z: u32) -> Self {
+        GridAabb {
+            min: origin.clone(),
+            max: origin.offset(x as i32 - 1, y as i32 - 1, z as i32 - 1),
+        }
+    }
+
+    /// Box reaching the given number of cells out from a center cell
+    pub fn around(center: &GridPosition, radius: u32) -> Self {
+        let r = radius as i32;
+        GridAabb {
+            min: center.offset(-r, -r, -r),
+            max: center.offset(r, r, r),
+        }
+    }
+
+    #[inline(always)]
+    pub fn min(&self) -> &GridPosition {
+        &self.min
+    }
+
+    #[inline(always)]
+    pub fn max(&self) -> &GridPosition {
+        &self.max
+    }
+
+    /// Number of cells along each axis, zero along inverted ones
+    pub fn size(&self) -> (u64, u64, u64) {
+        // Widened, as spans between far apart corners don't fit in 32 bits
+        let span = |min: i32, max: i32| (i64::from(max) - i64::from(min) + 1).max(0) as u64;
+        (
+            span(self.min.x(), self.max.x()),
+            span(self.min.y(), self.max.y()),
+            span(self.min.z(), self.max.z()),
+        )
+    }
+
+    /// Number of cells in the box, saturating at `usize::MAX`
+    pub fn volume(&self) -> usize {
+        let (x, y, z) = self.size();
+        x.saturating_mul(y).saturating_mul(z).min(usize::MAX as u64) as usize
+    }
+
+    /// Indicates whether the box covers no cells, as boxes made with a zero
+    /// size do.
+    pub fn is_empty(&self) -> bool {
+        self.min.x() > self.max.x() || self.min.y() > self.max.y() || self.min.z() > self.max.z()
+    }
+
+    pub fn contains(&self, pos: &GridPosition) -> bool {
+        pos.x() >= self.min.x()
+            && pos.x() <= self.max.x()
+            && pos.y() >= self.min.y()
+            && pos.y() <= self.max.y()
+            && pos.z() >= self.min.z()
+            && pos.z() <= self.max.z()
+    }
+
+    /// Overlapping part of two boxes, `None` if they don't touch.
+    pub fn intersection(&self, other: &GridAabb) -> Option<GridAabb> {
+        let min = GridPosition::new(
+            ::std::cmp::max(self.min.x(), other.min.x()),
+            ::std::cmp::max(self.min.y(), other.min.y()),
+            ::std::cmp::max(self.min.z(), other.min.z()),
+        );
+        let max = GridPosition::new(
+            ::std::cmp::min(self.max.x(), other.max.x()),
+            ::std::cmp::min(self.max.y(), other.max.y()),
+            ::std::cmp::min(self.max.z(), other.max.z()),
+        );
+
+        if min.x() > max.x() || min.y() > max.y() || min.z() > max.z() {
+            None
+        } else {
+            Some(GridAabb { min, max })
+        }
+    }
+
+    /// Part of the box that lies within the grid, `None` if it lies
+    /// completely outside.
+    pub fn clamp(&self, grid: &Grid) -> Option<GridAabb> {
+        self.intersection(&grid.aabb())
+    }
+
+    /// Iterates over every cell in the box, x first, then y, then z.
+    pub fn iter(&self) -> GridAabbIter {
+        GridAabbIter {
+            aabb: self.clone(),
+            next: if self.is_empty() {
+                None
+            } else {
+                Some(self.min.clone())
+            },
+        }
+    }
+}
+
+impl IntoIterator for &GridAabb {
+    type Item = GridPosition;
+    type IntoIter = GridAabbIter;
+
+    fn into_iter(self) -> GridAabbIter {
+        self.iter()
+    }
+}
+
+pub struct GridAabbIter {
+    aabb: GridAabb,
+    next: Option<GridPosition>,
+}
+
+impl Iterator for GridAabbIter {
+    type Item = GridPosition;
+
+    fn next(&mut self) -> Option<GridPosition> {
+        let current = self.next.take()?;
+        let (min, max) = (&self.aabb.min, &self.aabb.max);
+
+        self.next = if current.x() < max.x() {
+            Some(current.offset(1, 0, 0))
+        } else if current.y() < max.y() {
+            Some(GridPosition::new(min.x(), current.y() + 1, current.z()))
+        } else if current.z() < max.z() {
+            Some(GridPosition::new(min.x(), min.y(), current.z() + 1))
+        } else {
+            None
+        };
+
+        Some(current)
+    }
 }
 
 #[cfg(test)]
@@ -185,6 +579,148 @@ mod test {
         assert_eq!(999, grid_index(&size, &GridPosition::new(9, 9, 9)));
     }
 
+    #[test]
+    fn test_ops() {
+        let a = GridPosition::new(1, 2, 3);
+        let b = GridPosition::new(-1, 4, 0);
+
+        assert_eq!(GridPosition::new(0, 6, 3), &a + &b);
+        assert_eq!(GridPosition::new(2, -2, 3), &a - &b);
+        assert_eq!(GridPosition::new(0, 6, 3), a.clone() + b.clone());
+        assert_eq!(GridPosition::new(2, -2, 3), a.clone() - b.clone());
+        assert_eq!(GridPosition::new(-1, -2, -3), -a.clone());
+        assert_eq!(GridPosition::new(3, 1, 3), a.offset(2, -1, 0));
+    }
+
+    #[test]
+    fn test_directions() {
+        let a = GridPosition::new(5, 5, 5);
+
+        assert_eq!(GridPosition::new(5, 5, 6), a.above());
+        assert_eq!(GridPosition::new(5, 5, 4), a.below());
+        assert_eq!(GridPosition::new(5, 4, 5), a.step(Direction::North));
+        assert_eq!(GridPosition::new(6, 6, 5), &a + Direction::SouthEast);
+
+        for dir in Direction::ALL.iter() {
+            let b = a.step(*dir);
+            assert_eq!(Some(*dir), Direction::between(&a, &b));
+            assert_eq!(a, b.step(dir.opposite()));
+            assert_eq!(dir.is_diagonal(), a.is_diagonal_2d(&b));
+        }
+
+        assert_eq!(None, Direction::between(&a, &a.offset(2, 0, 0)));
+        assert_eq!(8, Direction::HORIZONTAL.len());
+        assert!(Direction::HORIZONTAL.iter().all(|dir| dir.offset().2 == 0));
+    }
+
+    #[test]
+    fn test_cardinal() {
+        assert_eq!(Cardinal::East, Cardinal::North.clockwise());
+        assert_eq!(Cardinal::North, Cardinal::West.clockwise());
+        assert_eq!(Cardinal::South, Cardinal::North.opposite());
+        assert_eq!(Direction::West, Cardinal::West.into());
+    }
+
+    #[test]
+    fn test_center() {
+        let center = GridPosition::new(1, 2, 3).center();
+        assert_eq!(1., center.x());
+        assert_eq!(2., center.y());
+        assert_eq!(3., center.z());
+        assert_eq!(GridPosition::new(1, 2, 3), center.to_grid());
+    }
+
+    #[test]
+    fn test_grid_clamp() {
+        let grid = Grid::with_size(4, 4, 2);
+        assert_eq!(
+            GridPosition::new(0, 3, 1),
+            grid.clamp(&GridPosition::new(-2, 7, 1))
+        );
+        assert_eq!(
+            GridPosition::new(2, 2, 0),
+            grid.clamp(&GridPosition::new(2, 2, 0))
+        );
+    }
+
+    #[test]
+    fn test_aabb_size_extremes() {
+        let empty = GridAabb::with_size(&GridPosition::new(3, 3, 3), 0, 2, 2);
+        assert!(empty.is_empty());
+        assert_eq!((0, 2, 2), empty.size());
+        assert_eq!(0, empty.volume());
+        assert_eq!(0, empty.iter().count());
+
+        let huge = GridAabb::new(
+            &GridPosition::new(i32::MIN, i32::MIN, 0),
+            &GridPosition::new(i32::MAX, i32::MAX, 0),
+        );
+        assert_eq!((1 << 32, 1 << 32, 1), huge.size());
+        assert_eq!(usize::MAX, huge.volume());
+    }
+
+    #[test]
+    fn test_aabb() {
+        let aabb = GridAabb::new(&GridPosition::new(2, 0, 1), &GridPosition::new(0, 1, 1));
+        assert_eq!(&GridPosition::new(0, 0, 1), aabb.min());
+        assert_eq!(&GridPosition::new(2, 1, 1), aabb.max());
+        assert_eq!((3, 2, 1), aabb.size());
+        assert_eq!(6, aabb.volume());
+        assert!(!aabb.is_empty());
+        assert!(aabb.contains(&GridPosition::new(1, 1, 1)));
+        assert!(!aabb.contains(&GridPosition::new(1, 1, 0)));
+
+        assert_eq!(
+            GridAabb::new(&GridPosition::new(0, 0, 0), &GridPosition::new(1, 1, 1)),
+            GridAabb::with_size(&GridPosition::new(0, 0, 0), 2, 2, 2)
+        );
+        assert_eq!(
+            GridAabb::new(&GridPosition::new(0, 0, 0), &GridPosition::new(2, 2, 2)),
+            GridAabb::around(&GridPosition::new(1, 1, 1), 1)
+        );
+    }
+
+    #[test]
+    fn test_aabb_iter() {
+        let aabb = GridAabb::new(&GridPosition::new(0, 0, 0), &GridPosition::new(1, 1, 1));
+        let cells = aabb.iter().collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                GridPosition::new(0, 0, 0),
+                GridPosition::new(1, 0, 0),
+                GridPosition::new(0, 1, 0),
+                GridPosition::new(1, 1, 0),
+                GridPosition::new(0, 0, 1),
+                GridPosition::new(1, 0, 1),
+                GridPosition::new(0, 1, 1),
+                GridPosition::new(1, 1, 1),
+            ],
+            cells
+        );
+
+        let single = GridAabb::new(&GridPosition::new(3, 3, 3), &GridPosition::new(3, 3, 3));
+        assert_eq!(1, (&single).into_iter().count());
+    }
+
+    #[test]
+    fn test_aabb_clamp() {
+        let grid = Grid::with_size(4, 4, 4);
+
+        let partly = GridAabb::around(&GridPosition::new(0, 0, 0), 1);
+        assert_eq!(
+            Some(GridAabb::new(
+                &GridPosition::new(0, 0, 0),
+                &GridPosition::new(1, 1, 1)
+            )),
+            partly.clamp(&grid)
+        );
+
+        let outside = GridAabb::around(&GridPosition::new(-5, 0, 0), 1);
+        assert_eq!(None, outside.clamp(&grid));
+        assert_eq!(64, grid.aabb().volume());
+    }
+
     #[test]
     fn test_diagonal_2d() {
         {
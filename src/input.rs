@@ -0,0 +1,546 @@
+//! Player Input
+//!
+//! Keys are bound to abstract actions through a config file, one binding
+//! per line:
+//!
+//! ```text
+//! # action = key
+//! camera_north = Up
+//! view_top = D1
+//! ```
+//!
+//! Keys are named as Piston names them. Events are turned into actions as
+//! they arrive, and systems consume the actions on the next tick, turning
+//! them into commands that can be recorded and replayed.
+//!
+//! The mouse isn't rebindable: the left button selects and the right button
+//! gives orders, with shift adding to the selection or the orders.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use na::{Vector2, Vector3};
+use piston::input::{
+    Button, Event, Key, MouseButton, MouseCursorEvent, MouseScrollEvent, PressEvent, ReleaseEvent,
+};
+use specs::prelude::*;
+
+use crate::command::{Command, CommandQueue};
+use crate::isometric::{Isometric, Rotation};
+use crate::view::CutMode;
+
+/// Something the player wants done, independent of the key pressed
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Action {
+    CameraNorth,
+    CameraSouth,
+    CameraWest,
+    CameraEast,
+    CameraRaise,
+    CameraLower,
+    ViewTop,
+    ViewLeft,
+    ViewRight,
+    ViewBottom,
+    ViewSlab,
+    ViewCrossSection,
+
+    /// Turns the world clockwise on screen
+    RotateClockwise,
+    RotateAnticlockwise,
+    ZoomIn,
+    ZoomOut,
+    ReportStats,
+    ExportStats,
+}
+
+const ACTIONS: [(Action, &str); 18] = [
+    (Action::CameraNorth, "camera_north"),
+    (Action::CameraSouth, "camera_south"),
+    (Action::CameraWest, "camera_west"),
+    (Action::CameraEast, "camera_east"),
+    (Action::CameraRaise, "camera_raise"),
+    (Action::CameraLower, "camera_lower"),
+    (Action::ViewTop, "view_top"),
+    (Action::ViewLeft, "view_left"),
+    (Action::ViewRight, "view_right"),
+    (Action::ViewBottom, "view_bottom"),
+    (Action::ViewSlab, "view_slab"),
+    (Action::ViewCrossSection, "view_cross_section"),
+    (Action::RotateClockwise, "rotate_clockwise"),
+    (Action::RotateAnticlockwise, "rotate_anticlockwise"),
+    (Action::ZoomIn, "zoom_in"),
+    (Action::ZoomOut, "zoom_out"),
+    (Action::ReportStats, "report_stats"),
+    (Action::ExportStats, "export_stats"),
+];
+
+impl Action {
+    /// Indicates whether the action only concerns the game's tooling, and
+    /// is handled outside the simulation.
+    pub fn is_debug(self) -> bool {
+        matches!(self, Action::ReportStats | Action::ExportStats)
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = ACTIONS
+            .iter()
+            .find(|(action, _)| action == self)
+            .map(|(_, name)| *name)
+            .unwrap_or("unknown");
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ACTIONS
+            .iter()
+            .find(|(_, name)| *name == s)
+            .map(|(action, _)| *action)
+            .ok_or_else(|| format!("Unknown action: {}", s))
+    }
+}
+
+/// Looks a key up by its Piston name, like `Up`, `Q` or `D1`.
+fn parse_key(name: &str) -> Option<Key> {
+    // Piston's key codes follow SDL, with printable keys as ASCII and
+    // the rest in a block of their own
+    (0..0x80)
+        .chain(0x4000_0039..=0x4000_011a)
+        .map(Key::from)
+        .filter(|key| *key != Key::Unknown)
+        .find(|key| format!("{:?}", key) == name)
+}
+
+/// Which action each key triggers
+pub struct KeyBindings {
+    keys: HashMap<Key, Action>,
+}
+
+impl KeyBindings {
+    pub fn new() -> Self {
+        KeyBindings {
+            keys: HashMap::new(),
+        }
+    }
+
+    /// Reads bindings from a config file.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Result<Self, String>> {
+        Ok(fs::read_to_string(path)?.parse())
+    }
+
+    /// Binds the key to the action, replacing what it was bound to before.
+    pub fn bind(&mut self, key: Key, action: Action) {
+        self.keys.insert(key, action);
+    }
+
+    pub fn action(&self, key: Key) -> Option<Action> {
+        self.keys.get(&key).cloned()
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let mut bindings = KeyBindings::new();
+        bindings.bind(Key::Up, Action::CameraNorth);
+        bindings.bind(Key::Down, Action::CameraSouth);
+        bindings.bind(Key::Left, Action::CameraWest);
+        bindings.bind(Key::Right, Action::CameraEast);
+        bindings.bind(Key::Q, Action::CameraRaise);
+        bindings.bind(Key::A, Action::CameraLower);
+        bindings.bind(Key::D1, Action::ViewTop);
+        bindings.bind(Key::D2, Action::ViewLeft);
+        bindings.bind(Key::D3, Action::ViewRight);
+        bindings.bind(Key::D4, Action::ViewBottom);
+        bindings.bind(Key::D5, Action::ViewSlab);
+        bindings.bind(Key::D6, Action::ViewCrossSection);
+        bindings.bind(Key::E, Action::RotateClockwise);
+        bindings.bind(Key::W, Action::RotateAnticlockwise);
+        bindings.bind(Key::Equals, Action::ZoomIn);
+        bindings.bind(Key::Minus, Action::ZoomOut);
+        bindings.bind(Key::F3, Action::ReportStats);
+        bindings.bind(Key::F4, Action::ExportStats);
+        bindings
+    }
+}
+
+impl FromStr for KeyBindings {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bindings = KeyBindings::new();
+
+        for (number, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |msg: String| format!("Line {}: {}", number + 1, msg);
+            let mut parts = line.splitn(2, '=').map(str::trim);
+            let (action, key) = match (parts.next(), parts.next()) {
+                (Some(action), Some(key)) => (action, key),
+                _ => return Err(error(format!("Expected `action = key`: {}", line))),
+            };
+
+            let action = action.parse().map_err(error)?;
+            let key = parse_key(key).ok_or_else(|| error(format!("Unknown key: {}", key)))?;
+            bindings.bind(key, action);
+        }
+
+        Ok(bindings)
+    }
+}
+
+/// What the player did with the mouse, in pixels from the top left of the
+/// window
+#[derive(Clone, Debug, PartialEq)]
+pub enum Gesture {
+    /// Left click, or a box dragged with the left button
+    Select {
+        from: Vector2<f64>,
+        to: Vector2<f64>,
+
+        /// Adds to the selection instead of replacing it
+        add: bool,
+    },
+
+    /// Right click
+    Order {
+        at: Vector2<f64>,
+
+        /// Queues a waypoint instead of replacing the orders
+        queue: bool,
+    },
+}
+
+/// Resource holding the actions triggered since the last tick, and the
+/// gestures since the last frame
+#[derive(Default)]
+pub struct Input {
+    actions: Vec<Action>,
+    gestures: Vec<Gesture>,
+
+    /// Where the mouse is, and where the left button went down
+    pointer: Option<Vector2<f64>>,
+    drag_from: Option<Vector2<f64>>,
+
+    shift: bool,
+}
+
+impl Input {
+    /// Turns a released key into its bound action. Debug actions are
+    /// returned to the caller, the rest are kept for the next tick.
+    ///
+    /// Mouse buttons are turned into gestures, and the wheel zooms.
+    pub fn handle_event(&mut self, event: &Event, bindings: &KeyBindings) -> Option<Action> {
+        if let Some(pos) = event.mouse_cursor_args() {
+            self.pointer = Some(Vector2::new(pos[0], pos[1]));
+        }
+
+        if let Some([_, scroll]) = event.mouse_scroll_args() {
+            if scroll > 0. {
+                self.actions.push(Action::ZoomIn);
+            } else if scroll < 0. {
+                self.actions.push(Action::ZoomOut);
+            }
+        }
+
+        match event.press_args() {
+            Some(Button::Keyboard(Key::LShift)) | Some(Button::Keyboard(Key::RShift)) => {
+                self.shift = true
+            }
+            Some(Button::Mouse(MouseButton::Left)) => self.drag_from = self.pointer,
+            _ => {}
+        }
+
+        let action = match event.release_args() {
+            Some(Button::Keyboard(key)) => {
+                if key == Key::LShift || key == Key::RShift {
+                    self.shift = false;
+                }
+                bindings.action(key)?
+            }
+            Some(Button::Mouse(button)) => {
+                self.release_mouse(button);
+                return None;
+            }
+            _ => return None,
+        };
+
+        if action.is_debug() {
+            Some(action)
+        } else {
+            self.actions.push(action);
+            None
+        }
+    }
+
+    fn release_mouse(&mut self, button: MouseButton) {
+        let pointer = match self.pointer {
+            Some(pointer) => pointer,
+            None => return,
+        };
+
+        match button {
+            MouseButton::Left => self.gestures.push(Gesture::Select {
+                from: self.drag_from.take().unwrap_or(pointer),
+                to: pointer,
+                add: self.shift,
+            }),
+            MouseButton::Right => self.gestures.push(Gesture::Order {
+                at: pointer,
+                queue: self.shift,
+            }),
+            _ => {}
+        }
+    }
+
+    pub fn push(&mut self, action: Action) {
+        self.actions.push(action);
+    }
+
+    pub fn push_gesture(&mut self, gesture: Gesture) {
+        self.gestures.push(gesture);
+    }
+
+    /// Takes the gestures made since they were last taken
+    pub fn take_gestures(&mut self) -> Vec<Gesture> {
+        ::std::mem::take(&mut self.gestures)
+    }
+}
+
+/// Layers below the camera the slab view keeps
+const SLAB_LAYERS: u32 = 3;
+
+/// Turns camera and view actions into commands
+///
+/// The camera moves along the screen, whichever way the view is turned.
+pub struct CameraInputSystem;
+
+impl CameraInputSystem {
+    pub fn new() -> Self {
+        CameraInputSystem
+    }
+}
+
+impl<'a> System<'a> for CameraInputSystem {
+    type SystemData = (
+        Read<'a, Isometric>,
+        Write<'a, Input>,
+        Write<'a, CommandQueue>,
+    );
+
+    fn run(&mut self, (projection, mut input, mut queue): Self::SystemData) {
+        // Turns queued before a move apply first
+        let mut rotation = projection.rotation();
+        let move_camera = |rotation: Rotation, x: f64, y: f64, z: f64| {
+            let offset = rotation.inverse().rotate(&Vector3::new(x, y, z));
+            Command::MoveCamera(
+                offset.x.round() as i32,
+                offset.y.round() as i32,
+                offset.z.round() as i32,
+            )
+        };
+
+        for action in input.actions.drain(..) {
+            let command = match action {
+                Action::CameraNorth => move_camera(rotation, -1., -1., 0.),
+                Action::CameraSouth => move_camera(rotation, 1., 1., 0.),
+                Action::CameraWest => move_camera(rotation, -1., 1., 0.),
+                Action::CameraEast => move_camera(rotation, 1., -1., 0.),
+                Action::CameraRaise => move_camera(rotation, 0., 0., 1.),
+                Action::CameraLower => move_camera(rotation, 0., 0., -1.),
+                Action::ViewTop => Command::SetCutMode(CutMode::Top),
+                Action::ViewLeft => Command::SetCutMode(CutMode::Left),
+                Action::ViewRight => Command::SetCutMode(CutMode::Right),
+                Action::ViewBottom => Command::SetCutMode(CutMode::Bottom),
+                Action::ViewSlab => Command::SetCutMode(CutMode::Slab(SLAB_LAYERS)),
+                Action::ViewCrossSection => Command::SetCutMode(CutMode::CrossSection),
+                Action::RotateClockwise | Action::RotateAnticlockwise => {
+                    let turns = if action == Action::RotateClockwise {
+                        1
+                    } else {
+                        -1
+                    };
+                    rotation = rotation.turn(turns);
+                    Command::RotateView(turns)
+                }
+                Action::ZoomIn => Command::ZoomCamera(1),
+                Action::ZoomOut => Command::ZoomCamera(-1),
+                Action::ReportStats | Action::ExportStats => continue,
+            };
+
+            queue.push(command);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::actor::Actor;
+    use crate::command::CommandSystem;
+    use crate::pathfinding::components::Pather;
+    use crate::position::Position;
+    use crate::view::components::IsometricCamera;
+    use crate::view::ViewCutMode;
+
+    #[test]
+    fn test_parse_bindings() {
+        let bindings = "# Vim style\ncamera_west = H\ncamera_east=L\n\nview_top = F1\n"
+            .parse::<KeyBindings>()
+            .unwrap();
+        assert_eq!(Some(Action::CameraWest), bindings.action(Key::H));
+        assert_eq!(Some(Action::CameraEast), bindings.action(Key::L));
+        assert_eq!(Some(Action::ViewTop), bindings.action(Key::F1));
+        assert_eq!(None, bindings.action(Key::Up));
+
+        assert!("camera_west = Nope".parse::<KeyBindings>().is_err());
+        assert!("fly = Up".parse::<KeyBindings>().is_err());
+        assert!("camera_west".parse::<KeyBindings>().is_err());
+
+        // The shipped config matches the defaults
+        let shipped = include_str!("../resources/keybindings.cfg")
+            .parse::<KeyBindings>()
+            .unwrap();
+        let defaults = KeyBindings::default();
+        assert_eq!(defaults.keys, shipped.keys);
+
+        for (action, name) in ACTIONS.iter() {
+            assert_eq!(Ok(*action), action.to_string().parse());
+            assert_eq!(*name, action.to_string());
+        }
+    }
+
+    #[test]
+    fn test_actions_move_camera() {
+        let mut world = World::new();
+        world.add_resource(Input::default());
+        world.add_resource(CommandQueue::default());
+        world.add_resource(ViewCutMode::default());
+        world.add_resource(Isometric::default());
+        world.register::<Actor>();
+        world.register::<IsometricCamera>();
+        world.register::<Pather>();
+        world.register::<Position>();
+        let camera = world
+            .create_entity()
+            .with(IsometricCamera::new(true))
+            .with(Position::new(0., 0., 0.))
+            .build();
+
+        {
+            let mut input = world.write_resource::<Input>();
+            input.push(Action::CameraNorth);
+            input.push(Action::CameraRaise);
+            input.push(Action::ViewRight);
+        }
+
+        CameraInputSystem::new().run_now(&world.res);
+        CommandSystem::new().run_now(&world.res);
+
+        assert_eq!(
+            &na::Vector3::new(-1., -1., 1.),
+            world
+                .read_storage::<Position>()
+                .get(camera)
+                .unwrap()
+                .to_vector()
+        );
+        assert_eq!(CutMode::Right, world.read_resource::<ViewCutMode>().mode());
+
+        // Up the screen is along another axis once the view is turned
+        {
+            let mut input = world.write_resource::<Input>();
+            input.push(Action::RotateClockwise);
+            input.push(Action::CameraNorth);
+        }
+
+        CameraInputSystem::new().run_now(&world.res);
+        CommandSystem::new().run_now(&world.res);
+
+        assert_eq!(
+            &na::Vector3::new(-2., 0., 1.),
+            world
+                .read_storage::<Position>()
+                .get(camera)
+                .unwrap()
+                .to_vector()
+        );
+        assert_eq!(
+            Rotation::Deg90,
+            world.read_resource::<Isometric>().rotation()
+        );
+    }
+
+    #[test]
+    fn test_mouse_gestures() {
+        use piston::input::{ButtonArgs, ButtonState, Motion};
+
+        let move_to = |x, y| Event::Input(piston::input::Input::Move(Motion::MouseCursor(x, y)));
+        let button = |button, state| {
+            Event::Input(piston::input::Input::Button(ButtonArgs {
+                state,
+                button,
+                scancode: None,
+            }))
+        };
+        let left = Button::Mouse(MouseButton::Left);
+        let right = Button::Mouse(MouseButton::Right);
+        let shift = Button::Keyboard(Key::LShift);
+
+        let bindings = KeyBindings::default();
+        let mut input = Input::default();
+        let events = [
+            move_to(10., 20.),
+            button(left, ButtonState::Press),
+            move_to(50., 60.),
+            button(left, ButtonState::Release),
+            button(shift, ButtonState::Press),
+            button(right, ButtonState::Release),
+            button(shift, ButtonState::Release),
+            button(left, ButtonState::Press),
+            button(left, ButtonState::Release),
+        ];
+        for event in events.iter() {
+            assert_eq!(None, input.handle_event(event, &bindings));
+        }
+
+        assert_eq!(
+            vec![
+                Gesture::Select {
+                    from: Vector2::new(10., 20.),
+                    to: Vector2::new(50., 60.),
+                    add: false,
+                },
+                Gesture::Order {
+                    at: Vector2::new(50., 60.),
+                    queue: true,
+                },
+                Gesture::Select {
+                    from: Vector2::new(50., 60.),
+                    to: Vector2::new(50., 60.),
+                    add: false,
+                },
+            ],
+            input.take_gestures()
+        );
+        assert!(input.take_gestures().is_empty());
+
+        // The wheel zooms a step per notch
+        let scroll = |y| Event::Input(piston::input::Input::Move(Motion::MouseScroll(0., y)));
+        input.handle_event(&scroll(1.), &bindings);
+        input.handle_event(&scroll(-1.), &bindings);
+        assert_eq!(vec![Action::ZoomIn, Action::ZoomOut], input.actions);
+    }
+}
@@ -15,18 +15,141 @@
 //!     *_  |  _*          |
 //!        *|*            _|
 //!
+//! The tile size, in pixels, is kept in the `Isometric` resource, so art
+//! packs of different sizes only need a different projection.
+//!
+//! The view can be turned in quarter turns about the vertical axis. The
+//! world is rotated into view coordinates first, which are then projected
+//! as if the view wasn't turned.
+
+use na::{Vector2, Vector3};
+
+use crate::grid::Cardinal;
+
+/// Quarter turns of the view about the vertical axis
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Rotation {
+    #[default]
+    Deg0,
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
+impl Rotation {
+    pub fn from_quarter_turns(turns: i32) -> Rotation {
+        match turns.rem_euclid(4) {
+            0 => Rotation::Deg0,
+            1 => Rotation::Deg90,
+            2 => Rotation::Deg180,
+            _ => Rotation::Deg270,
+        }
+    }
 
+    pub fn quarter_turns(self) -> i32 {
+        match self {
+            Rotation::Deg0 => 0,
+            Rotation::Deg90 => 1,
+            Rotation::Deg180 => 2,
+            Rotation::Deg270 => 3,
+        }
+    }
+
+    /// Turns the view further by the number of quarter turns
+    pub fn turn(self, turns: i32) -> Rotation {
+        Rotation::from_quarter_turns(self.quarter_turns() + turns)
+    }
+
+    pub fn inverse(self) -> Rotation {
+        Rotation::from_quarter_turns(-self.quarter_turns())
+    }
+
+    /// Turns a world vector into view coordinates
+    pub fn rotate(self, v: &Vector3<f64>) -> Vector3<f64> {
+        match self {
+            Rotation::Deg0 => *v,
+            Rotation::Deg90 => Vector3::new(-v.y, v.x, v.z),
+            Rotation::Deg180 => Vector3::new(-v.x, -v.y, v.z),
+            Rotation::Deg270 => Vector3::new(v.y, -v.x, v.z),
+        }
+    }
+
+    /// Compass direction in the view that a world direction points to
+    pub fn rotate_cardinal(self, cardinal: Cardinal) -> Cardinal {
+        (0..self.quarter_turns()).fold(cardinal, |c, _| c.clockwise())
+    }
+}
+
+/// Projection between the world and the screen, for tiles of a size
 pub struct Isometric {
     tile_size: na::Vector3<u32>,
+    rotation: Rotation,
 }
 
 impl Isometric {
     pub fn new(tile_width: u32, tile_height: u32, tile_depth: u32) -> Self {
         Isometric {
             tile_size: na::Vector3::new(tile_width, tile_height, tile_depth),
+            rotation: Rotation::Deg0,
         }
     }
 
+    pub fn rotation(&self) -> Rotation {
+        self.rotation
+    }
+
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.rotation = rotation;
+    }
+
+    /// Position in view coordinates, where the viewer looks down along
+    /// negative x, y and z whichever way the view is turned
+    pub fn world_to_view(&self, pos: &Vector3<f64>) -> Vector3<f64> {
+        self.rotation.rotate(pos)
+    }
+
+    pub fn view_to_world(&self, pos: &Vector3<f64>) -> Vector3<f64> {
+        self.rotation.inverse().rotate(pos)
+    }
+
+    /// Width, height and depth of a tile in pixels
+    pub fn tile_size(&self) -> &Vector3<u32> {
+        &self.tile_size
+    }
+
+    /// Point on a block's texture that is drawn at the block's position,
+    /// the middle of its bottom face, as a fraction of the texture's size
+    pub fn block_anchor(&self) -> (f64, f64) {
+        let height = f64::from(self.tile_size.y);
+        let depth = f64::from(self.tile_size.z);
+        (0.5, (height / 2. + depth) / (height + depth))
+    }
+
+    /// Pixels a 3D position is drawn at, relative to the world's origin
+    pub fn to_screen(&self, pos: &Vector3<f64>) -> Vector2<f64> {
+        let iso = Isometric::cart_to_iso(&self.world_to_view(pos));
+        let (width, height, depth) = self.tile_pixels();
+
+        // One unit of iso x is half a tile across, and one of iso y a
+        // quarter of a tile down
+        Vector2::new(iso.x * width, iso.y * height * 2. - iso.z * depth)
+    }
+
+    /// 3D position at the given height that is drawn at the pixels
+    pub fn to_world(&self, screen: &Vector2<f64>, z: f64) -> Vector3<f64> {
+        let (width, height, depth) = self.tile_pixels();
+        let iso = Vector3::new(screen.x / width, (screen.y + z * depth) / (height * 2.), z);
+        self.view_to_world(&Isometric::iso_to_cart(&iso))
+    }
+
+    fn tile_pixels(&self) -> (f64, f64, f64) {
+        (
+            f64::from(self.tile_size.x),
+            f64::from(self.tile_size.y),
+            f64::from(self.tile_size.z),
+        )
+    }
+
     /// Convert 3D Cartesian coordinates to 2D Isometric Coordinates
     pub fn cart_to_iso<N>(pos: &na::Vector3<N>) -> na::Vector3<N>
     where
@@ -51,6 +174,13 @@ impl Isometric {
     }
 }
 
+impl Default for Isometric {
+    /// Size of the bundled art
+    fn default() -> Self {
+        Isometric::new(80, 40, 50)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -134,4 +264,67 @@ mod test {
             Isometric::iso_to_cart(&Vector3::<f32>::new(0.5, -0.25, 0.))
         );
     }
+
+    #[test]
+    fn test_tile_sizes() {
+        let small = Isometric::new(64, 32, 40);
+        let large = Isometric::new(128, 64, 80);
+
+        // Tile corners land on the corners of the texture's top face
+        let right = Vector3::new(1., 0., 0.);
+        let up = Vector3::new(0., 0., 1.);
+        assert_eq!(Vector2::new(32., 16.), small.to_screen(&right));
+        assert_eq!(Vector2::new(64., 32.), large.to_screen(&right));
+        assert_eq!(Vector2::new(0., -40.), small.to_screen(&up));
+        assert_eq!((0.5, 56. / 72.), small.block_anchor());
+        assert_eq!((0.5, 70. / 90.), Isometric::default().block_anchor());
+
+        let pos = Vector3::new(0.75, -1.5, 2.);
+        for projection in [small, large, Isometric::default()].iter() {
+            let screen = projection.to_screen(&pos);
+            assert_eq!(pos, projection.to_world(&screen, pos.z));
+        }
+    }
+
+    #[test]
+    fn test_rotation() {
+        use crate::grid::Direction;
+
+        assert_eq!(Rotation::Deg270, Rotation::Deg0.turn(-1));
+        assert_eq!(Rotation::Deg90, Rotation::Deg270.turn(2));
+        assert_eq!(Rotation::Deg270, Rotation::Deg90.inverse());
+
+        let pos = Vector3::new(0.75, -1.5, 2.);
+        for turns in 0..4 {
+            let mut projection = Isometric::default();
+            projection.set_rotation(Rotation::from_quarter_turns(turns));
+
+            let view = projection.world_to_view(&pos);
+            assert_eq!(pos.z, view.z);
+            assert_eq!(pos, projection.view_to_world(&view));
+
+            let screen = projection.to_screen(&pos);
+            assert_eq!(pos, projection.to_world(&screen, pos.z));
+
+            // Compass directions turn along with vectors
+            let rotation = projection.rotation();
+            for cardinal in Cardinal::ALL.iter() {
+                let (x, y, z) = Direction::from(*cardinal).offset();
+                let (vx, vy, vz) = Direction::from(rotation.rotate_cardinal(*cardinal)).offset();
+                let v = Vector3::new(f64::from(x), f64::from(y), f64::from(z));
+                assert_eq!(
+                    Vector3::new(f64::from(vx), f64::from(vy), f64::from(vz)),
+                    rotation.rotate(&v)
+                );
+            }
+        }
+
+        // Half a turn shows the world from the opposite corner
+        let mut projection = Isometric::default();
+        projection.set_rotation(Rotation::Deg180);
+        assert_eq!(
+            Isometric::default().to_screen(&Vector3::new(-pos.x, -pos.y, pos.z)),
+            projection.to_screen(&pos)
+        );
+    }
 }
@@ -5,7 +5,9 @@ extern crate specs;
 #[macro_use]
 extern crate specs_derive;
 
+pub mod common;
 pub mod grid;
 pub mod pathfinding;
 pub mod pigeon;
+pub mod position;
 pub mod tilemap;
@@ -1,10 +1,10 @@
 //! A* Pathfinding
 
 use super::cost::*;
-use super::distance::*;
+use super::landmarks::Landmarks;
 use super::locomotion::*;
 use super::path_node::*;
-use super::path_result::PathResult;
+use super::path_result::{PathFailure, PathResult, SearchOptions};
 use super::path_space::PathSpace;
 use super::pathfinder::Pathfinder;
 use crate::grid::{Grid, GridPosition};
@@ -13,11 +13,62 @@ use std::collections::HashSet;
 use std::time;
 
 #[derive(Default)]
-pub struct AStar;
+pub struct AStar {
+    options: SearchOptions,
+}
 
 impl AStar {
     pub fn new() -> AStar {
-        AStar
+        AStar::with_options(SearchOptions::default())
+    }
+
+    pub fn with_options(options: SearchOptions) -> AStar {
+        AStar { options }
+    }
+
+    #[inline(always)]
+    pub fn options(&self) -> &SearchOptions {
+        &self.options
+    }
+
+    /// Searches for a path guided by landmark estimates, which expands far
+    /// fewer nodes than straight line distance when walls are in the way.
+    #[allow(clippy::too_many_arguments)]
+    pub fn find_path_with_landmarks<C, L>(
+        &self,
+        grid: &Grid,
+        locomotion: &Locomotion,
+        start: &GridPosition,
+        end: &GridPosition,
+        landmarks: &Landmarks,
+        cost_strat: &C,
+        loco_strat: &L,
+    ) -> PathResult
+    where
+        C: CostStrategy,
+        L: LocomotionStrategy,
+    {
+        if !grid.in_bounds(end) {
+            return PathResult::with_failure(
+                0,
+                time::Duration::default(),
+                PathFailure::OutOfBounds,
+            );
+        }
+
+        self.find_path_where(
+            grid,
+            locomotion,
+            start,
+            |pos| pos == end,
+            |pos| {
+                landmarks
+                    .heuristic(pos, end)
+                    .max(cost_strat.estimate(pos, end))
+            },
+            cost_strat,
+            loco_strat,
+        )
     }
 }
 
@@ -34,17 +85,60 @@ impl Pathfinder for AStar {
     where
         C: CostStrategy,
         L: LocomotionStrategy,
+    {
+        if !grid.in_bounds(end) {
+            return PathResult::with_failure(
+                0,
+                time::Duration::default(),
+                PathFailure::OutOfBounds,
+            );
+        }
+
+        self.find_path_where(
+            grid,
+            locomotion,
+            start,
+            |pos| pos == end,
+            |pos| cost_strat.estimate(pos, end),
+            cost_strat,
+            loco_strat,
+        )
+    }
+
+    fn find_path_where<C, L, G, H>(
+        &self,
+        grid: &Grid,
+        locomotion: &Locomotion,
+        start: &GridPosition,
+        is_goal: G,
+        heuristic: H,
+        cost_strat: &C,
+        loco_strat: &L,
+    ) -> PathResult
+    where
+        C: CostStrategy,
+        L: LocomotionStrategy,
+        G: Fn(&GridPosition) -> bool,
+        H: Fn(&GridPosition) -> u32,
     {
         let mut iter_count = 0;
         let start_time = time::Instant::now();
 
+        if !grid.in_bounds(start) {
+            return PathResult::with_failure(0, start_time.elapsed(), PathFailure::OutOfBounds);
+        }
+
+        // Closest explored node to the goal, for partial paths
+        let mut closest: (u32, u32, GridPosition) = (u32::MAX, u32::MAX, start.clone());
+        let mut failure = PathFailure::Blocked;
+
         // Note the BinaryHeap is a max-heap
         let mut nodes = PathSpace::from_grid(&grid);
         let mut open: BinaryHeap<PathNodePos> = BinaryHeap::new();
         let mut close: HashSet<GridPosition> = HashSet::new();
 
         // Seed lists with initial position
-        let start_h = euler(start, end);
+        let start_h = heuristic(start);
         let start_node = PathNode {
             pos: start.clone(),
             g: 0,
@@ -65,22 +159,31 @@ impl Pathfinder for AStar {
                     .get(&node_pos)
                     .expect("Popped node from priority queue that's not in the known space");
                 node_g = node.g;
+
+                if (node.h, node.g) < (closest.0, closest.1) {
+                    closest = (node.h, node.g, node_pos.clone());
+                }
             }
 
             // Check if we've reached our destination
-            if &node_pos == end {
+            if is_goal(&node_pos) {
                 // Trace the path back to start
                 return PathResult::with_stats(
                     iter_count,
                     time::Instant::now().duration_since(start_time),
-                    nodes.carve_path(&end),
+                    nodes.carve_path(&node_pos),
                 );
             }
 
+            if let Some(exceeded) = self.options.exceeded(iter_count, &start_time) {
+                failure = exceeded;
+                break;
+            }
+
             // TODO: Conditionally either do a 2d or 3d neighbour search
             let neighbours = grid.neighbours_3d(&node_pos);
             let in_bound_neighbours = neighbours
-                .into_iter()
+                .iter()
                 .filter_map(|maybe_neigh| maybe_neigh.as_ref());
 
             for neigh_pos in in_bound_neighbours {
@@ -99,9 +202,8 @@ impl Pathfinder for AStar {
                 }
 
                 // TODO: Corner cutting detection
-                // TODO: Cost retrieved from cost_func added to `g`
                 let g = &node_g + cost.passable().unwrap();
-                let h = euler(&node_pos, neigh_pos) * 10;
+                let h = heuristic(neigh_pos);
 
                 // TODO: Check if node is pathable
                 let parent_node = Some(node_pos.clone());
@@ -118,6 +220,188 @@ impl Pathfinder for AStar {
             }
         }
 
-        PathResult::with_fail_stats(iter_count, time::Instant::now().duration_since(start_time))
+        let (_, _, closest_pos) = closest;
+        if self.options.allow_partial && &closest_pos != start {
+            return PathResult::with_partial(
+                iter_count,
+                time::Instant::now().duration_since(start_time),
+                nodes.carve_path(&closest_pos),
+                failure,
+            );
+        }
+
+        PathResult::with_failure(
+            iter_count,
+            time::Instant::now().duration_since(start_time),
+            failure,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pathfinding::{NoOpCost, NoOpLocomotion, PathGoal, GO_ANYWHERE};
+
+    fn positions(result: &PathResult) -> Vec<GridPosition> {
+        result
+            .path()
+            .expect("Path not found")
+            .iter()
+            .map(|node| node.pos.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_nearest_of_many() {
+        let grid = Grid::with_size(16, 16, 1);
+        let goals = (0..16)
+            .map(|y| GridPosition::new(15, y, 0))
+            .chain(::std::iter::once(GridPosition::new(4, 0, 0)))
+            .collect::<Vec<_>>();
+
+        let result = AStar::new().find_path_to_any(
+            &grid,
+            &Locomotion::new(&[GO_ANYWHERE]),
+            &GridPosition::new(0, 0, 0),
+            &goals,
+            &NoOpCost,
+            &NoOpLocomotion,
+        );
+
+        assert_eq!(Some(&GridPosition::new(4, 0, 0)), result.goal());
+        assert_eq!(5, positions(&result).len());
+    }
+
+    #[test]
+    fn test_adjacent_goal() {
+        let grid = Grid::with_size(8, 1, 1);
+        let result = AStar::new().find_path_to(
+            &grid,
+            &Locomotion::new(&[GO_ANYWHERE]),
+            &GridPosition::new(0, 0, 0),
+            &PathGoal::AdjacentTo(GridPosition::new(5, 0, 0)),
+            &NoOpCost,
+            &NoOpLocomotion,
+        );
+
+        assert_eq!(Some(&GridPosition::new(4, 0, 0)), result.goal());
+    }
+
+    #[test]
+    fn test_partial_path() {
+        // Wall across the corridor at x = 4
+        struct WallCost;
+        impl CostStrategy for WallCost {
+            fn is_passable(&self, _source: &GridPosition, target: &GridPosition) -> Cost {
+                if target.x() == 4 {
+                    Cost::Blocked
+                } else {
+                    Cost::Passable(10)
+                }
+            }
+        }
+
+        let grid = Grid::with_size(8, 1, 1);
+        let locomotion = Locomotion::new(&[GO_ANYWHERE]);
+        let start = GridPosition::new(0, 0, 0);
+        let end = GridPosition::new(7, 0, 0);
+
+        let result =
+            AStar::new().find_path(&grid, &locomotion, &start, &end, &WallCost, &NoOpLocomotion);
+        assert!(!result.is_success());
+        assert!(result.path().is_none());
+        assert_eq!(Some(PathFailure::Blocked), result.failure());
+
+        let options = SearchOptions {
+            allow_partial: true,
+            ..Default::default()
+        };
+        let result = AStar::with_options(options).find_path(
+            &grid,
+            &locomotion,
+            &start,
+            &end,
+            &WallCost,
+            &NoOpLocomotion,
+        );
+        assert!(result.is_partial());
+        assert_eq!(None, result.goal());
+        assert_eq!(
+            &GridPosition::new(3, 0, 0),
+            positions(&result).last().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_failure_reasons() {
+        let grid = Grid::with_size(16, 16, 1);
+        let locomotion = Locomotion::new(&[GO_ANYWHERE]);
+
+        let result = AStar::new().find_path(
+            &grid,
+            &locomotion,
+            &GridPosition::new(0, 0, 0),
+            &GridPosition::new(20, 0, 0),
+            &NoOpCost,
+            &NoOpLocomotion,
+        );
+        assert_eq!(Some(PathFailure::OutOfBounds), result.failure());
+
+        // Goals of every kind are checked before searching
+        for goal in [
+            PathGoal::AnyOf(vec![
+                GridPosition::new(20, 0, 0),
+                GridPosition::new(0, 20, 0),
+            ]),
+            PathGoal::AdjacentTo(GridPosition::new(0, 0, 2)),
+        ]
+        .iter()
+        {
+            let result = AStar::new().find_path_to(
+                &grid,
+                &locomotion,
+                &GridPosition::new(0, 0, 0),
+                goal,
+                &NoOpCost,
+                &NoOpLocomotion,
+            );
+            assert_eq!(Some(PathFailure::OutOfBounds), result.failure());
+            assert_eq!(0, result.iter_count());
+        }
+
+        let options = SearchOptions {
+            max_iterations: Some(3),
+            allow_partial: true,
+            ..Default::default()
+        };
+        let result = AStar::with_options(options).find_path(
+            &grid,
+            &locomotion,
+            &GridPosition::new(0, 0, 0),
+            &GridPosition::new(15, 15, 0),
+            &NoOpCost,
+            &NoOpLocomotion,
+        );
+        assert_eq!(Some(PathFailure::IterationCap), result.failure());
+        assert!(result.is_partial());
+    }
+
+    #[test]
+    fn test_predicate_goal() {
+        let grid = Grid::with_size(8, 8, 1);
+        let result = AStar::new().find_path_where(
+            &grid,
+            &Locomotion::new(&[GO_ANYWHERE]),
+            &GridPosition::new(0, 0, 0),
+            |pos| pos.x() + pos.y() >= 6,
+            |_| 0,
+            &NoOpCost,
+            &NoOpLocomotion,
+        );
+
+        let goal = result.goal().expect("Path not found");
+        assert_eq!(6, goal.x() + goal.y());
+        assert_eq!(goal, positions(&result).last().unwrap());
     }
 }
@@ -0,0 +1,447 @@
+//! Bidirectional A* Pathfinding
+//!
+//! Searches forward from the start and backward from the goal at the same
+//! time, until the two frontiers meet. On long queries this expands far
+//! fewer nodes than a single search, whose frontier grows with distance.
+
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::time;
+
+use super::astar::AStar;
+use super::cost::*;
+use super::locomotion::*;
+use super::path_node::*;
+use super::path_result::{PathFailure, PathResult, SearchOptions};
+use super::pathfinder::Pathfinder;
+use crate::grid::{Grid, GridPosition};
+
+/// One half of the search
+struct Frontier<'a, C> {
+    /// The position this half estimates its distance towards
+    target: &'a GridPosition,
+
+    /// Whether this half walks edges forwards, from the start
+    is_forward: bool,
+    cost_strat: &'a C,
+    open: BinaryHeap<PathNodePos>,
+    closed: HashSet<GridPosition>,
+
+    /// Best known cost from the half's origin, and the previous position
+    /// on the way there
+    nodes: HashMap<GridPosition, (u32, Option<GridPosition>)>,
+}
+
+impl<'a, C: CostStrategy> Frontier<'a, C> {
+    fn new(
+        origin: &GridPosition,
+        target: &'a GridPosition,
+        is_forward: bool,
+        cost_strat: &'a C,
+    ) -> Self {
+        let mut frontier = Frontier {
+            target,
+            is_forward,
+            cost_strat,
+            open: BinaryHeap::new(),
+            closed: HashSet::new(),
+            nodes: HashMap::new(),
+        };
+        frontier.nodes.insert(origin.clone(), (0, None));
+        frontier
+            .open
+            .push(PathNodePos(origin.clone(), frontier.heuristic(origin)));
+        frontier
+    }
+
+    /// Lower bound on the cost between the position and the target, which
+    /// the stopping rule relies on to never skip a cheaper path.
+    #[inline]
+    fn heuristic(&self, pos: &GridPosition) -> u32 {
+        // The backward half measures routes that lead from the target to it
+        if self.is_forward {
+            self.cost_strat.estimate(pos, self.target)
+        } else {
+            self.cost_strat.estimate(self.target, pos)
+        }
+    }
+
+    #[inline]
+    fn g(&self, pos: &GridPosition) -> Option<u32> {
+        self.nodes.get(pos).map(|(g, _)| *g)
+    }
+
+    /// Pops the next node to expand, skipping entries that were superseded
+    /// by a cheaper route.
+    fn pop(&mut self) -> Option<GridPosition> {
+        while let Some(PathNodePos(pos, f)) = self.open.pop() {
+            if self.closed.contains(&pos) {
+                continue;
+            }
+
+            let g = self.g(&pos).unwrap_or(u32::MAX);
+            if g + self.heuristic(&pos) < f {
+                continue;
+            }
+
+            self.closed.insert(pos.clone());
+            return Some(pos);
+        }
+
+        None
+    }
+
+    /// Lowest cost estimate left in the open list
+    fn peek(&self) -> Option<u32> {
+        self.open.peek().map(|PathNodePos(_, f)| *f)
+    }
+
+    /// Records a route to the position if it's the cheapest so far.
+    fn relax(&mut self, pos: &GridPosition, g: u32, parent: &GridPosition) -> bool {
+        if self.closed.contains(pos) || self.g(pos).map(|old| old <= g).unwrap_or(false) {
+            return false;
+        }
+
+        self.nodes.insert(pos.clone(), (g, Some(parent.clone())));
+        self.open
+            .push(PathNodePos(pos.clone(), g + self.heuristic(pos)));
+        true
+    }
+
+    /// Positions from the origin up to the given one
+    fn trace(&self, pos: &GridPosition) -> Vec<GridPosition> {
+        let mut result = vec![pos.clone()];
+        let mut next = pos;
+
+        while let Some((_, Some(parent))) = self.nodes.get(next) {
+            result.push(parent.clone());
+            next = parent;
+        }
+
+        result
+    }
+}
+
+/// A* that searches from both ends of the path
+///
+/// The backward half walks edges in reverse, asking the strategies whether
+/// the neighbour can reach the node rather than the other way around. That
+/// keeps one-way moves, like drops and ladders only climbable one way,
+/// correct in both halves.
+///
+/// Searches for goals other than a single cell can't run backwards, and
+/// fall back to a forward `AStar` search.
+#[derive(Default)]
+pub struct BidirectionalAStar {
+    options: SearchOptions,
+}
+
+impl BidirectionalAStar {
+    pub fn new() -> BidirectionalAStar {
+        BidirectionalAStar::with_options(SearchOptions::default())
+    }
+
+    pub fn with_options(options: SearchOptions) -> BidirectionalAStar {
+        BidirectionalAStar { options }
+    }
+
+    #[inline(always)]
+    pub fn options(&self) -> &SearchOptions {
+        &self.options
+    }
+}
+
+impl Pathfinder for BidirectionalAStar {
+    fn find_path<C, L>(
+        &self,
+        grid: &Grid,
+        locomotion: &Locomotion,
+        start: &GridPosition,
+        end: &GridPosition,
+        cost_strat: &C,
+        loco_strat: &L,
+    ) -> PathResult
+    where
+        C: CostStrategy,
+        L: LocomotionStrategy,
+    {
+        let mut iter_count = 0;
+        let start_time = time::Instant::now();
+
+        if !grid.in_bounds(start) || !grid.in_bounds(end) {
+            return PathResult::with_failure(0, start_time.elapsed(), PathFailure::OutOfBounds);
+        }
+
+        let mut forward = Frontier::new(start, end, true, cost_strat);
+        let mut backward = Frontier::new(end, start, false, cost_strat);
+
+        // Cheapest complete path found so far, by the node the halves met at
+        let mut best: Option<(u32, GridPosition)> = if start == end {
+            Some((0, start.clone()))
+        } else {
+            None
+        };
+
+        // Closest explored node to the goal, for partial paths
+        let mut closest: (u32, u32, GridPosition) = (u32::MAX, u32::MAX, start.clone());
+        let mut failure = PathFailure::Blocked;
+
+        loop {
+            // Nothing left in either frontier can beat the best path
+            let best_cost = best.as_ref().map(|(cost, _)| *cost).unwrap_or(u32::MAX);
+            let (forward_min, backward_min) = match (forward.peek(), backward.peek()) {
+                (Some(f), Some(b)) => (f, b),
+                // One half ran dry, so every route it could take was tried
+                _ => break,
+            };
+            if forward_min >= best_cost || backward_min >= best_cost {
+                break;
+            }
+
+            if let Some(exceeded) = self.options.exceeded(iter_count, &start_time) {
+                failure = exceeded;
+                break;
+            }
+
+            // Grow the smaller half, which keeps both frontiers balanced
+            let is_forward = forward.open.len() <= backward.open.len();
+            let (active, other) = if is_forward {
+                (&mut forward, &backward)
+            } else {
+                (&mut backward, &forward)
+            };
+
+            let node_pos = match active.pop() {
+                Some(pos) => pos,
+                None => continue,
+            };
+            iter_count += 1;
+
+            let node_g = active.g(&node_pos).unwrap_or(0);
+            if is_forward {
+                let h = active.heuristic(&node_pos);
+                if (h, node_g) < (closest.0, closest.1) {
+                    closest = (h, node_g, node_pos.clone());
+                }
+            }
+
+            let neighbours = grid.neighbours_3d(&node_pos);
+            for neigh_pos in neighbours.iter().filter_map(|n| n.as_ref()) {
+                // Backward edges lead from the neighbour into the node
+                let (source, target) = if is_forward {
+                    (&node_pos, neigh_pos)
+                } else {
+                    (neigh_pos, &node_pos)
+                };
+
+                let cost = match cost_strat.is_passable(source, target) {
+                    Cost::Passable(cost) => cost,
+                    Cost::Blocked => continue,
+                };
+
+                if !loco_strat.is_passable(locomotion, source, target) {
+                    continue;
+                }
+
+                let g = node_g + cost;
+                if !active.relax(neigh_pos, g, &node_pos) {
+                    continue;
+                }
+
+                if let Some(other_g) = other.g(neigh_pos) {
+                    let total = g + other_g;
+                    if best.as_ref().map(|(cost, _)| total < *cost).unwrap_or(true) {
+                        best = Some((total, neigh_pos.clone()));
+                    }
+                }
+            }
+        }
+
+        let duration = time::Instant::now().duration_since(start_time);
+
+        match best {
+            Some((total, meet)) => {
+                let mut positions = forward.trace(&meet);
+                positions.reverse();
+
+                // Costs along the backward half are counted from the goal
+                let to_meet = positions.iter().map(|pos| forward.g(pos).unwrap_or(0));
+                let from_meet = backward
+                    .trace(&meet)
+                    .into_iter()
+                    .skip(1)
+                    .map(|pos| {
+                        let g = total - backward.g(&pos).unwrap_or(0);
+                        (pos, g)
+                    })
+                    .collect::<Vec<_>>();
+
+                let path = positions
+                    .iter()
+                    .cloned()
+                    .zip(to_meet)
+                    .chain(from_meet)
+                    .map(|(pos, g)| {
+                        let h = forward.heuristic(&pos);
+                        PathNode {
+                            pos,
+                            g,
+                            h,
+                            cost: g + h,
+                        }
+                    })
+                    .collect();
+
+                PathResult::with_stats(iter_count, duration, path)
+            }
+            None => {
+                let (_, _, closest_pos) = closest;
+                if self.options.allow_partial && &closest_pos != start {
+                    let mut positions = forward.trace(&closest_pos);
+                    positions.reverse();
+
+                    let path = positions
+                        .into_iter()
+                        .map(|pos| {
+                            let g = forward.g(&pos).unwrap_or(0);
+                            let h = forward.heuristic(&pos);
+                            PathNode {
+                                pos,
+                                g,
+                                h,
+                                cost: g + h,
+                            }
+                        })
+                        .collect();
+
+                    return PathResult::with_partial(iter_count, duration, path, failure);
+                }
+
+                PathResult::with_failure(iter_count, duration, failure)
+            }
+        }
+    }
+
+    fn find_path_where<C, L, G, H>(
+        &self,
+        grid: &Grid,
+        locomotion: &Locomotion,
+        start: &GridPosition,
+        is_goal: G,
+        heuristic: H,
+        cost_strat: &C,
+        loco_strat: &L,
+    ) -> PathResult
+    where
+        C: CostStrategy,
+        L: LocomotionStrategy,
+        G: Fn(&GridPosition) -> bool,
+        H: Fn(&GridPosition) -> u32,
+    {
+        AStar::with_options(self.options.clone()).find_path_where(
+            grid, locomotion, start, is_goal, heuristic, cost_strat, loco_strat,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pathfinding::{NoOpCost, NoOpLocomotion, TilemapCost, GO_ANYWHERE};
+    use crate::tilemap::{Tile, Tilemap};
+
+    /// Moving from x = 3 to x = 2 is only possible at y = 2, like dropping
+    /// down a ledge that can only be climbed back up with a ladder.
+    struct OneWay;
+
+    impl LocomotionStrategy for OneWay {
+        fn is_passable(
+            &self,
+            _locomotion: &Locomotion,
+            source: &GridPosition,
+            target: &GridPosition,
+        ) -> bool {
+            !(source.x() >= 3 && target.x() <= 2 && !(source.y() == 2 && target.y() == 2))
+        }
+    }
+
+    fn positions(result: &PathResult) -> Vec<GridPosition> {
+        result
+            .path()
+            .expect("Path not found")
+            .iter()
+            .map(|node| node.pos.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_straight_path() {
+        let grid = Grid::with_size(16, 16, 1);
+        let start = GridPosition::new(0, 0, 0);
+        let end = GridPosition::new(10, 10, 0);
+
+        let result = BidirectionalAStar::new().find_path(
+            &grid,
+            &Locomotion::new(&[GO_ANYWHERE]),
+            &start,
+            &end,
+            &NoOpCost,
+            &NoOpLocomotion,
+        );
+
+        let path = positions(&result);
+        assert_eq!(11, path.len());
+        assert_eq!(&start, path.first().unwrap());
+        assert_eq!(&end, path.last().unwrap());
+        assert_eq!(100, result.path().unwrap().last().unwrap().g);
+    }
+
+    #[test]
+    fn test_one_way_edges() {
+        let grid = Grid::with_size(6, 3, 1);
+        let locomotion = Locomotion::new(&[GO_ANYWHERE]);
+        let left = GridPosition::new(0, 0, 0);
+        let right = GridPosition::new(5, 0, 0);
+        let pathfinder = BidirectionalAStar::new();
+
+        // With the grain, the direct route works
+        let result = pathfinder.find_path(&grid, &locomotion, &left, &right, &NoOpCost, &OneWay);
+        assert_eq!(6, positions(&result).len());
+
+        // Against it, the path must detour through the one crossing
+        let result = pathfinder.find_path(&grid, &locomotion, &right, &left, &NoOpCost, &OneWay);
+        let path = positions(&result);
+        assert_eq!(&right, path.first().unwrap());
+        assert_eq!(&left, path.last().unwrap());
+        for step in path.windows(2) {
+            assert!(OneWay.is_passable(&locomotion, &step[0], &step[1]));
+        }
+        assert!(path.contains(&GridPosition::new(3, 2, 0)));
+        assert!(path.contains(&GridPosition::new(2, 2, 0)));
+
+        // Without the crossing there is no way back at all
+        let grid = Grid::with_size(6, 2, 1);
+        let result = pathfinder.find_path(&grid, &locomotion, &right, &left, &NoOpCost, &OneWay);
+        assert_eq!(Some(PathFailure::Blocked), result.failure());
+    }
+
+    #[test]
+    fn test_cheapest_path_with_diagonals() {
+        let grid = Grid::with_size(12, 12, 1);
+        let mut tilemap = Tilemap::with_size(12, 12, 1);
+        for y in 0..10 {
+            tilemap.set_tile(&GridPosition::new(6, y, 0), Tile::GreyBlock);
+        }
+        let result = BidirectionalAStar::new().find_path(
+            &grid,
+            &Locomotion::new(&[GO_ANYWHERE]),
+            &GridPosition::new(1, 2, 0),
+            &GridPosition::new(10, 1, 0),
+            &TilemapCost::new(&tilemap),
+            &NoOpLocomotion,
+        );
+
+        // Around the end of the wall, through (6, 10): 5 diagonals and 3
+        // straight steps there, 4 diagonals and 5 straight steps back
+        assert_eq!(14 * 9 + 10 * 8, result.path().unwrap().last().unwrap().g);
+    }
+}
@@ -0,0 +1,298 @@
+//! Reuse of recently found paths
+//!
+//! Actors tend to walk the same trips over and over, so finished searches
+//! are kept around until the terrain under them changes.
+
+use std::collections::HashMap;
+
+use crate::grid::{Grid, GridAabb, GridPosition};
+use crate::tilemap::Tilemap;
+
+use super::cost::CostStrategy;
+use super::doors::Credentials;
+use super::goal::PathGoal;
+use super::locomotion::*;
+use super::path_node::PathNode;
+use super::path_result::PathResult;
+use super::pathfinder::Pathfinder;
+
+/// Identifies a search that can be answered from the cache
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct PathKey {
+    pub start: GridPosition,
+    pub goal: PathGoal,
+
+    /// Locomotion methods of the pather, since they decide where it can go
+    pub locomotion: u32,
+
+    /// Doors the pather may pass through
+    pub credentials: Option<Credentials>,
+}
+
+impl PathKey {
+    pub fn new(
+        start: &GridPosition,
+        goal: &PathGoal,
+        locomotion: &Locomotion,
+        credentials: Option<&Credentials>,
+    ) -> Self {
+        PathKey {
+            start: start.clone(),
+            goal: goal.clone(),
+            locomotion: locomotion.methods(),
+            credentials: credentials.cloned(),
+        }
+    }
+}
+
+struct CacheEntry {
+    path: Vec<PathNode>,
+
+    /// Value of the cache's use counter when the entry was last read
+    last_used: u64,
+}
+
+/// Least recently used cache of successful path searches
+///
+/// Call `sync` with the tilemap before each lookup, so that paths running
+/// through edited tiles are evicted. Partial and failed results are never
+/// cached, since any edit could open up the way.
+pub struct PathCache {
+    capacity: usize,
+    entries: HashMap<PathKey, CacheEntry>,
+
+    /// Tilemap revision the entries are valid for
+    revision: u64,
+
+    /// Ever increasing counter to order entries by use
+    uses: u64,
+
+    hits: u64,
+    misses: u64,
+}
+
+impl PathCache {
+    pub fn with_capacity(capacity: usize) -> Self {
+        PathCache {
+            capacity,
+            entries: HashMap::new(),
+            revision: 0,
+            uses: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Evicts the paths affected by tile edits since the last sync.
+    pub fn sync(&mut self, tilemap: &Tilemap) {
+        if tilemap.revision() == self.revision {
+            return;
+        }
+
+        match tilemap.changes_since(self.revision) {
+            Some(changes) => {
+                for cell in changes {
+                    self.invalidate(cell);
+                }
+            }
+            None => self.entries.clear(),
+        }
+
+        self.revision = tilemap.revision();
+    }
+
+    /// Evicts the paths that an edit of the given cell could affect.
+    ///
+    /// Passability of a cell depends on the cells directly above and below
+    /// it, so paths passing next to the edit are dropped too.
+    pub fn invalidate(&mut self, cell: &GridPosition) {
+        let touched = GridAabb::around(cell, 1);
+        self.entries
+            .retain(|_, entry| !entry.path.iter().any(|node| touched.contains(&node.pos)));
+    }
+
+    pub fn get(&mut self, key: &PathKey) -> Option<PathResult> {
+        self.get_if(key, |_| true)
+    }
+
+    /// Looks up a path, only using it if it passes the given check, for
+    /// example against other actors' reservations. Rejected paths count as
+    /// a miss, but stay cached.
+    pub fn get_if<F>(&mut self, key: &PathKey, accept: F) -> Option<PathResult>
+    where
+        F: Fn(&[PathNode]) -> bool,
+    {
+        self.uses += 1;
+
+        match self.entries.get_mut(key) {
+            Some(entry) if accept(&entry.path) => {
+                entry.last_used = self.uses;
+                self.hits += 1;
+                Some(PathResult::with_cached(entry.path.clone()))
+            }
+            _ => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Stores the path of a successful search, evicting the least recently
+    /// used entry when full.
+    pub fn insert(&mut self, key: PathKey, result: &PathResult) {
+        if !result.is_success() || self.capacity == 0 {
+            return;
+        }
+
+        if let Some(path) = result.path() {
+            if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+                let oldest = self
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.last_used)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    self.entries.remove(&oldest);
+                }
+            }
+
+            self.uses += 1;
+            self.entries.insert(
+                key,
+                CacheEntry {
+                    path: path.clone(),
+                    last_used: self.uses,
+                },
+            );
+        }
+    }
+
+    /// Answers the search from the cache, or runs it and caches the result.
+    #[allow(clippy::too_many_arguments)]
+    pub fn find_path_to<P, C, L>(
+        &mut self,
+        pathfinder: &P,
+        grid: &Grid,
+        locomotion: &Locomotion,
+        credentials: Option<&Credentials>,
+        start: &GridPosition,
+        goal: &PathGoal,
+        cost_strat: &C,
+        loco_strat: &L,
+    ) -> PathResult
+    where
+        P: Pathfinder,
+        C: CostStrategy,
+        L: LocomotionStrategy,
+    {
+        let key = PathKey::new(start, goal, locomotion, credentials);
+        if let Some(result) = self.get(&key) {
+            return result;
+        }
+
+        let result = pathfinder.find_path_to(grid, locomotion, start, goal, cost_strat, loco_strat);
+        self.insert(key, &result);
+        result
+    }
+
+    #[inline(always)]
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    #[inline(always)]
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl Default for PathCache {
+    fn default() -> Self {
+        PathCache::with_capacity(256)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pathfinding::{AStar, NoOpCost, NoOpLocomotion, GO_ANYWHERE};
+    use crate::tilemap::Tile;
+
+    fn search(cache: &mut PathCache, end: GridPosition) -> PathResult {
+        let grid = Grid::with_size(8, 8, 1);
+        let locomotion = Locomotion::new(&[GO_ANYWHERE]);
+
+        cache.find_path_to(
+            &AStar::new(),
+            &grid,
+            &locomotion,
+            None,
+            &GridPosition::new(0, 0, 0),
+            &PathGoal::Cell(end),
+            &NoOpCost,
+            &NoOpLocomotion,
+        )
+    }
+
+    #[test]
+    fn test_hit_and_eviction() {
+        let mut cache = PathCache::with_capacity(2);
+        let a = GridPosition::new(7, 0, 0);
+        let b = GridPosition::new(0, 7, 0);
+        let c = GridPosition::new(7, 7, 0);
+
+        assert!(!search(&mut cache, a.clone()).is_cached());
+        assert!(search(&mut cache, a.clone()).is_cached());
+        search(&mut cache, b.clone());
+
+        // `b` is the least recently used once `a` is read again
+        search(&mut cache, a.clone());
+        search(&mut cache, c.clone());
+        assert_eq!(2, cache.len());
+        assert!(search(&mut cache, a.clone()).is_cached());
+        assert!(!search(&mut cache, b).is_cached());
+        assert_eq!(3, cache.hits());
+        assert_eq!(4, cache.misses());
+
+        // Rejected paths are a miss, but stay around
+        let key = PathKey::new(
+            &GridPosition::new(0, 0, 0),
+            &PathGoal::Cell(GridPosition::new(7, 0, 0)),
+            &Locomotion::new(&[GO_ANYWHERE]),
+            None,
+        );
+        assert!(cache.get_if(&key, |_| false).is_none());
+        assert!(cache.get(&key).is_some());
+        assert_eq!(5, cache.misses());
+    }
+
+    #[test]
+    fn test_tile_edit_invalidates() {
+        let mut cache = PathCache::default();
+        let mut tilemap = Tilemap::with_size(8, 8, 1);
+        search(&mut cache, GridPosition::new(7, 0, 0));
+        search(&mut cache, GridPosition::new(0, 7, 0));
+
+        // Far away from either path
+        tilemap.set_tile(&GridPosition::new(5, 5, 0), Tile::GreyBlock);
+        cache.sync(&tilemap);
+        assert_eq!(2, cache.len());
+
+        tilemap.set_tile(&GridPosition::new(4, 0, 0), Tile::GreyBlock);
+        cache.sync(&tilemap);
+        assert_eq!(1, cache.len());
+        assert!(search(&mut cache, GridPosition::new(0, 7, 0)).is_cached());
+    }
+}
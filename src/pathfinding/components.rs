@@ -1,9 +1,12 @@
+use std::collections::VecDeque;
+
 use specs::prelude::*;
 
 use crate::grid::GridPosition;
 
+use super::goal::PathGoal;
 use super::path_node::PathNode;
-use super::path_result::PathResult;
+use super::path_result::{PathFailure, PathResult};
 
 /// Marks an Entity as being able to search paths
 #[derive(Component)]
@@ -11,6 +14,15 @@ use super::path_result::PathResult;
 pub struct Pather {
     cursor: usize,
     request: PathRequest,
+
+    /// Goal of the last request, kept to search again when the map changes
+    goal: Option<PathGoal>,
+
+    /// Seconds spent standing on a wait step
+    waited: f64,
+
+    /// Cells to head for in turn once the current goal is reached
+    waypoints: VecDeque<GridPosition>,
 }
 
 impl Pather {
@@ -18,13 +30,23 @@ impl Pather {
         Pather {
             cursor: 0,
             request: PathRequest::Nothing,
+            goal: None,
+            waited: 0.,
+            waypoints: VecDeque::new(),
         }
     }
 
     pub fn with_request(start: GridPosition, end: GridPosition) -> Pather {
+        Pather::with_goal(start, PathGoal::Cell(end))
+    }
+
+    pub fn with_goal(start: GridPosition, goal: PathGoal) -> Pather {
         Pather {
             cursor: 0,
-            request: PathRequest::Request(start, end),
+            goal: Some(goal.clone()),
+            request: PathRequest::Request(start, goal),
+            waited: 0.,
+            waypoints: VecDeque::new(),
         }
     }
 
@@ -55,13 +77,72 @@ impl Pather {
 
     #[inline(always)]
     pub fn set_request(&mut self, req: PathRequest) {
+        if let PathRequest::Request(_, ref goal) = req {
+            self.goal = Some(goal.clone());
+        }
+
+        self.cursor = 0;
+        self.waited = 0.;
         self.request = req;
     }
 
+    /// Goal the pather is, or was last, heading for
+    pub fn goal(&self) -> Option<&PathGoal> {
+        self.goal.as_ref()
+    }
+
+    /// Indicates whether the pather is stuck without a full path to its
+    /// goal, and could use another search when the way opens up.
+    pub fn is_waiting(&self) -> bool {
+        match self.request {
+            PathRequest::Failed(_) => true,
+            PathRequest::Ready(ref path_result) => path_result.is_partial(),
+            _ => false,
+        }
+    }
+
+    /// Requests a new search from the given position towards the last goal.
+    ///
+    /// Returns `false` when there is no goal to head for.
+    pub fn retry(&mut self, start: GridPosition) -> bool {
+        match self.goal.clone() {
+            Some(goal) => {
+                self.set_request(PathRequest::Request(start, goal));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sends the pather from its cell straight to the goal, dropping any
+    /// waypoints it had queued.
+    pub fn order(&mut self, start: GridPosition, goal: GridPosition) {
+        self.waypoints.clear();
+        self.set_request(PathRequest::Request(start, PathGoal::Cell(goal)));
+    }
+
+    /// Adds a cell to head for after the current goal. A pather with
+    /// nowhere to go, or whose last search failed, sets off for its next
+    /// waypoint straight away.
+    pub fn queue_waypoint(&mut self, start: GridPosition, waypoint: GridPosition) {
+        self.waypoints.push_back(waypoint);
+
+        if matches!(self.request, PathRequest::Failed(_) | PathRequest::Nothing) {
+            if let Some(next) = self.waypoints.pop_front() {
+                self.set_request(PathRequest::Request(start, PathGoal::Cell(next)));
+            }
+        }
+    }
+
+    pub fn waypoints(&self) -> &VecDeque<GridPosition> {
+        &self.waypoints
+    }
+
     pub fn next(&mut self) -> Option<&PathNode> {
         if let PathRequest::Ready(ref path_result) = self.request {
             if let Some(node) = path_result.path().and_then(|p| p.get(self.cursor)) {
                 self.cursor += 1;
+                self.waited = 0.;
                 Some(node)
             } else {
                 None
@@ -71,6 +152,18 @@ impl Pather {
         }
     }
 
+    /// Nodes of the path still ahead, starting with the current one
+    pub fn remaining(&self) -> &[PathNode] {
+        if let PathRequest::Ready(ref path_result) = self.request {
+            path_result
+                .path()
+                .and_then(|p| p.get(self.cursor..))
+                .unwrap_or(&[])
+        } else {
+            &[]
+        }
+    }
+
     pub fn current(&self) -> Option<&PathNode> {
         if let PathRequest::Ready(ref path_result) = self.request {
             path_result.path().and_then(|p| p.get(self.cursor))
@@ -79,15 +172,168 @@ impl Pather {
         }
     }
 
+    /// Indicates whether the current node keeps the pather in place, as
+    /// produced by cooperative searches that wait for others to pass.
+    pub fn is_wait_step(&self) -> bool {
+        if let PathRequest::Ready(ref path_result) = self.request {
+            if self.cursor == 0 {
+                return false;
+            }
+
+            path_result
+                .path()
+                .and_then(|p| match (p.get(self.cursor - 1), p.get(self.cursor)) {
+                    (Some(prev), Some(curr)) => Some(prev.pos == curr.pos),
+                    _ => None,
+                })
+                .unwrap_or(false)
+        } else {
+            false
+        }
+    }
+
+    /// Indicates whether the current node is the last one on the path.
+    pub fn is_last_step(&self) -> bool {
+        if let PathRequest::Ready(ref path_result) = self.request {
+            path_result
+                .path()
+                .map(|p| self.cursor + 1 == p.len())
+                .unwrap_or(false)
+        } else {
+            false
+        }
+    }
+
+    /// Accumulates time spent on a wait step.
+    ///
+    /// Returns `true` once the pather has waited for the given duration.
+    pub fn wait(&mut self, dt: f64, duration: f64) -> bool {
+        self.waited += dt;
+        self.waited >= duration
+    }
+
+    /// Ends a path the pather walked to the end.
+    ///
+    /// Partial paths leave the pather waiting to search again, while full
+    /// paths mean it arrived, and it sets off from where it stands towards
+    /// the next waypoint, if any.
+    pub fn finish(&mut self, pos: GridPosition) {
+        let failure = match self.request {
+            PathRequest::Ready(ref path_result) if path_result.is_partial() => {
+                path_result.failure()
+            }
+            _ => None,
+        };
+
+        if let Some(failure) = failure {
+            self.set_request(PathRequest::Failed(failure));
+        } else if let Some(waypoint) = self.waypoints.pop_front() {
+            self.set_request(PathRequest::Request(pos, PathGoal::Cell(waypoint)));
+        } else {
+            self.reset();
+        }
+    }
+
     pub fn reset(&mut self) {
         self.cursor = 0;
         self.request = PathRequest::Nothing;
+        self.goal = None;
+        self.waited = 0.;
+        self.waypoints.clear();
     }
 }
 
 pub enum PathRequest {
-    Request(GridPosition, GridPosition),
+    Request(GridPosition, PathGoal),
+    /// Search finished with a path to follow, which may only be partial
     Ready(PathResult),
-    Failed,
+    /// Search finished without any path to follow
+    Failed(PathFailure),
     Nothing,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_partial_path_waits_for_retry() {
+        let start = GridPosition::new(0, 0, 0);
+        let end = GridPosition::new(5, 0, 0);
+        let node = PathNode {
+            pos: GridPosition::new(2, 0, 0),
+            g: 20,
+            h: 30,
+            cost: 50,
+        };
+
+        let mut pather = Pather::with_request(start.clone(), end.clone());
+        pather.take_request();
+        pather.set_request(PathRequest::Ready(PathResult::with_partial(
+            1,
+            Duration::from_secs(0),
+            vec![node],
+            PathFailure::Blocked,
+        )));
+        assert!(pather.is_waiting());
+
+        // Walked to the end of the partial path
+        pather.next();
+        pather.finish(GridPosition::new(2, 0, 0));
+        assert!(pather.is_waiting());
+        assert_eq!(Some(&PathGoal::Cell(end.clone())), pather.goal());
+
+        assert!(pather.retry(GridPosition::new(2, 0, 0)));
+        assert!(pather.needs_path());
+
+        pather.reset();
+        assert!(!pather.retry(start));
+    }
+
+    #[test]
+    fn test_waypoints() {
+        let start = GridPosition::new(0, 0, 0);
+        let first = GridPosition::new(1, 0, 0);
+        let second = GridPosition::new(1, 1, 0);
+        let third = GridPosition::new(2, 2, 0);
+
+        // An idle pather sets off for the first waypoint straight away
+        let mut pather = Pather::new();
+        pather.queue_waypoint(start.clone(), first.clone());
+        pather.queue_waypoint(start.clone(), second.clone());
+        assert!(pather.needs_path());
+        assert_eq!(Some(&PathGoal::Cell(first.clone())), pather.goal());
+        assert_eq!(1, pather.waypoints().len());
+
+        // Arriving heads for the next one, from where the pather stands
+        pather.take_request();
+        pather.set_request(PathRequest::Ready(PathResult::with_cached(vec![])));
+        pather.finish(first.clone());
+        match pather.request() {
+            PathRequest::Request(from, goal) => {
+                assert_eq!(&first, from);
+                assert_eq!(&PathGoal::Cell(second.clone()), goal);
+            }
+            _ => panic!("Expected a request for the next waypoint"),
+        }
+        assert!(pather.waypoints().is_empty());
+
+        // A new order replaces the queue
+        pather.queue_waypoint(first.clone(), third.clone());
+        pather.order(first.clone(), third.clone());
+        assert!(pather.waypoints().is_empty());
+
+        pather.finish(third.clone());
+        assert_eq!(None, pather.goal());
+
+        // A failed order doesn't hold up the next waypoint
+        pather.order(third.clone(), first.clone());
+        pather.take_request();
+        pather.set_request(PathRequest::Failed(PathFailure::Blocked));
+        pather.queue_waypoint(third.clone(), second.clone());
+        assert!(pather.needs_path());
+        assert_eq!(Some(&PathGoal::Cell(second)), pather.goal());
+        assert!(pather.waypoints().is_empty());
+    }
+}
@@ -0,0 +1,632 @@
+//! Windowed Hierarchical Cooperative A*
+//!
+//! Searches through space and time, treating cells claimed in the
+//! `ReservationTable` as blocked at the ticks they are held. Each step
+//! takes as many ticks as the actor's `Pace` needs to walk it. Within the
+//! window an actor may also wait in place, which lets it step aside or
+//! hold back while another actor passes. Beyond the window the search
+//! collapses back to a plain spatial A*.
+//!
+//! Actors are planned one after the other in a fixed priority order, so
+//! conflicts such as two actors meeting head-on in a narrow tunnel are
+//! resolved the same way every time.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::time;
+
+use super::cost::*;
+use super::goal::PathGoal;
+use super::landmarks::Landmarks;
+use super::locomotion::*;
+use super::path_node::*;
+use super::path_result::{PathFailure, PathResult, SearchOptions};
+use super::reservation::{AgentId, Pace, ReservationTable, Tick};
+use crate::grid::{Grid, GridPosition};
+
+/// Cost of standing still for one tick
+pub const WAIT_COST: u32 = 10;
+
+type SpaceTimeKey = (GridPosition, Tick);
+
+pub struct CooperativeAStar {
+    /// Number of ticks ahead for which reservations are respected
+    window: Tick,
+
+    /// Number of ticks an actor keeps its goal claimed after arriving
+    hold: Tick,
+
+    options: SearchOptions,
+}
+
+impl CooperativeAStar {
+    pub fn new() -> Self {
+        CooperativeAStar::with_window(16)
+    }
+
+    pub fn with_window(window: Tick) -> Self {
+        CooperativeAStar::with_options(window, SearchOptions::default())
+    }
+
+    pub fn with_options(window: Tick, options: SearchOptions) -> Self {
+        CooperativeAStar {
+            window,
+            hold: window,
+            options,
+        }
+    }
+
+    #[inline(always)]
+    pub fn options(&self) -> &SearchOptions {
+        &self.options
+    }
+
+    #[inline(always)]
+    pub fn window(&self) -> Tick {
+        self.window
+    }
+
+    #[inline(always)]
+    pub fn hold(&self) -> Tick {
+        self.hold
+    }
+
+    /// Searches for a path to the goal that avoids the cells reserved by
+    /// other agents.
+    ///
+    /// The resulting path has one node per step, starting at the table's
+    /// current tick, and each step takes the ticks the pace gives it.
+    /// Consecutive nodes with the same position are wait steps. The table
+    /// is not modified; see `ReservationTable::reserve_path`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn find_path<C, L>(
+        &self,
+        grid: &Grid,
+        locomotion: &Locomotion,
+        start: &GridPosition,
+        goal: &PathGoal,
+        agent: AgentId,
+        pace: &Pace,
+        reservations: &ReservationTable,
+        cost_strat: &C,
+        loco_strat: &L,
+    ) -> PathResult
+    where
+        C: CostStrategy,
+        L: LocomotionStrategy,
+    {
+        self.search(
+            grid,
+            locomotion,
+            start,
+            goal,
+            agent,
+            pace,
+            reservations,
+            |pos| goal.heuristic(pos, cost_strat),
+            cost_strat,
+            loco_strat,
+        )
+    }
+
+    /// Searches like `find_path`, guided by landmark estimates towards
+    /// single cell goals.
+    #[allow(clippy::too_many_arguments)]
+    pub fn find_path_with_landmarks<C, L>(
+        &self,
+        grid: &Grid,
+        locomotion: &Locomotion,
+        start: &GridPosition,
+        goal: &PathGoal,
+        agent: AgentId,
+        pace: &Pace,
+        reservations: &ReservationTable,
+        landmarks: &Landmarks,
+        cost_strat: &C,
+        loco_strat: &L,
+    ) -> PathResult
+    where
+        C: CostStrategy,
+        L: LocomotionStrategy,
+    {
+        self.search(
+            grid,
+            locomotion,
+            start,
+            goal,
+            agent,
+            pace,
+            reservations,
+            |pos| match goal {
+                PathGoal::Cell(end) => landmarks
+                    .heuristic(pos, end)
+                    .max(cost_strat.estimate(pos, end)),
+                _ => goal.heuristic(pos, cost_strat),
+            },
+            cost_strat,
+            loco_strat,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn search<C, L, H>(
+        &self,
+        grid: &Grid,
+        locomotion: &Locomotion,
+        start: &GridPosition,
+        goal: &PathGoal,
+        agent: AgentId,
+        pace: &Pace,
+        reservations: &ReservationTable,
+        heuristic: H,
+        cost_strat: &C,
+        loco_strat: &L,
+    ) -> PathResult
+    where
+        C: CostStrategy,
+        L: LocomotionStrategy,
+        H: Fn(&GridPosition) -> u32,
+    {
+        let mut iter_count = 0;
+        let start_time = time::Instant::now();
+        let now = reservations.now();
+
+        if !grid.in_bounds(start) || !goal.in_bounds(grid) {
+            return PathResult::with_failure(0, start_time.elapsed(), PathFailure::OutOfBounds);
+        }
+
+        // Closest explored node to the goal, for partial paths
+        let mut closest: (u32, u32, SpaceTimeKey) = (u32::MAX, u32::MAX, (start.clone(), 0));
+        let mut failure = PathFailure::Blocked;
+
+        let mut nodes: HashMap<SpaceTimeKey, (PathNode, Option<SpaceTimeKey>)> = HashMap::new();
+        let mut open: BinaryHeap<SpaceTimeNode> = BinaryHeap::new();
+        let mut close: HashSet<SpaceTimeKey> = HashSet::new();
+
+        let start_h = heuristic(start);
+        relax(&mut nodes, &mut open, (start.clone(), 0), None, 0, start_h);
+
+        while let Some(SpaceTimeNode(node_pos, t, _)) = open.pop() {
+            if !close.insert((node_pos.clone(), t)) {
+                // Already expanded via a cheaper route
+                continue;
+            }
+            iter_count += 1;
+
+            let (node_g, node_h) = {
+                let node = &nodes[&(node_pos.clone(), t)].0;
+                (node.g, node.h)
+            };
+
+            if (node_h, node_g) < (closest.0, closest.1) {
+                closest = (node_h, node_g, (node_pos.clone(), t));
+            }
+
+            if goal.is_goal(&node_pos)
+                && self.can_rest(agent, &node_pos, now + t, now, reservations)
+            {
+                return PathResult::with_stats(
+                    iter_count,
+                    time::Instant::now().duration_since(start_time),
+                    carve_path(&mut nodes, (node_pos, t)),
+                );
+            }
+
+            if let Some(exceeded) = self.options.exceeded(iter_count, &start_time) {
+                failure = exceeded;
+                break;
+            }
+
+            // Time stands still beyond the window, where reservations no longer apply
+            let in_window = t < self.window;
+            let arrival = |to: &GridPosition| {
+                if in_window {
+                    t + pace.step(&node_pos, to)
+                } else {
+                    t
+                }
+            };
+
+            // The actor holds its cell until it arrives on the next one
+            let can_leave = |arrival: Tick| {
+                (t + 1..arrival).all(|tick| !reservations.is_reserved(agent, &node_pos, now + tick))
+            };
+
+            let wait_t = arrival(&node_pos);
+            if in_window
+                && can_leave(wait_t)
+                && !reservations.is_reserved(agent, &node_pos, now + wait_t)
+            {
+                let h = heuristic(&node_pos);
+                relax(
+                    &mut nodes,
+                    &mut open,
+                    (node_pos.clone(), wait_t),
+                    Some((node_pos.clone(), t)),
+                    node_g + WAIT_COST,
+                    h,
+                );
+            }
+
+            let neighbours = grid.neighbours_3d(&node_pos);
+            for neigh_pos in neighbours
+                .iter()
+                .filter_map(|maybe_neigh| maybe_neigh.as_ref())
+            {
+                let next_t = arrival(neigh_pos);
+                if close.contains(&(neigh_pos.clone(), next_t)) {
+                    continue;
+                }
+
+                let cost = cost_strat.is_passable(&node_pos, neigh_pos);
+                if cost == Cost::Blocked {
+                    continue;
+                }
+
+                if !loco_strat.is_passable(locomotion, &node_pos, neigh_pos) {
+                    continue;
+                }
+
+                if in_window
+                    && (reservations.is_reserved(agent, neigh_pos, now + next_t)
+                        || reservations.is_swap(agent, &node_pos, neigh_pos, now + t)
+                        || !can_leave(next_t))
+                {
+                    continue;
+                }
+
+                let g = node_g + cost.passable().unwrap();
+                let h = heuristic(neigh_pos);
+                relax(
+                    &mut nodes,
+                    &mut open,
+                    (neigh_pos.clone(), next_t),
+                    Some((node_pos.clone(), t)),
+                    g,
+                    h,
+                );
+            }
+        }
+
+        let (_, _, closest_key) = closest;
+        if self.options.allow_partial && &closest_key.0 != start {
+            return PathResult::with_partial(
+                iter_count,
+                time::Instant::now().duration_since(start_time),
+                carve_path(&mut nodes, closest_key),
+                failure,
+            );
+        }
+
+        PathResult::with_failure(
+            iter_count,
+            time::Instant::now().duration_since(start_time),
+            failure,
+        )
+    }
+
+    /// Checks a path found earlier, such as a cached one, against the
+    /// current reservations, under the same rules the search plans by.
+    ///
+    /// The path is walked from the table's current tick at the given pace.
+    pub fn is_path_free(
+        &self,
+        path: &[PathNode],
+        agent: AgentId,
+        pace: &Pace,
+        reservations: &ReservationTable,
+    ) -> bool {
+        let now = reservations.now();
+        let mut t = 0;
+
+        for step in path.windows(2) {
+            let (node, next) = (&step[0].pos, &step[1].pos);
+            if t >= self.window {
+                // Reservations no longer apply
+                return true;
+            }
+
+            let next_t = t + pace.step(node, next);
+            let blocked = reservations.is_reserved(agent, next, now + next_t)
+                || reservations.is_swap(agent, node, next, now + t)
+                || (t + 1..next_t).any(|tick| reservations.is_reserved(agent, node, now + tick));
+            if blocked {
+                return false;
+            }
+            t = next_t;
+        }
+
+        match path.last() {
+            Some(last) => self.can_rest(agent, &last.pos, now + t, now, reservations),
+            None => false,
+        }
+    }
+
+    /// An agent may only finish on its goal if nobody else needs to pass
+    /// through it for the remainder of the window.
+    fn can_rest(
+        &self,
+        agent: AgentId,
+        goal: &GridPosition,
+        arrival: Tick,
+        now: Tick,
+        reservations: &ReservationTable,
+    ) -> bool {
+        (arrival..=now + self.window).all(|tick| !reservations.is_reserved(agent, goal, tick))
+    }
+}
+
+impl Default for CooperativeAStar {
+    fn default() -> Self {
+        CooperativeAStar::new()
+    }
+}
+
+/// Records a node if it is new, or reached more cheaply than before.
+fn relax(
+    nodes: &mut HashMap<SpaceTimeKey, (PathNode, Option<SpaceTimeKey>)>,
+    open: &mut BinaryHeap<SpaceTimeNode>,
+    key: SpaceTimeKey,
+    parent: Option<SpaceTimeKey>,
+    g: u32,
+    h: u32,
+) {
+    if let Some((known, _)) = nodes.get(&key) {
+        if known.g <= g {
+            return;
+        }
+    }
+
+    open.push(SpaceTimeNode(key.0.clone(), key.1, g + h));
+    let node = PathNode {
+        pos: key.0.clone(),
+        g,
+        h,
+        cost: g + h,
+    };
+    nodes.insert(key, (node, parent));
+}
+
+fn carve_path(
+    nodes: &mut HashMap<SpaceTimeKey, (PathNode, Option<SpaceTimeKey>)>,
+    end: SpaceTimeKey,
+) -> Vec<PathNode> {
+    let mut next_key = end;
+    let mut result = Vec::<PathNode>::new();
+
+    'walk: while let Some((node, maybe_parent)) = nodes.remove(&next_key) {
+        result.push(node);
+        match maybe_parent {
+            Some(parent_key) => next_key = parent_key,
+            None => break 'walk,
+        }
+    }
+
+    // Tracing is backwards, from end to start
+    result.reverse();
+    result
+}
+
+/// Wrapper for a space-time position and cost to allow for min-heap compare
+#[derive(Eq, PartialEq)]
+struct SpaceTimeNode(GridPosition, Tick, u32);
+
+impl Ord for SpaceTimeNode {
+    fn cmp(&self, other: &SpaceTimeNode) -> Ordering {
+        // Note that this is backwards to allow for a min-heap
+        other.2.cmp(&self.2)
+    }
+}
+
+impl PartialOrd for SpaceTimeNode {
+    fn partial_cmp(&self, other: &SpaceTimeNode) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pathfinding::{NoOpLocomotion, TilemapCost, GO_ANYWHERE};
+    use crate::tilemap::{Tile, Tilemap};
+
+    /// One wide tunnel along the x axis, with a single pocket to the side
+    struct TunnelCost;
+
+    impl CostStrategy for TunnelCost {
+        fn is_passable(&self, _source: &GridPosition, target: &GridPosition) -> Cost {
+            if target.y() == 0 || target.x() == 3 {
+                Cost::Passable(10)
+            } else {
+                Cost::Blocked
+            }
+        }
+    }
+
+    fn plan(
+        pathfinder: &CooperativeAStar,
+        table: &mut ReservationTable,
+        agent: AgentId,
+        start: GridPosition,
+        end: GridPosition,
+    ) -> Vec<GridPosition> {
+        let grid = Grid::with_size(8, 2, 1);
+        let mut result = pathfinder.find_path(
+            &grid,
+            &Locomotion::new(&[GO_ANYWHERE]),
+            &start,
+            &PathGoal::Cell(end),
+            agent,
+            &Pace::default(),
+            table,
+            &TunnelCost,
+            &NoOpLocomotion,
+        );
+        let path = result.take_path().expect("Path not found");
+        table.reserve_path(
+            agent,
+            &path,
+            table.now(),
+            pathfinder.hold(),
+            &Pace::default(),
+        );
+        path.into_iter().map(|node| node.pos).collect()
+    }
+
+    fn at(path: &[GridPosition], tick: usize) -> &GridPosition {
+        path.get(tick).unwrap_or_else(|| path.last().unwrap())
+    }
+
+    #[test]
+    fn test_head_on_in_tunnel() {
+        let pathfinder = CooperativeAStar::new();
+        let mut table = ReservationTable::default();
+
+        let east = plan(
+            &pathfinder,
+            &mut table,
+            1,
+            GridPosition::new(0, 0, 0),
+            GridPosition::new(7, 0, 0),
+        );
+        let west = plan(
+            &pathfinder,
+            &mut table,
+            2,
+            GridPosition::new(7, 0, 0),
+            GridPosition::new(0, 0, 0),
+        );
+
+        assert_eq!(&GridPosition::new(7, 0, 0), east.last().unwrap());
+        assert_eq!(&GridPosition::new(0, 0, 0), west.last().unwrap());
+
+        // Lower priority actor has to step into the pocket
+        assert!(west.contains(&GridPosition::new(3, 1, 0)));
+
+        let ticks = ::std::cmp::max(east.len(), west.len());
+        for tick in 0..ticks {
+            assert_ne!(at(&east, tick), at(&west, tick), "Collision at {}", tick);
+
+            let swapped =
+                at(&east, tick) == at(&west, tick + 1) && at(&west, tick) == at(&east, tick + 1);
+            assert!(!swapped, "Actors walked through each other at {}", tick);
+        }
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let pathfinder = CooperativeAStar::new();
+        let mut runs = vec![];
+
+        for _ in 0..2 {
+            let mut table = ReservationTable::default();
+            plan(
+                &pathfinder,
+                &mut table,
+                1,
+                GridPosition::new(0, 0, 0),
+                GridPosition::new(7, 0, 0),
+            );
+            runs.push(plan(
+                &pathfinder,
+                &mut table,
+                2,
+                GridPosition::new(7, 0, 0),
+                GridPosition::new(0, 0, 0),
+            ));
+        }
+
+        assert_eq!(runs[0], runs[1]);
+    }
+
+    #[test]
+    fn test_path_free_of_reservations() {
+        let pathfinder = CooperativeAStar::new();
+        let mut table = ReservationTable::default();
+        let grid = Grid::with_size(8, 2, 1);
+        let path = pathfinder
+            .find_path(
+                &grid,
+                &Locomotion::new(&[GO_ANYWHERE]),
+                &GridPosition::new(0, 0, 0),
+                &PathGoal::Cell(GridPosition::new(7, 0, 0)),
+                1,
+                &Pace::default(),
+                &table,
+                &TunnelCost,
+                &NoOpLocomotion,
+            )
+            .take_path()
+            .expect("Path not found");
+        assert!(pathfinder.is_path_free(&path, 1, &Pace::default(), &table));
+
+        // Someone else standing in the way at the wrong time
+        table.reserve(2, &GridPosition::new(4, 0, 0), 4);
+        assert!(!pathfinder.is_path_free(&path, 1, &Pace::default(), &table));
+        assert!(pathfinder.is_path_free(&path, 2, &Pace::default(), &table));
+    }
+
+    #[test]
+    fn test_goal_out_of_bounds() {
+        let result = CooperativeAStar::new().find_path(
+            &Grid::with_size(8, 2, 1),
+            &Locomotion::new(&[GO_ANYWHERE]),
+            &GridPosition::new(0, 0, 0),
+            &PathGoal::Cell(GridPosition::new(9, 0, 0)),
+            1,
+            &Pace::default(),
+            &ReservationTable::default(),
+            &TunnelCost,
+            &NoOpLocomotion,
+        );
+        assert_eq!(Some(PathFailure::OutOfBounds), result.failure());
+    }
+
+    #[test]
+    fn test_landmarks_guide_search() {
+        // Winding cave: walls at x = 2 and x = 5 with gaps at opposite ends
+        let grid = Grid::with_size(8, 8, 1);
+        let mut tilemap = Tilemap::with_size(8, 8, 1);
+        for y in 0..7 {
+            tilemap.set_tile(&GridPosition::new(2, y + 1, 0), Tile::GreyBlock);
+            tilemap.set_tile(&GridPosition::new(5, y, 0), Tile::GreyBlock);
+        }
+
+        let locomotion = Locomotion::new(&[GO_ANYWHERE]);
+        let cost_strat = TilemapCost::new(&tilemap);
+        let picked = Landmarks::select(&grid, 4, |pos| tilemap.is_passable(pos));
+        let landmarks = Landmarks::build(&grid, &locomotion, picked, &cost_strat, &NoOpLocomotion);
+
+        let pathfinder = CooperativeAStar::new();
+        let table = ReservationTable::default();
+        let start = GridPosition::new(0, 7, 0);
+        let goal = PathGoal::Cell(GridPosition::new(7, 7, 0));
+        let plain = pathfinder.find_path(
+            &grid,
+            &locomotion,
+            &start,
+            &goal,
+            1,
+            &Pace::default(),
+            &table,
+            &cost_strat,
+            &NoOpLocomotion,
+        );
+        let guided = pathfinder.find_path_with_landmarks(
+            &grid,
+            &locomotion,
+            &start,
+            &goal,
+            1,
+            &Pace::default(),
+            &table,
+            &landmarks,
+            &cost_strat,
+            &NoOpLocomotion,
+        );
+
+        let cost = |result: &PathResult| result.path().unwrap().last().unwrap().g;
+        assert_eq!(cost(&plain), cost(&guided));
+        assert!(guided.iter_count() < plain.iter_count());
+    }
+}
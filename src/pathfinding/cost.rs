@@ -1,5 +1,7 @@
 use crate::grid::GridPosition;
 
+use super::distance::chebyshev;
+
 #[derive(Eq, PartialEq)]
 pub enum Cost {
     Passable(u32),
@@ -23,4 +25,12 @@ pub trait CostStrategy {
     /// Indicates whether the pather can travel from the source position to
     /// the target position, and the expected cost of the movement.
     fn is_passable(&self, source: &GridPosition, target: &GridPosition) -> Cost;
+
+    /// Lower bound on the cost of travelling between two positions, to guide
+    /// searches towards their goal. It must never overestimate.
+    ///
+    /// Defaults to 10 per step, the cheapest move any strategy charges.
+    fn estimate(&self, source: &GridPosition, target: &GridPosition) -> u32 {
+        chebyshev(source, target) * 10
+    }
 }
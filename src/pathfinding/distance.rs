@@ -6,7 +6,53 @@ pub fn manhatten(a: &GridPosition, b: &GridPosition) -> u32 {
     ((a.x() - b.x()).abs() + (a.y() - b.y()).abs() + (a.z() - b.z()).abs()) as u32
 }
 
+/// Fewest steps between two cells, when every one of the 26 cells around
+/// a cell is a single step away.
+pub fn chebyshev(a: &GridPosition, b: &GridPosition) -> u32 {
+    let x = (a.x() - b.x()).abs();
+    let y = (a.y() - b.y()).abs();
+    let z = (a.z() - b.z()).abs();
+    x.max(y).max(z) as u32
+}
+
+/// Cheapest walk between two cells when straight and vertical steps cost 10
+/// and horizontal diagonals cost 14, as on the tilemap.
+///
+/// Climbing comes free with horizontal steps, so it only adds to the cost
+/// when the climb is longer than the walk, and then trading diagonals for
+/// straight steps makes room for it.
+pub fn octile(a: &GridPosition, b: &GridPosition) -> u32 {
+    let x = (a.x() - b.x()).unsigned_abs();
+    let y = (a.y() - b.y()).unsigned_abs();
+    let z = (a.z() - b.z()).unsigned_abs();
+    let (lo, hi) = (x.min(y), x.max(y));
+
+    let diagonals = (hi + lo).saturating_sub(z).min(lo);
+    let steps = (hi + lo - diagonals).max(z);
+    steps * 10 + diagonals * 4
+}
+
 pub fn euler(a: &GridPosition, b: &GridPosition) -> u32 {
     let x = ((a.x() - b.x()).pow(2) + (a.y() - b.y()).pow(2) + (a.z() - b.z()).pow(2)) as f64;
     x.sqrt().floor() as u32
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_octile() {
+        let a = GridPosition::new(0, 0, 0);
+
+        assert_eq!(0, octile(&a, &a));
+        assert_eq!(14 * 3 + 10 * 2, octile(&a, &GridPosition::new(5, 3, 0)));
+        // Climbing alongside a walk is free
+        assert_eq!(14 * 3 + 10 * 2, octile(&a, &GridPosition::new(5, 3, 4)));
+        // Longer climbs trade diagonals for straight steps
+        assert_eq!(10 * 4 + 14 * 2, octile(&a, &GridPosition::new(5, 3, 6)));
+        assert_eq!(10 * 9, octile(&a, &GridPosition::new(-5, 3, 9)));
+
+        assert_eq!(9, chebyshev(&a, &GridPosition::new(-5, 3, 9)));
+    }
+}
@@ -0,0 +1,153 @@
+//! Pathing rules for doors that only let some actors through
+
+use specs::prelude::*;
+
+use super::cost::*;
+use crate::grid::GridPosition;
+use crate::tilemap::{DoorAccess, DoorState, FactionId, KeyId, Tilemap};
+
+/// Extra cost of stopping to open a closed door
+pub const DOOR_OPEN_COST: u32 = 20;
+
+/// Which doors an entity is allowed through
+#[derive(Component, Clone, Debug, Default, Eq, PartialEq, Hash)]
+#[storage(DenseVecStorage)]
+pub struct Credentials {
+    faction: Option<FactionId>,
+
+    /// Kept sorted, so equal credentials compare equal
+    keys: Vec<KeyId>,
+}
+
+impl Credentials {
+    pub fn new(faction: Option<FactionId>, keys: &[KeyId]) -> Self {
+        let mut keys = keys.to_vec();
+        keys.sort();
+        keys.dedup();
+
+        Credentials { faction, keys }
+    }
+
+    pub fn faction(&self) -> Option<FactionId> {
+        self.faction
+    }
+
+    pub fn has_key(&self, key: KeyId) -> bool {
+        self.keys.binary_search(&key).is_ok()
+    }
+
+    pub fn add_key(&mut self, key: KeyId) {
+        if let Err(index) = self.keys.binary_search(&key) {
+            self.keys.insert(index, key);
+        }
+    }
+
+    /// Indicates whether the credentials grant passage through the door.
+    pub fn allows(&self, access: &DoorAccess) -> bool {
+        match access {
+            DoorAccess::Anyone => true,
+            DoorAccess::Faction(faction) => self.faction == Some(*faction),
+            DoorAccess::Key(key) => self.has_key(*key),
+        }
+    }
+}
+
+/// Wraps a cost strategy to apply the door rules of the tilemap
+///
+/// Locked doors are blocked unless the credentials allow passage, and
+/// closed doors cost extra to open. Pathers without credentials may only
+/// use doors open to anyone.
+pub struct DoorCost<'a, C> {
+    tilemap: &'a Tilemap,
+    inner: &'a C,
+    credentials: Option<&'a Credentials>,
+}
+
+impl<'a, C> DoorCost<'a, C> {
+    pub fn new(tilemap: &'a Tilemap, inner: &'a C, credentials: Option<&'a Credentials>) -> Self {
+        DoorCost {
+            tilemap,
+            inner,
+            credentials,
+        }
+    }
+}
+
+impl<'a, C: CostStrategy> CostStrategy for DoorCost<'a, C> {
+    #[inline(always)]
+    fn is_passable(&self, source: &GridPosition, target: &GridPosition) -> Cost {
+        let cost = self.inner.is_passable(source, target);
+
+        let door = match self.tilemap.door(target) {
+            Some(door) => door,
+            None => return cost,
+        };
+
+        let allowed = match self.credentials {
+            Some(credentials) => credentials.allows(&door.access),
+            None => door.access == DoorAccess::Anyone,
+        };
+
+        match (door.state, cost) {
+            (DoorState::Open, cost) => cost,
+            (_, Cost::Blocked) => Cost::Blocked,
+            (DoorState::Closed, Cost::Passable(cost)) => Cost::Passable(cost + DOOR_OPEN_COST),
+            (DoorState::Locked, Cost::Passable(cost)) if allowed => {
+                Cost::Passable(cost + DOOR_OPEN_COST)
+            }
+            (DoorState::Locked, _) => Cost::Blocked,
+        }
+    }
+
+    /// Doors only ever add to the cost
+    #[inline(always)]
+    fn estimate(&self, source: &GridPosition, target: &GridPosition) -> u32 {
+        self.inner.estimate(source, target)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pathfinding::NoOpCost;
+    use crate::tilemap::Door;
+
+    #[test]
+    fn test_locked_door() {
+        let from = GridPosition::new(0, 0, 0);
+        let door_pos = GridPosition::new(1, 0, 0);
+        let mut tilemap = Tilemap::with_size(4, 1, 1);
+        tilemap.set_door(
+            &door_pos,
+            Door::new(DoorState::Locked, DoorAccess::Faction(7)),
+        );
+
+        let member = Credentials::new(Some(7), &[]);
+        let stranger = Credentials::new(Some(2), &[3]);
+
+        let cost = DoorCost::new(&tilemap, &NoOpCost, Some(&member));
+        assert!(cost.is_passable(&from, &door_pos) == Cost::Passable(10 + DOOR_OPEN_COST));
+
+        let cost = DoorCost::new(&tilemap, &NoOpCost, Some(&stranger));
+        assert!(cost.is_passable(&from, &door_pos) == Cost::Blocked);
+
+        let cost = DoorCost::new(&tilemap, &NoOpCost, None);
+        assert!(cost.is_passable(&from, &door_pos) == Cost::Blocked);
+
+        tilemap.set_door_state(&door_pos, DoorState::Open);
+        let cost = DoorCost::new(&tilemap, &NoOpCost, Some(&stranger));
+        assert!(cost.is_passable(&from, &door_pos) == Cost::Passable(10));
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut credentials = Credentials::new(None, &[4, 1, 4]);
+        assert!(credentials.allows(&DoorAccess::Key(1)));
+        assert!(!credentials.allows(&DoorAccess::Key(2)));
+        assert!(!credentials.allows(&DoorAccess::Faction(0)));
+
+        credentials.add_key(2);
+        assert!(credentials.allows(&DoorAccess::Key(2)));
+        assert_eq!(Credentials::new(None, &[1, 2, 4]), credentials);
+    }
+}
@@ -0,0 +1,101 @@
+use crate::grid::{Grid, GridAabb, GridPosition};
+
+use super::cost::CostStrategy;
+
+/// Where a path search should end up
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum PathGoal {
+    /// Reach one specific cell
+    Cell(GridPosition),
+
+    /// Reach whichever of the cells is cheapest to get to
+    AnyOf(Vec<GridPosition>),
+
+    /// Stand in any cell touching the given one, without entering it,
+    /// for example to dig it out
+    AdjacentTo(GridPosition),
+}
+
+impl PathGoal {
+    /// Indicates whether standing on the position satisfies the goal
+    pub fn is_goal(&self, pos: &GridPosition) -> bool {
+        use PathGoal::*;
+
+        match self {
+            Cell(cell) => cell == pos,
+            AnyOf(cells) => cells.contains(pos),
+            AdjacentTo(cell) => cell != pos && GridAabb::around(cell, 1).contains(pos),
+        }
+    }
+
+    /// Indicates whether any goal cell lies on the grid. Searches for goals
+    /// off the grid fail as out of bounds, without exploring anything.
+    pub fn in_bounds(&self, grid: &Grid) -> bool {
+        use PathGoal::*;
+
+        match self {
+            Cell(cell) => grid.in_bounds(cell),
+            AnyOf(cells) => cells.iter().any(|cell| grid.in_bounds(cell)),
+            AdjacentTo(cell) => GridAabb::around(cell, 1)
+                .iter()
+                .any(|around| &around != cell && grid.in_bounds(&around)),
+        }
+    }
+
+    /// Estimated cost from the position to the closest goal cell, never
+    /// more than the cost strategy would charge.
+    pub fn heuristic<C: CostStrategy>(&self, pos: &GridPosition, cost_strat: &C) -> u32 {
+        use PathGoal::*;
+
+        match self {
+            Cell(cell) => cost_strat.estimate(pos, cell),
+            AnyOf(cells) => cells
+                .iter()
+                .map(|cell| cost_strat.estimate(pos, cell))
+                .min()
+                .unwrap_or(0),
+            AdjacentTo(cell) => GridAabb::around(cell, 1)
+                .iter()
+                .filter(|around| around != cell)
+                .map(|around| cost_strat.estimate(pos, &around))
+                .min()
+                .unwrap_or(0),
+        }
+    }
+}
+
+impl From<GridPosition> for PathGoal {
+    fn from(cell: GridPosition) -> PathGoal {
+        PathGoal::Cell(cell)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pathfinding::NoOpCost;
+
+    #[test]
+    fn test_adjacent_goal() {
+        let goal = PathGoal::AdjacentTo(GridPosition::new(2, 2, 2));
+
+        assert!(goal.is_goal(&GridPosition::new(1, 2, 2)));
+        assert!(goal.is_goal(&GridPosition::new(3, 3, 1)));
+        assert!(!goal.is_goal(&GridPosition::new(2, 2, 2)));
+        assert!(!goal.is_goal(&GridPosition::new(0, 2, 2)));
+        assert_eq!(0, goal.heuristic(&GridPosition::new(1, 2, 2), &NoOpCost));
+    }
+
+    #[test]
+    fn test_any_of_heuristic() {
+        let goal = PathGoal::AnyOf(vec![GridPosition::new(9, 0, 0), GridPosition::new(3, 0, 0)]);
+
+        assert_eq!(30, goal.heuristic(&GridPosition::new(0, 0, 0), &NoOpCost));
+        assert!(goal.is_goal(&GridPosition::new(9, 0, 0)));
+        assert!(!goal.is_goal(&GridPosition::new(4, 0, 0)));
+        assert_eq!(
+            0,
+            PathGoal::AnyOf(vec![]).heuristic(&GridPosition::new(0, 0, 0), &NoOpCost)
+        );
+    }
+}
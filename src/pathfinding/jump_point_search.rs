@@ -1,5 +1,6 @@
 use crate::grid::{Grid, GridPosition};
 
+use super::astar::AStar;
 use super::cost::*;
 use super::locomotion::*;
 use super::path_result::PathResult;
@@ -23,4 +24,27 @@ impl Pathfinder for JumpPointSearch {
     {
         unimplemented!()
     }
+
+    /// Jump points are pruned towards a single known goal, which a goal
+    /// predicate doesn't give, so these searches fall back to plain A*.
+    fn find_path_where<C, L, G, H>(
+        &self,
+        grid: &Grid,
+        locomotion: &Locomotion,
+        start: &GridPosition,
+        is_goal: G,
+        heuristic: H,
+        cost_strat: &C,
+        loco_strat: &L,
+    ) -> PathResult
+    where
+        C: CostStrategy,
+        L: LocomotionStrategy,
+        G: Fn(&GridPosition) -> bool,
+        H: Fn(&GridPosition) -> u32,
+    {
+        AStar::new().find_path_where(
+            grid, locomotion, start, is_goal, heuristic, cost_strat, loco_strat,
+        )
+    }
 }
@@ -0,0 +1,384 @@
+//! Landmark heuristic (ALT)
+//!
+//! A handful of landmark cells get the exact travel cost to and from every
+//! other cell precomputed. By the triangle inequality, the difference of
+//! two cells' costs to a landmark never exceeds the cost between the cells,
+//! which makes for a far tighter heuristic than straight line distance in
+//! winding caves.
+
+use std::collections::{BinaryHeap, HashMap};
+use std::mem;
+
+use na::Vector3;
+
+use super::cost::*;
+use super::distance::*;
+use super::locomotion::*;
+use super::path_node::PathNodePos;
+use super::tilemap::{TilemapCost, TilemapLocomotion};
+use crate::grid::{grid_index, Grid, GridPosition};
+use crate::tilemap::Tilemap;
+
+/// Marks cells that can't be reached at all
+const UNREACHABLE: u32 = u32::MAX;
+
+/// Precomputed travel costs between landmarks and every cell, for one
+/// locomotion class.
+pub struct Landmarks {
+    size: Vector3<u32>,
+    landmarks: Vec<GridPosition>,
+
+    /// Cost from each landmark to every cell
+    from: Vec<Vec<u32>>,
+
+    /// Cost from every cell to each landmark, which differs from `from`
+    /// when some moves only go one way
+    to: Vec<Vec<u32>>,
+}
+
+impl Landmarks {
+    /// Computes the cost fields of the given landmarks.
+    pub fn build<C, L>(
+        grid: &Grid,
+        locomotion: &Locomotion,
+        landmarks: Vec<GridPosition>,
+        cost_strat: &C,
+        loco_strat: &L,
+    ) -> Self
+    where
+        C: CostStrategy,
+        L: LocomotionStrategy,
+    {
+        let (x, y, z) = grid.size();
+        let size = Vector3::new(x, y, z);
+
+        let from = landmarks
+            .iter()
+            .map(|l| cost_field(grid, locomotion, l, false, cost_strat, loco_strat))
+            .collect();
+        let to = landmarks
+            .iter()
+            .map(|l| cost_field(grid, locomotion, l, true, cost_strat, loco_strat))
+            .collect();
+
+        Landmarks {
+            size,
+            landmarks,
+            from,
+            to,
+        }
+    }
+
+    /// Picks up to `count` landmarks spread across the grid.
+    ///
+    /// Each landmark is the candidate cell furthest from those picked
+    /// before, which puts them around the edges of the map where they give
+    /// the best estimates.
+    pub fn select<F>(grid: &Grid, count: usize, is_candidate: F) -> Vec<GridPosition>
+    where
+        F: Fn(&GridPosition) -> bool,
+    {
+        let candidates = grid
+            .aabb()
+            .iter()
+            .filter(|pos| is_candidate(pos))
+            .collect::<Vec<_>>();
+
+        let (x, y, z) = grid.size();
+        let center = GridPosition::new(x as i32 / 2, y as i32 / 2, z as i32 / 2);
+        let mut picked: Vec<GridPosition> = vec![];
+
+        while picked.len() < count {
+            let furthest = candidates
+                .iter()
+                .filter(|pos| !picked.contains(pos))
+                .max_by_key(|pos| {
+                    if picked.is_empty() {
+                        euler(pos, &center)
+                    } else {
+                        picked.iter().map(|p| euler(pos, p)).min().unwrap_or(0)
+                    }
+                })
+                .cloned();
+
+            match furthest {
+                Some(pos) => picked.push(pos),
+                None => break,
+            }
+        }
+
+        picked
+    }
+
+    /// Lower bound of the cost from one cell to another
+    pub fn heuristic(&self, pos: &GridPosition, goal: &GridPosition) -> u32 {
+        let pos_index = grid_index(&self.size, pos);
+        let goal_index = grid_index(&self.size, goal);
+
+        let mut best = 0;
+        for (from, to) in self.from.iter().zip(self.to.iter()) {
+            // d(L, goal) - d(L, pos) <= d(pos, goal)
+            let (l_pos, l_goal) = (from[pos_index], from[goal_index]);
+            if l_pos != UNREACHABLE && l_goal != UNREACHABLE {
+                best = best.max(l_goal.saturating_sub(l_pos));
+            }
+
+            // d(pos, L) - d(goal, L) <= d(pos, goal)
+            let (pos_l, goal_l) = (to[pos_index], to[goal_index]);
+            if pos_l != UNREACHABLE && goal_l != UNREACHABLE {
+                best = best.max(pos_l.saturating_sub(goal_l));
+            }
+        }
+
+        best
+    }
+
+    #[inline(always)]
+    pub fn landmarks(&self) -> &[GridPosition] {
+        &self.landmarks
+    }
+
+    /// Bytes used by the cost fields
+    pub fn memory_usage(&self) -> usize {
+        let fields = self
+            .from
+            .iter()
+            .chain(self.to.iter())
+            .map(|field| field.capacity() * mem::size_of::<u32>())
+            .sum::<usize>();
+
+        fields + self.landmarks.capacity() * mem::size_of::<GridPosition>()
+    }
+}
+
+/// Cheapest cost between the origin and every cell, by Dijkstra.
+///
+/// With `reverse`, costs are of travelling from each cell to the origin.
+fn cost_field<C, L>(
+    grid: &Grid,
+    locomotion: &Locomotion,
+    origin: &GridPosition,
+    reverse: bool,
+    cost_strat: &C,
+    loco_strat: &L,
+) -> Vec<u32>
+where
+    C: CostStrategy,
+    L: LocomotionStrategy,
+{
+    let (x, y, z) = grid.size();
+    let size = Vector3::new(x, y, z);
+    let mut field = vec![UNREACHABLE; (x * y * z) as usize];
+    let mut open = BinaryHeap::new();
+
+    field[grid_index(&size, origin)] = 0;
+    open.push(PathNodePos(origin.clone(), 0));
+
+    while let Some(PathNodePos(pos, g)) = open.pop() {
+        if g > field[grid_index(&size, &pos)] {
+            continue;
+        }
+
+        for neigh in grid.neighbours_3d(&pos).iter().filter_map(|n| n.as_ref()) {
+            let (source, target) = if reverse {
+                (neigh, &pos)
+            } else {
+                (&pos, neigh)
+            };
+
+            let cost = match cost_strat.is_passable(source, target) {
+                Cost::Passable(cost) => cost,
+                Cost::Blocked => continue,
+            };
+            if !loco_strat.is_passable(locomotion, source, target) {
+                continue;
+            }
+
+            let index = grid_index(&size, neigh);
+            if g + cost < field[index] {
+                field[index] = g + cost;
+                open.push(PathNodePos(neigh.clone(), g + cost));
+            }
+        }
+    }
+
+    field
+}
+
+/// Resource holding landmarks for each locomotion class
+///
+/// Landmarks are built over the tilemap on first use, and dropped whenever
+/// the terrain changes, since a removed wall could make the estimates
+/// overshoot. They are built without door rules, which only ever add cost,
+/// so they stay valid for every pather, and through doors opening and
+/// closing.
+pub struct LandmarkOracle {
+    /// Landmarks to place per locomotion class
+    count: usize,
+
+    /// Tilemap revision the landmarks were built for
+    revision: u64,
+
+    classes: HashMap<u32, Landmarks>,
+}
+
+impl LandmarkOracle {
+    pub fn with_count(count: usize) -> Self {
+        LandmarkOracle {
+            count,
+            revision: 0,
+            classes: HashMap::new(),
+        }
+    }
+
+    /// Drops landmarks that were built for older terrain.
+    pub fn sync(&mut self, tilemap: &Tilemap) {
+        if tilemap.revision() == self.revision {
+            return;
+        }
+
+        let stale = tilemap
+            .terrain_changes_since(self.revision)
+            .map(|cells| !cells.is_empty())
+            .unwrap_or(true);
+        if stale {
+            self.classes.clear();
+        }
+        self.revision = tilemap.revision();
+    }
+
+    /// Landmarks for the locomotion class, built when needed.
+    pub fn landmarks(
+        &mut self,
+        grid: &Grid,
+        tilemap: &Tilemap,
+        locomotion: &Locomotion,
+    ) -> &Landmarks {
+        self.sync(tilemap);
+
+        let count = self.count;
+        self.classes.entry(locomotion.methods()).or_insert_with(|| {
+            let cost_strat = TilemapCost::new(tilemap);
+            let loco_strat = TilemapLocomotion::new(tilemap, grid);
+            // Only cells the class can stand in, or the landmark could end
+            // up in mid air where nothing reaches it
+            let picked = Landmarks::select(grid, count, |pos| {
+                tilemap.is_passable(pos) && loco_strat.is_passable(locomotion, pos, pos)
+            });
+
+            Landmarks::build(grid, locomotion, picked, &cost_strat, &loco_strat)
+        })
+    }
+
+    /// Bytes used by the landmarks of every class
+    pub fn memory_usage(&self) -> usize {
+        self.classes.values().map(|l| l.memory_usage()).sum()
+    }
+
+    pub fn len(&self) -> usize {
+        self.classes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.classes.is_empty()
+    }
+}
+
+impl Default for LandmarkOracle {
+    fn default() -> Self {
+        LandmarkOracle::with_count(8)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pathfinding::{AStar, NoOpLocomotion, GO_ANYWHERE};
+    use crate::tilemap::{Door, DoorState, Tile};
+
+    /// Winding cave: walls at x = 2 and x = 5 with gaps at opposite ends
+    fn cave() -> (Grid, Tilemap) {
+        let grid = Grid::with_size(8, 8, 1);
+        let mut tilemap = Tilemap::with_size(8, 8, 1);
+        for y in 0..7 {
+            tilemap.set_tile(&GridPosition::new(2, y + 1, 0), Tile::GreyBlock);
+            tilemap.set_tile(&GridPosition::new(5, y, 0), Tile::GreyBlock);
+        }
+
+        (grid, tilemap)
+    }
+
+    #[test]
+    fn test_heuristic_is_admissible() {
+        let (grid, tilemap) = cave();
+        let locomotion = Locomotion::new(&[GO_ANYWHERE]);
+        let cost_strat = TilemapCost::new(&tilemap);
+        let picked = Landmarks::select(&grid, 4, |pos| tilemap.is_passable(pos));
+        let landmarks = Landmarks::build(&grid, &locomotion, picked, &cost_strat, &NoOpLocomotion);
+
+        let start = GridPosition::new(0, 7, 0);
+        let end = GridPosition::new(7, 7, 0);
+        let actual = cost_field(
+            &grid,
+            &locomotion,
+            &start,
+            false,
+            &cost_strat,
+            &NoOpLocomotion,
+        )[grid_index(&landmarks.size, &end)];
+
+        let estimate = landmarks.heuristic(&start, &end);
+        assert!(estimate <= actual);
+
+        // Far better than straight line distance through the walls
+        assert!(estimate > euler(&start, &end) * 10);
+
+        let result = AStar::new().find_path_with_landmarks(
+            &grid,
+            &locomotion,
+            &start,
+            &end,
+            &landmarks,
+            &cost_strat,
+            &NoOpLocomotion,
+        );
+        assert_eq!(actual, result.path().unwrap().last().unwrap().g);
+    }
+
+    #[test]
+    fn test_oracle_invalidation() {
+        // The cave on solid ground, with open air above it
+        let grid = Grid::with_size(8, 8, 3);
+        let mut tilemap = Tilemap::with_size(8, 8, 3);
+        for pos in grid.aabb().iter() {
+            if pos.z() == 0 {
+                tilemap.set_tile(&pos, Tile::GreyBlock);
+            }
+        }
+        for y in 0..7 {
+            tilemap.set_tile(&GridPosition::new(2, y + 1, 1), Tile::GreyBlock);
+            tilemap.set_tile(&GridPosition::new(5, y, 1), Tile::GreyBlock);
+        }
+
+        let mut oracle = LandmarkOracle::with_count(2);
+        let locomotion = Locomotion::new(&[GROUND_WALK]);
+
+        let landmarks = oracle.landmarks(&grid, &tilemap, &locomotion).landmarks();
+        assert_eq!(2, landmarks.len());
+        assert!(landmarks.iter().all(|pos| pos.z() == 1));
+        assert_eq!(1, oracle.len());
+        assert!(oracle.memory_usage() >= 2 * 2 * 192 * 4);
+
+        // Doors don't go into the cost fields
+        let door = GridPosition::new(3, 3, 1);
+        tilemap.set_door(&door, Door::default());
+        tilemap.set_door_state(&door, DoorState::Locked);
+        oracle.sync(&tilemap);
+        assert_eq!(1, oracle.len());
+
+        tilemap.set_tile(&GridPosition::new(2, 4, 1), Tile::Empty);
+        oracle.sync(&tilemap);
+        assert!(oracle.is_empty());
+        assert_eq!(0, oracle.memory_usage());
+    }
+}
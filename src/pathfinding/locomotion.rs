@@ -21,15 +21,28 @@ pub const GO_ANYWHERE: u32 = 1 << 31;
 #[storage(DenseVecStorage)]
 pub struct Locomotion {
     methods: u32,
+
+    /// In cells per second
+    walk_speed: f64,
 }
 
 impl Locomotion {
     pub fn new(methods: &[u32]) -> Self {
         Locomotion {
             methods: methods.iter().fold(0, |acc, x| acc | x),
+            walk_speed: 4.0,
         }
     }
 
+    pub fn with_speed(self, walk_speed: f64) -> Self {
+        Locomotion { walk_speed, ..self }
+    }
+
+    #[inline(always)]
+    pub fn walk_speed(&self) -> f64 {
+        self.walk_speed
+    }
+
     pub fn has_method(&self, method: u32) -> bool {
         self.methods & method == method
     }
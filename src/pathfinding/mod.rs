@@ -1,25 +1,41 @@
 mod astar;
+mod bidirectional;
+mod cache;
 pub mod components;
+mod cooperative;
 mod cost;
 mod distance;
+mod doors;
+mod goal;
 mod jump_point_search;
+mod landmarks;
 mod locomotion;
 mod noop_strats;
 mod path_node;
 mod path_result;
 mod path_space;
 mod pathfinder;
+mod reservation;
+mod stats;
 pub mod systems;
 mod tilemap;
 
 pub use astar::*;
+pub use bidirectional::*;
+pub use cache::*;
+pub use cooperative::*;
 pub use cost::*;
 pub use distance::*;
+pub use doors::*;
+pub use goal::*;
 pub use jump_point_search::*;
+pub use landmarks::*;
 pub use locomotion::*;
 pub use noop_strats::*;
 pub use path_node::*;
 pub use path_result::*;
 pub use path_space::*;
 pub use pathfinder::*;
+pub use reservation::*;
+pub use stats::*;
 pub use tilemap::*;
@@ -1,6 +1,7 @@
 use crate::grid::GridPosition;
 use std::cmp::Ordering;
 
+#[derive(Clone)]
 pub struct PathNode {
     pub pos: GridPosition,
     /// distance from start
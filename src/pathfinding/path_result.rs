@@ -1,7 +1,60 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+use crate::grid::GridPosition;
 
 use super::path_node::PathNode;
 
+/// Reason a search did not reach its goal
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PathFailure {
+    /// Every reachable cell was explored without finding the goal
+    Blocked,
+
+    /// The start or goal lies outside of the grid
+    OutOfBounds,
+
+    /// The search expanded more nodes than allowed
+    IterationCap,
+
+    /// The search took longer than allowed
+    Timeout,
+}
+
+/// Limits and fallbacks applied to a path search
+#[derive(Clone, Debug, Default)]
+pub struct SearchOptions {
+    /// Give up after expanding this many nodes
+    pub max_iterations: Option<u32>,
+
+    /// Give up after searching for this long
+    pub timeout: Option<Duration>,
+
+    /// When the goal can't be reached, return the path to the explored
+    /// node closest to it instead of nothing
+    pub allow_partial: bool,
+}
+
+impl SearchOptions {
+    /// Checks whether the search has run out of budget
+    #[inline]
+    pub fn exceeded(&self, iter_count: u32, start_time: &Instant) -> Option<PathFailure> {
+        if let Some(max_iterations) = self.max_iterations {
+            if iter_count >= max_iterations {
+                return Some(PathFailure::IterationCap);
+            }
+        }
+
+        if let Some(timeout) = self.timeout {
+            // Reading the clock isn't free, so only check every so often
+            if iter_count % 64 == 0 && start_time.elapsed() >= timeout {
+                return Some(PathFailure::Timeout);
+            }
+        }
+
+        None
+    }
+}
+
 pub struct PathResult {
     /// Metric for number of iterations
     iter_count: u32,
@@ -13,6 +66,18 @@ pub struct PathResult {
     ///
     /// `None` if the search failed
     path: Option<Vec<PathNode>>,
+
+    /// Goal cell the path leads to, which is of interest when the search
+    /// had several to choose from
+    goal: Option<GridPosition>,
+
+    /// Why the goal wasn't reached
+    ///
+    /// A failed search may still carry a partial path.
+    failure: Option<PathFailure>,
+
+    /// Whether the path was served from a `PathCache` instead of searched
+    cached: bool,
 }
 
 impl PathResult {
@@ -20,30 +85,93 @@ impl PathResult {
         PathResult {
             iter_count,
             duration,
+            goal: path.last().map(|node| node.pos.clone()),
             path: Some(path),
+            failure: None,
+            cached: false,
+        }
+    }
+
+    /// Result of a path found in a cache, which took no search.
+    pub fn with_cached(path: Vec<PathNode>) -> Self {
+        PathResult {
+            cached: true,
+            ..PathResult::with_stats(0, Duration::from_secs(0), path)
         }
     }
 
     pub fn with_fail_stats(iter_count: u32, duration: Duration) -> Self {
+        PathResult::with_failure(iter_count, duration, PathFailure::Blocked)
+    }
+
+    pub fn with_failure(iter_count: u32, duration: Duration, failure: PathFailure) -> Self {
         PathResult {
             iter_count,
             duration,
             path: None,
+            goal: None,
+            failure: Some(failure),
+            cached: false,
+        }
+    }
+
+    /// Result of a failed search, with a path leading as close to the
+    /// goal as the search got.
+    pub fn with_partial(
+        iter_count: u32,
+        duration: Duration,
+        path: Vec<PathNode>,
+        failure: PathFailure,
+    ) -> Self {
+        PathResult {
+            iter_count,
+            duration,
+            path: Some(path),
+            goal: None,
+            failure: Some(failure),
+            cached: false,
         }
     }
 
+    #[inline(always)]
+    pub fn iter_count(&self) -> u32 {
+        self.iter_count
+    }
+
+    #[inline(always)]
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
     pub fn path(&self) -> Option<&Vec<PathNode>> {
         self.path.as_ref()
     }
 
+    pub fn goal(&self) -> Option<&GridPosition> {
+        self.goal.as_ref()
+    }
+
     pub fn take_path(&mut self) -> Option<Vec<PathNode>> {
         self.path.take()
     }
 
+    pub fn failure(&self) -> Option<PathFailure> {
+        self.failure
+    }
+
+    #[inline(always)]
+    pub fn is_cached(&self) -> bool {
+        self.cached
+    }
+
+    /// Indicates whether the path leads all the way to the goal
     pub fn is_success(&self) -> bool {
-        match self.path {
-            Some(_) => true,
-            None => false,
-        }
+        self.path.is_some() && self.failure.is_none()
+    }
+
+    /// Indicates whether the goal couldn't be reached, but there's a path
+    /// that leads closer to it.
+    pub fn is_partial(&self) -> bool {
+        self.path.is_some() && self.failure.is_some()
     }
 }
@@ -1,8 +1,12 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
 use crate::grid::{Grid, GridPosition};
 
 use super::cost::*;
+use super::goal::PathGoal;
 use super::locomotion::*;
-use super::path_result::PathResult;
+use super::path_result::{PathFailure, PathResult};
 
 pub trait Pathfinder {
     fn find_path<C, L>(
@@ -17,4 +21,110 @@ pub trait Pathfinder {
     where
         C: CostStrategy,
         L: LocomotionStrategy;
+
+    /// Searches for a path to the first position that satisfies the
+    /// `is_goal` predicate.
+    ///
+    /// The `heuristic` estimates the remaining cost from a position to the
+    /// nearest goal. It must not overestimate, and a heuristic that always
+    /// returns zero is valid when nothing better is known.
+    ///
+    /// A predicate can't be checked against the grid up front, so a goal off
+    /// the grid is only found out once every reachable cell was explored.
+    /// Searches for a `PathGoal` should go through `find_path_to`, which
+    /// checks the goal first.
+    #[allow(clippy::too_many_arguments)]
+    fn find_path_where<C, L, G, H>(
+        &self,
+        grid: &Grid,
+        locomotion: &Locomotion,
+        start: &GridPosition,
+        is_goal: G,
+        heuristic: H,
+        cost_strat: &C,
+        loco_strat: &L,
+    ) -> PathResult
+    where
+        C: CostStrategy,
+        L: LocomotionStrategy,
+        G: Fn(&GridPosition) -> bool,
+        H: Fn(&GridPosition) -> u32;
+
+    /// Searches for a path to whichever of the goals is cheapest to reach,
+    /// in a single search.
+    fn find_path_to_any<C, L>(
+        &self,
+        grid: &Grid,
+        locomotion: &Locomotion,
+        start: &GridPosition,
+        goals: &[GridPosition],
+        cost_strat: &C,
+        loco_strat: &L,
+    ) -> PathResult
+    where
+        C: CostStrategy,
+        L: LocomotionStrategy,
+    {
+        // Goals off the grid can never be reached
+        let goals = goals
+            .iter()
+            .filter(|goal| grid.in_bounds(goal))
+            .collect::<Vec<_>>();
+        if goals.is_empty() {
+            return PathResult::with_failure(0, Duration::default(), PathFailure::OutOfBounds);
+        }
+
+        let lookup = goals.iter().cloned().collect::<HashSet<_>>();
+        self.find_path_where(
+            grid,
+            locomotion,
+            start,
+            |pos| lookup.contains(pos),
+            |pos| {
+                goals
+                    .iter()
+                    .map(|goal| cost_strat.estimate(pos, goal))
+                    .min()
+                    .unwrap_or(0)
+            },
+            cost_strat,
+            loco_strat,
+        )
+    }
+
+    fn find_path_to<C, L>(
+        &self,
+        grid: &Grid,
+        locomotion: &Locomotion,
+        start: &GridPosition,
+        goal: &PathGoal,
+        cost_strat: &C,
+        loco_strat: &L,
+    ) -> PathResult
+    where
+        C: CostStrategy,
+        L: LocomotionStrategy,
+    {
+        if !goal.in_bounds(grid) {
+            return PathResult::with_failure(0, Duration::default(), PathFailure::OutOfBounds);
+        }
+
+        match goal {
+            PathGoal::Cell(end) => {
+                self.find_path(grid, locomotion, start, end, cost_strat, loco_strat)
+            }
+            PathGoal::AnyOf(goals) => {
+                self.find_path_to_any(grid, locomotion, start, goals, cost_strat, loco_strat)
+            }
+            PathGoal::AdjacentTo(_) => self.find_path_where(
+                grid,
+                locomotion,
+                start,
+                |pos| goal.is_goal(pos),
+                |pos| goal.heuristic(pos, cost_strat),
+                cost_strat,
+                loco_strat,
+            ),
+        }
+    }
 }
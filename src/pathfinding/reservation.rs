@@ -0,0 +1,314 @@
+//! Space-time reservations for cooperative pathfinding
+//!
+//! Actors claim the cells they intend to occupy at each tick of their
+//! route. Later searches treat claimed cells as blocked at those ticks,
+//! which lets actors plan around each other.
+//!
+//! A step takes as many ticks as the actor needs to walk it, so claims
+//! keep pace with where actors really are, diagonals included.
+
+use std::collections::HashMap;
+
+use crate::grid::GridPosition;
+
+use super::path_node::PathNode;
+
+/// Identifies the actor holding a reservation
+pub type AgentId = u32;
+
+/// Discrete simulation time, measured in path steps
+pub type Tick = u32;
+
+/// How many ticks an agent takes over each step of a path
+#[derive(Clone, Debug)]
+pub struct Pace {
+    /// Ticks taken to walk the length of one cell
+    ticks_per_cell: f64,
+}
+
+impl Pace {
+    /// Pace of an agent walking the given number of cells per second, on
+    /// a clock with ticks of the given duration
+    pub fn new(walk_speed: f64, tick_duration: f64) -> Self {
+        Pace {
+            ticks_per_cell: 1. / (walk_speed * tick_duration),
+        }
+    }
+
+    /// Ticks taken to walk from one cell to the next. Staying put takes as
+    /// long as walking a cell, the time a wait step holds position for.
+    pub fn step(&self, from: &GridPosition, to: &GridPosition) -> Tick {
+        let cells = if from == to {
+            1.
+        } else {
+            (to - from).vector().map(f64::from).norm()
+        };
+        ((cells * self.ticks_per_cell).round() as Tick).max(1)
+    }
+}
+
+impl Default for Pace {
+    /// One cell per tick
+    fn default() -> Self {
+        Pace { ticks_per_cell: 1. }
+    }
+}
+
+pub struct ReservationTable {
+    /// Which agent holds a cell at a given tick
+    cells: HashMap<(GridPosition, Tick), AgentId>,
+
+    /// Reservations held by each agent, so they can be released on replanning
+    agents: HashMap<AgentId, Vec<(GridPosition, Tick)>>,
+
+    /// Seconds of simulation time that makes up one tick
+    tick_duration: f64,
+
+    /// Seconds accumulated towards the next tick
+    elapsed: f64,
+
+    now: Tick,
+}
+
+impl ReservationTable {
+    pub fn new(tick_duration: f64) -> Self {
+        ReservationTable {
+            cells: HashMap::new(),
+            agents: HashMap::new(),
+            tick_duration,
+            elapsed: 0.,
+            now: 0,
+        }
+    }
+
+    /// Current tick of the table's clock
+    #[inline(always)]
+    pub fn now(&self) -> Tick {
+        self.now
+    }
+
+    pub fn tick_duration(&self) -> f64 {
+        self.tick_duration
+    }
+
+    /// Pace of an agent walking the given number of cells per second
+    pub fn pace(&self, walk_speed: f64) -> Pace {
+        Pace::new(walk_speed, self.tick_duration)
+    }
+
+    /// Advances the clock by the given delta time in seconds, dropping
+    /// reservations that have fallen into the past.
+    pub fn advance(&mut self, dt: f64) {
+        self.elapsed += dt;
+        let mut ticked = false;
+
+        while self.elapsed >= self.tick_duration {
+            self.elapsed -= self.tick_duration;
+            self.now += 1;
+            ticked = true;
+        }
+
+        if ticked {
+            let now = self.now;
+            self.cells.retain(|(_pos, tick), _agent| *tick >= now);
+            for claims in self.agents.values_mut() {
+                claims.retain(|(_pos, tick)| *tick >= now);
+            }
+            self.agents.retain(|_agent, claims| !claims.is_empty());
+        }
+    }
+
+    /// Claims a cell at a tick on behalf of an agent.
+    ///
+    /// Returns `false` if the cell is already held by another agent.
+    pub fn reserve(&mut self, agent: AgentId, pos: &GridPosition, tick: Tick) -> bool {
+        let key = (pos.clone(), tick);
+        match self.cells.get(&key) {
+            Some(holder) if *holder != agent => false,
+            Some(_) => true,
+            None => {
+                self.cells.insert(key, agent);
+                self.agents
+                    .entry(agent)
+                    .or_default()
+                    .push((pos.clone(), tick));
+                true
+            }
+        }
+    }
+
+    /// Claims every node of a path, starting at the given tick.
+    ///
+    /// Each node is held from the tick the agent arrives on it until the
+    /// tick it reaches the next one, as the pace has it. The final node
+    /// stays claimed for a further `hold` ticks so actors arriving at their
+    /// goal are not walked through. Nodes that are already held by another
+    /// agent are skipped.
+    pub fn reserve_path(
+        &mut self,
+        agent: AgentId,
+        path: &[PathNode],
+        start: Tick,
+        hold: Tick,
+        pace: &Pace,
+    ) {
+        let mut arrival = start;
+        for (i, node) in path.iter().enumerate() {
+            let leave = match path.get(i + 1) {
+                Some(next) => arrival + pace.step(&node.pos, &next.pos),
+                None => arrival + 1,
+            };
+            for tick in arrival..leave {
+                self.reserve(agent, &node.pos, tick);
+            }
+            arrival = leave;
+        }
+
+        if let Some(last) = path.last() {
+            for tick in arrival..arrival + hold {
+                self.reserve(agent, &last.pos, tick);
+            }
+        }
+    }
+
+    /// Releases all reservations held by an agent
+    pub fn release(&mut self, agent: AgentId) {
+        if let Some(claims) = self.agents.remove(&agent) {
+            for key in claims {
+                if self.cells.get(&key) == Some(&agent) {
+                    self.cells.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// The agent holding a cell at a tick, if any
+    pub fn holder(&self, pos: &GridPosition, tick: Tick) -> Option<AgentId> {
+        self.cells.get(&(pos.clone(), tick)).cloned()
+    }
+
+    /// Indicates whether a cell is held by an agent other than the given one.
+    #[inline]
+    pub fn is_reserved(&self, agent: AgentId, pos: &GridPosition, tick: Tick) -> bool {
+        match self.holder(pos, tick) {
+            Some(holder) => holder != agent,
+            None => false,
+        }
+    }
+
+    /// Indicates whether moving from `source` to `target` between `tick` and
+    /// `tick + 1` would swap places with another agent travelling the
+    /// opposite way, which would have the two walk through each other.
+    pub fn is_swap(
+        &self,
+        agent: AgentId,
+        source: &GridPosition,
+        target: &GridPosition,
+        tick: Tick,
+    ) -> bool {
+        match self.holder(target, tick) {
+            Some(other) if other != agent => self.holder(source, tick + 1) == Some(other),
+            _ => false,
+        }
+    }
+
+    /// Number of reservations currently held
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+}
+
+impl Default for ReservationTable {
+    fn default() -> Self {
+        ReservationTable::new(1.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reserve_and_release() {
+        let mut table = ReservationTable::default();
+        let pos = GridPosition::new(1, 2, 3);
+
+        assert!(table.reserve(1, &pos, 4));
+        assert!(table.reserve(1, &pos, 4));
+        assert!(!table.reserve(2, &pos, 4));
+
+        assert!(table.is_reserved(2, &pos, 4));
+        assert!(!table.is_reserved(1, &pos, 4));
+        assert!(!table.is_reserved(2, &pos, 5));
+
+        table.release(1);
+        assert!(table.is_empty());
+        assert!(table.reserve(2, &pos, 4));
+    }
+
+    #[test]
+    fn test_swap_detection() {
+        let mut table = ReservationTable::default();
+        let a = GridPosition::new(0, 0, 0);
+        let b = GridPosition::new(1, 0, 0);
+
+        // Agent 1 walks from b to a
+        table.reserve(1, &b, 0);
+        table.reserve(1, &a, 1);
+
+        assert!(table.is_swap(2, &a, &b, 0));
+        assert!(!table.is_swap(1, &a, &b, 0));
+        assert!(!table.is_swap(2, &a, &b, 1));
+    }
+
+    #[test]
+    fn test_advance_drops_past_ticks() {
+        let mut table = ReservationTable::new(0.5);
+        let pos = GridPosition::new(0, 0, 0);
+        table.reserve(1, &pos, 0);
+        table.reserve(1, &pos, 1);
+        table.reserve(1, &pos, 2);
+
+        table.advance(0.25);
+        assert_eq!(0, table.now());
+        assert_eq!(3, table.len());
+
+        table.advance(0.75);
+        assert_eq!(2, table.now());
+        assert_eq!(1, table.len());
+        assert_eq!(Some(1), table.holder(&pos, 2));
+    }
+
+    #[test]
+    fn test_reserve_at_walking_pace() {
+        let node = |x, y| PathNode {
+            pos: GridPosition::new(x, y, 0),
+            g: 0,
+            h: 0,
+            cost: 0,
+        };
+        let path = [node(0, 0), node(1, 0), node(2, 1), node(2, 1)];
+
+        // Half a cell a tick, so straight steps take 2 ticks and diagonals 3
+        let mut table = ReservationTable::new(0.5);
+        let pace = table.pace(1.0);
+        assert_eq!(2, pace.step(&path[0].pos, &path[1].pos));
+        assert_eq!(3, pace.step(&path[1].pos, &path[2].pos));
+        assert_eq!(2, pace.step(&path[2].pos, &path[3].pos));
+
+        table.reserve_path(1, &path, 0, 2, &pace);
+        let held = |x, y| {
+            (0..12)
+                .filter(|tick| table.holder(&GridPosition::new(x, y, 0), *tick) == Some(1))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![0, 1], held(0, 0));
+        assert_eq!(vec![2, 3, 4], held(1, 0));
+        // Arrives on 5, waits until 7, then holds the goal
+        assert_eq!(vec![5, 6, 7, 8, 9], held(2, 1));
+    }
+}
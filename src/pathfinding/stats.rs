@@ -0,0 +1,400 @@
+//! Aggregated metrics of path searches, to spot regressions while playing
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use super::path_result::{PathFailure, PathResult};
+
+/// Number of finished ticks kept for the CSV dump
+const TICK_HISTORY: usize = 3600;
+
+/// Linear buckets within each power of two of the duration histogram, which
+/// keeps percentiles within an eighth of the real duration
+const SUB_BUCKETS: u64 = 8;
+
+/// Buckets needed to cover every duration that fits in a `u64`
+const BUCKETS: usize = ((64 - 2) * SUB_BUCKETS) as usize;
+
+/// Totals of the searches within some span of time
+#[derive(Clone, Debug, Default)]
+pub struct SearchTotals {
+    /// Searches finished, including those answered by a cache
+    pub searches: u32,
+
+    /// Nodes expanded over all searches
+    pub nodes_expanded: u64,
+
+    /// Time spent searching
+    pub duration: Duration,
+
+    pub cache_hits: u32,
+
+    /// Searches that came back with a partial path
+    pub partial: u32,
+
+    /// Searches that came back without a path, by reason
+    pub blocked: u32,
+    pub out_of_bounds: u32,
+    pub iteration_cap: u32,
+    pub timeout: u32,
+
+    /// Highest number of requests waiting for a search at once
+    pub max_queue_len: u32,
+}
+
+impl SearchTotals {
+    fn record(&mut self, result: &PathResult) {
+        self.searches += 1;
+        self.nodes_expanded += u64::from(result.iter_count());
+        self.duration += result.duration();
+
+        if result.is_cached() {
+            self.cache_hits += 1;
+        }
+
+        if result.is_partial() {
+            self.partial += 1;
+        } else if let Some(failure) = result.failure() {
+            match failure {
+                PathFailure::Blocked => self.blocked += 1,
+                PathFailure::OutOfBounds => self.out_of_bounds += 1,
+                PathFailure::IterationCap => self.iteration_cap += 1,
+                PathFailure::Timeout => self.timeout += 1,
+            }
+        }
+    }
+
+    fn add(&mut self, other: &SearchTotals) {
+        self.searches += other.searches;
+        self.nodes_expanded += other.nodes_expanded;
+        self.duration += other.duration;
+        self.cache_hits += other.cache_hits;
+        self.partial += other.partial;
+        self.blocked += other.blocked;
+        self.out_of_bounds += other.out_of_bounds;
+        self.iteration_cap += other.iteration_cap;
+        self.timeout += other.timeout;
+        self.max_queue_len = self.max_queue_len.max(other.max_queue_len);
+    }
+
+    /// Searches that came back without a path
+    pub fn failures(&self) -> u32 {
+        self.blocked + self.out_of_bounds + self.iteration_cap + self.timeout
+    }
+}
+
+/// Statistics of one finished tick
+#[derive(Clone, Debug)]
+pub struct TickStats {
+    pub tick: u64,
+    pub totals: SearchTotals,
+}
+
+/// Counts of search durations, bucketed on a log scale so the memory it
+/// takes stays the same however long the session runs
+struct DurationHistogram {
+    counts: Vec<u64>,
+    total: u64,
+
+    /// Longest duration recorded, in microseconds
+    max: u64,
+}
+
+impl DurationHistogram {
+    /// Bucket of a duration in microseconds. Durations below `SUB_BUCKETS`
+    /// get a bucket each, longer ones share them with their neighbours.
+    fn bucket(micros: u64) -> usize {
+        if micros < SUB_BUCKETS {
+            return micros as usize;
+        }
+
+        let octave = u64::from(63 - micros.leading_zeros());
+        let shift = octave - 3;
+        let sub = (micros >> shift) - SUB_BUCKETS;
+        ((octave - 2) * SUB_BUCKETS + sub) as usize
+    }
+
+    /// Longest duration that falls into a bucket, in microseconds
+    fn upper_bound(bucket: usize) -> u64 {
+        let bucket = bucket as u64;
+        if bucket < SUB_BUCKETS {
+            return bucket;
+        }
+
+        let octave = bucket / SUB_BUCKETS + 2;
+        let sub = bucket % SUB_BUCKETS;
+        let shift = octave - 3;
+        ((SUB_BUCKETS + sub) << shift) + ((1 << shift) - 1)
+    }
+
+    fn record(&mut self, micros: u64) {
+        self.counts[DurationHistogram::bucket(micros)] += 1;
+        self.total += 1;
+        self.max = self.max.max(micros);
+    }
+
+    /// Nearest rank percentile, rounded up to the end of its bucket
+    fn percentile(&self, percent: f64) -> Option<u64> {
+        if self.total == 0 {
+            return None;
+        }
+
+        let rank = ((percent / 100. * self.total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(DurationHistogram::upper_bound(bucket).min(self.max));
+            }
+        }
+
+        Some(self.max)
+    }
+}
+
+impl Default for DurationHistogram {
+    fn default() -> Self {
+        DurationHistogram {
+            counts: vec![0; BUCKETS],
+            total: 0,
+            max: 0,
+        }
+    }
+}
+
+/// Resource collecting the metrics of every path search
+///
+/// Pathfinding systems `record` each result and close the tick with
+/// `end_tick`. Durations of searches that actually ran are counted for the
+/// whole session, to report percentiles.
+#[derive(Default)]
+pub struct PathfindingStats {
+    tick_count: u64,
+    current: SearchTotals,
+    session: SearchTotals,
+    ticks: VecDeque<TickStats>,
+
+    /// Durations of the searches that ran
+    durations: DurationHistogram,
+
+    /// Bytes held by the landmark heuristic
+    landmark_memory: usize,
+}
+
+impl PathfindingStats {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn record(&mut self, result: &PathResult) {
+        self.current.record(result);
+
+        if !result.is_cached() {
+            self.durations.record(result.duration().as_micros() as u64);
+        }
+    }
+
+    /// Notes how many requests are waiting for a search.
+    pub fn set_queue_len(&mut self, len: usize) {
+        self.current.max_queue_len = self.current.max_queue_len.max(len as u32);
+    }
+
+    /// Notes how much memory the landmark heuristic holds.
+    pub fn set_landmark_memory(&mut self, bytes: usize) {
+        self.landmark_memory = bytes;
+    }
+
+    #[inline(always)]
+    pub fn landmark_memory(&self) -> usize {
+        self.landmark_memory
+    }
+
+    /// Folds the current tick into the session totals.
+    pub fn end_tick(&mut self) {
+        let totals = ::std::mem::take(&mut self.current);
+        self.session.add(&totals);
+
+        if self.ticks.len() == TICK_HISTORY {
+            self.ticks.pop_front();
+        }
+        self.ticks.push_back(TickStats {
+            tick: self.tick_count,
+            totals,
+        });
+        self.tick_count += 1;
+    }
+
+    /// Totals of the last finished tick
+    pub fn last_tick(&self) -> Option<&TickStats> {
+        self.ticks.back()
+    }
+
+    #[inline(always)]
+    pub fn session(&self) -> &SearchTotals {
+        &self.session
+    }
+
+    #[inline(always)]
+    pub fn tick_count(&self) -> u64 {
+        self.tick_count
+    }
+
+    /// Search duration below which the given percentage of searches fall,
+    /// to within an eighth. The 100th percentile is the exact maximum.
+    pub fn percentile(&self, percent: f64) -> Option<Duration> {
+        self.durations
+            .percentile(percent)
+            .map(Duration::from_micros)
+    }
+
+    /// Writes one row per remembered tick.
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(
+            out,
+            "tick,searches,nodes_expanded,duration_us,cache_hits,partial,\
+             blocked,out_of_bounds,iteration_cap,timeout,max_queue_len"
+        )?;
+
+        for tick in &self.ticks {
+            let t = &tick.totals;
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{},{},{},{},{}",
+                tick.tick,
+                t.searches,
+                t.nodes_expanded,
+                t.duration.as_micros(),
+                t.cache_hits,
+                t.partial,
+                t.blocked,
+                t.out_of_bounds,
+                t.iteration_cap,
+                t.timeout,
+                t.max_queue_len,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for PathfindingStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = &self.session;
+        let micros = |d: Option<Duration>| d.map(|d| d.as_micros()).unwrap_or(0);
+
+        writeln!(f, "Pathfinding over {} ticks", self.tick_count)?;
+        writeln!(
+            f,
+            "  searches: {} ({} cached), nodes expanded: {}",
+            s.searches, s.cache_hits, s.nodes_expanded
+        )?;
+        writeln!(
+            f,
+            "  time: {}us total, p50 {}us, p90 {}us, p99 {}us, max {}us",
+            s.duration.as_micros(),
+            micros(self.percentile(50.)),
+            micros(self.percentile(90.)),
+            micros(self.percentile(99.)),
+            micros(self.percentile(100.)),
+        )?;
+        writeln!(
+            f,
+            "  partial: {}, failed: {} (blocked {}, out of bounds {}, iteration cap {}, timeout {})",
+            s.partial,
+            s.failures(),
+            s.blocked,
+            s.out_of_bounds,
+            s.iteration_cap,
+            s.timeout
+        )?;
+        writeln!(f, "  max queue length: {}", s.max_queue_len)?;
+        write!(f, "  landmark memory: {} KiB", self.landmark_memory / 1024)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::grid::GridPosition;
+    use crate::pathfinding::PathNode;
+
+    fn found(micros: u64) -> PathResult {
+        let node = PathNode {
+            pos: GridPosition::new(0, 0, 0),
+            g: 0,
+            h: 0,
+            cost: 0,
+        };
+        PathResult::with_stats(10, Duration::from_micros(micros), vec![node])
+    }
+
+    #[test]
+    fn test_aggregation() {
+        let mut stats = PathfindingStats::new();
+
+        stats.set_queue_len(3);
+        stats.record(&found(100));
+        stats.record(&PathResult::with_failure(
+            5,
+            Duration::from_micros(300),
+            PathFailure::IterationCap,
+        ));
+        stats.end_tick();
+
+        stats.record(&found(200));
+        stats.record(&PathResult::with_cached(vec![]));
+        stats.end_tick();
+
+        let last = stats.last_tick().unwrap();
+        assert_eq!(1, last.tick);
+        assert_eq!(2, last.totals.searches);
+        assert_eq!(1, last.totals.cache_hits);
+
+        let session = stats.session();
+        assert_eq!(4, session.searches);
+        assert_eq!(25, session.nodes_expanded);
+        assert_eq!(1, session.failures());
+        assert_eq!(1, session.iteration_cap);
+        assert_eq!(3, session.max_queue_len);
+
+        // Cached results took no search, so they don't count
+        let median = stats.percentile(50.).unwrap().as_micros();
+        assert!((200..225).contains(&median), "Got {}us", median);
+        assert_eq!(Some(Duration::from_micros(300)), stats.percentile(100.));
+    }
+
+    #[test]
+    fn test_histogram_buckets() {
+        for micros in (0..5000).chain(vec![u64::MAX / 3, u64::MAX]) {
+            let bucket = DurationHistogram::bucket(micros);
+            assert!(bucket < BUCKETS);
+            assert!(micros <= DurationHistogram::upper_bound(bucket));
+            assert!(bucket == 0 || micros > DurationHistogram::upper_bound(bucket - 1));
+        }
+
+        let mut stats = PathfindingStats::new();
+        for _ in 0..2 * TICK_HISTORY {
+            stats.end_tick();
+        }
+        assert_eq!(TICK_HISTORY, stats.ticks.len());
+        assert_eq!(2 * TICK_HISTORY as u64 - 1, stats.last_tick().unwrap().tick);
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut stats = PathfindingStats::new();
+        stats.record(&found(100));
+        stats.end_tick();
+
+        let mut out = vec![];
+        stats.write_csv(&mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+
+        assert_eq!(2, lines.len());
+        assert_eq!("0,1,10,100,0,0,0,0,0,0,0", lines[1]);
+    }
+}
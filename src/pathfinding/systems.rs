@@ -1,14 +1,22 @@
 use super::astar::AStar;
+use super::cache::{PathCache, PathKey};
 use super::components::*;
+use super::cooperative::CooperativeAStar;
 use super::cost::Cost;
+use super::doors::{Credentials, DoorCost};
+use super::landmarks::LandmarkOracle;
 use super::locomotion::*;
-use super::pathfinder::*;
+use super::reservation::ReservationTable;
+use super::stats::PathfindingStats;
 use super::tilemap::*;
+use crate::common::DeltaTime;
 use crate::grid::Grid;
 use crate::grid::GridPosition;
-use crate::pathfinding::path_result::PathResult;
+use crate::pathfinding::path_result::PathFailure;
+use crate::position::Position;
 use crate::tilemap::Tilemap;
 use specs::prelude::*;
+use std::collections::HashSet;
 
 pub struct PathfindingSystem;
 
@@ -24,38 +32,349 @@ impl<'a> System<'a> for PathfindingSystem {
         Read<'a, AStar>,
         Read<'a, Grid>,
         Read<'a, Tilemap>,
+        Write<'a, PathCache>,
+        Write<'a, PathfindingStats>,
+        ReadStorage<'a, Credentials>,
         ReadStorage<'a, Locomotion>,
         WriteStorage<'a, Pather>,
     );
 
-    fn run(&mut self, (pathfinder, grid, tilemap, locomotions, mut pathers): Self::SystemData) {
+    fn run(
+        &mut self,
+        (
+            pathfinder,
+            grid,
+            tilemap,
+            mut cache,
+            mut stats,
+            credentials,
+            locomotions,
+            mut pathers,
+        ): Self::SystemData,
+    ) {
         let cost_strat = TilemapCost::new(&tilemap);
         let loco_strat = TilemapLocomotion::new(&tilemap, &grid);
 
+        cache.sync(&tilemap);
+        stats.set_queue_len((&pathers).join().filter(|p| p.needs_path()).count());
+
         // TODO: Parallel join is not reaching rayon threshold, so runs synchronously regardless
-        (&mut pathers, &locomotions)
+        (&mut pathers, &locomotions, credentials.maybe())
             .join()
-            .filter(|(pather, _)| pather.needs_path())
-            .for_each(|(pather, locomotion)| {
+            .filter(|(pather, _, _)| pather.needs_path())
+            .for_each(|(pather, locomotion, maybe_credentials)| {
                 let maybe_request = pather.take_request();
-                if let PathRequest::Request(start, end) = maybe_request {
+                if let PathRequest::Request(start, goal) = maybe_request {
                     println!("pathfinding thread: {:?}", ::std::thread::current().id());
 
-                    let path_result = pathfinder.find_path(
+                    let door_cost = DoorCost::new(&tilemap, &cost_strat, maybe_credentials);
+                    let path_result = cache.find_path_to(
+                        &*pathfinder,
                         &grid,
                         locomotion,
+                        maybe_credentials,
                         &start,
-                        &end,
-                        &cost_strat,
+                        &goal,
+                        &door_cost,
                         &loco_strat,
                     );
+                    stats.record(&path_result);
 
-                    if path_result.is_success() {
+                    if path_result.path().is_some() {
                         pather.set_request(PathRequest::Ready(path_result));
                     } else {
-                        pather.set_request(PathRequest::Failed);
+                        let failure = path_result.failure().unwrap_or(PathFailure::Blocked);
+                        pather.set_request(PathRequest::Failed(failure));
                     }
                 }
             });
+
+        stats.end_tick();
+    }
+}
+
+/// Plans paths cooperatively, so actors route around each other.
+///
+/// Requests are served in entity order, which acts as the priority when
+/// two actors contend for the same cells. Trips taken before are answered
+/// from the `PathCache`, as long as the cached path doesn't run into
+/// anyone's reservations.
+pub struct CooperativePathfindingSystem;
+
+impl CooperativePathfindingSystem {
+    pub fn new() -> Self {
+        CooperativePathfindingSystem
+    }
+}
+
+impl<'a> System<'a> for CooperativePathfindingSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, DeltaTime>,
+        Read<'a, CooperativeAStar>,
+        Read<'a, Grid>,
+        Read<'a, Tilemap>,
+        Write<'a, LandmarkOracle>,
+        Write<'a, PathCache>,
+        Write<'a, ReservationTable>,
+        Write<'a, PathfindingStats>,
+        ReadStorage<'a, Credentials>,
+        ReadStorage<'a, Locomotion>,
+        WriteStorage<'a, Pather>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            dt,
+            pathfinder,
+            grid,
+            tilemap,
+            mut oracle,
+            mut cache,
+            mut reservations,
+            mut stats,
+            credentials,
+            locomotions,
+            mut pathers,
+        ): Self::SystemData,
+    ) {
+        let cost_strat = TilemapCost::new(&tilemap);
+        let loco_strat = TilemapLocomotion::new(&tilemap, &grid);
+
+        cache.sync(&tilemap);
+        reservations.advance(dt.0);
+        stats.set_queue_len((&pathers).join().filter(|p| p.needs_path()).count());
+
+        // Joins iterate in entity order, keeping planning deterministic
+        for (e, pather, locomotion, maybe_credentials) in
+            (&entities, &mut pathers, &locomotions, credentials.maybe()).join()
+        {
+            if !pather.needs_path() {
+                continue;
+            }
+
+            if let PathRequest::Request(start, goal) = pather.take_request() {
+                // Old claims would block the actor's own replanning
+                reservations.release(e.id());
+                let pace = reservations.pace(locomotion.walk_speed());
+
+                let key = PathKey::new(&start, &goal, locomotion, maybe_credentials);
+                let cached = cache.get_if(&key, |path| {
+                    pathfinder.is_path_free(path, e.id(), &pace, &reservations)
+                });
+
+                let path_result = match cached {
+                    Some(path_result) => path_result,
+                    None => {
+                        let landmarks = oracle.landmarks(&grid, &tilemap, locomotion);
+                        let path_result = pathfinder.find_path_with_landmarks(
+                            &grid,
+                            locomotion,
+                            &start,
+                            &goal,
+                            e.id(),
+                            &pace,
+                            &reservations,
+                            landmarks,
+                            &DoorCost::new(&tilemap, &cost_strat, maybe_credentials),
+                            &loco_strat,
+                        );
+
+                        // Waits fit the traffic at the time, and would only
+                        // hold up later trips
+                        let waits = path_result
+                            .path()
+                            .map(|path| path.windows(2).any(|step| step[0].pos == step[1].pos))
+                            .unwrap_or(false);
+                        if !waits {
+                            cache.insert(key, &path_result);
+                        }
+                        path_result
+                    }
+                };
+                stats.record(&path_result);
+
+                if let Some(path) = path_result.path() {
+                    let now = reservations.now();
+                    reservations.reserve_path(e.id(), path, now, pathfinder.hold(), &pace);
+                }
+
+                if path_result.path().is_some() {
+                    pather.set_request(PathRequest::Ready(path_result));
+                } else {
+                    let failure = path_result.failure().unwrap_or(PathFailure::Blocked);
+                    pather.set_request(PathRequest::Failed(failure));
+                }
+            }
+        }
+
+        stats.set_landmark_memory(oracle.memory_usage());
+        stats.end_tick();
+    }
+}
+
+/// Searches again whenever doors change, for actors stuck without a full
+/// path, since the way may have opened up, and for actors whose path runs
+/// through one of the doors, since it may have closed.
+pub struct DoorRepathSystem {
+    /// Tilemap revision seen on the last run
+    revision: u64,
+}
+
+impl DoorRepathSystem {
+    pub fn new() -> Self {
+        DoorRepathSystem { revision: 0 }
+    }
+}
+
+impl<'a> System<'a> for DoorRepathSystem {
+    type SystemData = (
+        Read<'a, Tilemap>,
+        ReadStorage<'a, Position>,
+        WriteStorage<'a, Pather>,
+    );
+
+    fn run(&mut self, (tilemap, positions, mut pathers): Self::SystemData) {
+        if tilemap.revision() == self.revision {
+            return;
+        }
+
+        // Without the change log reaching back far enough, any door may have changed
+        let doors = tilemap
+            .door_changes_since(self.revision)
+            .map(|cells| cells.into_iter().cloned().collect::<HashSet<_>>());
+        self.revision = tilemap.revision();
+
+        if doors
+            .as_ref()
+            .map(|cells| cells.is_empty())
+            .unwrap_or(false)
+        {
+            return;
+        }
+
+        for (pather, pos) in (&mut pathers, &positions).join() {
+            let through_door = match doors {
+                Some(ref cells) => pather
+                    .remaining()
+                    .iter()
+                    .any(|node| cells.contains(&node.pos)),
+                None => pather.has_path(),
+            };
+
+            if pather.is_waiting() || through_door {
+                pather.retry(pos.to_grid());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pathfinding::{PathNode, PathResult};
+    use crate::tilemap::{Door, DoorAccess, DoorState, Tile};
+    use std::time::Duration;
+
+    fn path(cells: &[GridPosition]) -> PathResult {
+        let nodes = cells
+            .iter()
+            .map(|pos| PathNode {
+                pos: pos.clone(),
+                g: 0,
+                h: 0,
+                cost: 0,
+            })
+            .collect();
+        PathResult::with_stats(1, Duration::default(), nodes)
+    }
+
+    #[test]
+    fn test_door_changes_repath() {
+        let mut world = World::new();
+        let door = GridPosition::new(2, 0, 0);
+        let mut tilemap = Tilemap::with_size(8, 8, 1);
+        tilemap.set_door(&door, Door::new(DoorState::Open, DoorAccess::Anyone));
+        world.add_resource(tilemap);
+        world.register::<Position>();
+        world.register::<Pather>();
+
+        let mut walker =
+            Pather::with_request(GridPosition::new(0, 0, 0), GridPosition::new(4, 0, 0));
+        walker.take_request();
+        walker.set_request(PathRequest::Ready(path(&[
+            GridPosition::new(1, 0, 0),
+            door.clone(),
+            GridPosition::new(3, 0, 0),
+            GridPosition::new(4, 0, 0),
+        ])));
+        let walker = world
+            .create_entity()
+            .with(Position::new(0., 0., 0.))
+            .with(walker)
+            .build();
+
+        let mut stuck =
+            Pather::with_request(GridPosition::new(0, 5, 0), GridPosition::new(4, 5, 0));
+        stuck.take_request();
+        stuck.set_request(PathRequest::Failed(PathFailure::Blocked));
+        let stuck = world
+            .create_entity()
+            .with(Position::new(0., 5., 0.))
+            .with(stuck)
+            .build();
+
+        let mut system = DoorRepathSystem::new();
+        system.run_now(&world.res);
+        world
+            .write_storage::<Pather>()
+            .get_mut(stuck)
+            .unwrap()
+            .take_request();
+
+        // Locking a door on the way sends the walker looking for another
+        world
+            .write_resource::<Tilemap>()
+            .set_door_state(&door, DoorState::Locked);
+        system.run_now(&world.res);
+        assert!(world
+            .read_storage::<Pather>()
+            .get(walker)
+            .unwrap()
+            .needs_path());
+
+        // Taking a door out is a door change too
+        world
+            .write_storage::<Pather>()
+            .get_mut(stuck)
+            .unwrap()
+            .set_request(PathRequest::Failed(PathFailure::Blocked));
+        world
+            .write_resource::<Tilemap>()
+            .set_tile(&door, Tile::Empty);
+        system.run_now(&world.res);
+        assert!(world
+            .read_storage::<Pather>()
+            .get(stuck)
+            .unwrap()
+            .needs_path());
+
+        // Other edits leave everyone be
+        world
+            .write_storage::<Pather>()
+            .get_mut(stuck)
+            .unwrap()
+            .set_request(PathRequest::Failed(PathFailure::Blocked));
+        world
+            .write_resource::<Tilemap>()
+            .set_tile(&GridPosition::new(6, 6, 0), Tile::GreyBlock);
+        system.run_now(&world.res);
+        assert!(!world
+            .read_storage::<Pather>()
+            .get(stuck)
+            .unwrap()
+            .needs_path());
     }
 }
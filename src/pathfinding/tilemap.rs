@@ -1,6 +1,7 @@
 //! Strategies to enforce pathfinding rules based on the Tilemap terrain
 
 use super::cost::*;
+use super::distance::octile;
 use super::locomotion::*;
 use crate::grid::*;
 use crate::tilemap::Tilemap;
@@ -29,6 +30,11 @@ impl<'a> CostStrategy for TilemapCost<'a> {
             Cost::Blocked
         }
     }
+
+    #[inline(always)]
+    fn estimate(&self, source: &GridPosition, target: &GridPosition) -> u32 {
+        octile(source, target)
+    }
 }
 
 pub struct TilemapLocomotion<'a> {
@@ -50,8 +56,8 @@ impl<'a> LocomotionStrategy for TilemapLocomotion<'a> {
         _source: &GridPosition,
         target: &GridPosition,
     ) -> bool {
-        let beneath = GridPosition::new(target.x(), target.y(), target.z() - 1);
-        let overhead = GridPosition::new(target.x(), target.y(), target.z() + 1);
+        let beneath = target.below();
+        let overhead = target.above();
 
         if locomotion.has_method(GROUND_WALK) {
             // "I need solid ground to stand on"
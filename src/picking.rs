@@ -0,0 +1,325 @@
+//! Mouse Picking
+//!
+//! Finds the grid cell under a point on the screen. Each screen point is a
+//! ray through the world, looking down along the isometric view, which is
+//! walked cell by cell from the top of the map until it enters a visible
+//! tile.
+
+use na::{Vector2, Vector3};
+use specs::prelude::*;
+
+use crate::grid::{Grid, GridPosition};
+use crate::isometric::Isometric;
+use crate::position::Position;
+use crate::settings::HALF_TILE_3D;
+use crate::tilemap::{Tile, Tilemap};
+use crate::view::components::IsometricCamera;
+use crate::view::{ViewCutMode, Viewport};
+
+/// Side of a block that the cursor is over, as seen on screen
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Face {
+    Top,
+
+    /// Drawn on the left of the block, facing +y when the view isn't turned
+    Left,
+
+    /// Drawn on the right of the block, facing +x when the view isn't turned
+    Right,
+}
+
+/// Topmost visible cell under a screen point
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Pick {
+    pub cell: GridPosition,
+    pub face: Face,
+
+    /// Direction the face points to, in the world
+    pub normal: Vector3<i32>,
+}
+
+impl Pick {
+    /// Cell on the other side of the face
+    pub fn front(&self) -> GridPosition {
+        self.cell
+            .offset(self.normal.x, self.normal.y, self.normal.z)
+    }
+}
+
+/// Finds the cell drawn at an offset, in pixels, from the centre of the
+/// screen.
+pub fn pick(
+    projection: &Isometric,
+    grid: &Grid,
+    tilemap: &Tilemap,
+    view_cut: &ViewCutMode,
+    camera: &Position,
+    screen_offset: &Vector2<f64>,
+) -> Option<Pick> {
+    let screen = screen_offset + projection.to_screen(camera.to_vector());
+
+    // Block sprites are anchored at the middle of their bottom face, so a
+    // block is drawn centred on its position, and the cell's box is offset
+    // by half a cell from the grid
+    let drawn_offset = Vector3::new(HALF_TILE_3D, HALF_TILE_3D, 0.);
+    let low = projection.to_world(&screen, 0.) + drawn_offset;
+    let high = projection.to_world(&screen, 1.) + drawn_offset;
+
+    // Away from the viewer, into the screen
+    let dir = low - high;
+
+    let (x, y, z) = grid.size();
+    let size = Vector3::new(f64::from(x), f64::from(y), f64::from(z));
+    let (t_enter, entry_axis) = enter_box(&low, &dir, &size)?;
+
+    let entry = low + dir * t_enter;
+    let mut cell = Vector3::new(0i32, 0, 0);
+    let mut t_max = Vector3::zeros();
+    let mut t_delta = Vector3::zeros();
+    let mut step = Vector3::new(0i32, 0, 0);
+
+    for axis in 0..3 {
+        let max = size[axis] as i32 - 1;
+        cell[axis] = (entry[axis].floor() as i32).max(0).min(max);
+
+        if dir[axis] > 0. {
+            step[axis] = 1;
+            t_max[axis] = t_enter + (f64::from(cell[axis] + 1) - entry[axis]) / dir[axis];
+        } else if dir[axis] < 0. {
+            step[axis] = -1;
+            t_max[axis] = t_enter + (f64::from(cell[axis]) - entry[axis]) / dir[axis];
+        } else {
+            t_max[axis] = f64::INFINITY;
+        }
+        t_delta[axis] = if dir[axis] != 0. {
+            1. / dir[axis].abs()
+        } else {
+            f64::INFINITY
+        };
+    }
+
+    let mut axis = entry_axis;
+    loop {
+        let pos = GridPosition::new(cell.x, cell.y, cell.z);
+        if !grid.in_bounds(&pos) {
+            return None;
+        }
+
+        let is_solid = tilemap
+            .tile(&pos)
+            .map(|t| t != &Tile::Empty)
+            .unwrap_or(false);
+        if is_solid && !view_cut.is_outside(camera, &pos.center()) {
+            // The ray entered through the face pointing back along it
+            let mut normal = Vector3::new(0, 0, 0);
+            normal[axis] = -step[axis];
+
+            let view_normal = projection.world_to_view(&na::convert(normal));
+            let face = if view_normal.z > 0.5 {
+                Face::Top
+            } else if view_normal.x > 0.5 {
+                Face::Right
+            } else {
+                Face::Left
+            };
+            return Some(Pick {
+                cell: pos,
+                face,
+                normal,
+            });
+        }
+
+        axis = (0..3)
+            .min_by(|a, b| t_max[*a].partial_cmp(&t_max[*b]).unwrap())
+            .unwrap();
+        cell[axis] += step[axis];
+        t_max[axis] += t_delta[axis];
+    }
+}
+
+/// Offset, in pixels from the centre of the screen, that a point is drawn at
+/// before the camera's zoom is applied
+pub fn screen_offset(projection: &Isometric, camera: &Position, point: &Position) -> Vector2<f64> {
+    projection.to_screen(point.to_vector()) - projection.to_screen(camera.to_vector())
+}
+
+/// Where the ray first enters the box from the origin to `size`, and the
+/// axis whose face it enters through.
+fn enter_box(
+    origin: &Vector3<f64>,
+    dir: &Vector3<f64>,
+    size: &Vector3<f64>,
+) -> Option<(f64, usize)> {
+    let mut t_enter = f64::NEG_INFINITY;
+    let mut t_exit = f64::INFINITY;
+    let mut entry_axis = 2;
+
+    for axis in 0..3 {
+        if dir[axis] == 0. {
+            if origin[axis] < 0. || origin[axis] > size[axis] {
+                return None;
+            }
+            continue;
+        }
+
+        let t0 = (0. - origin[axis]) / dir[axis];
+        let t1 = (size[axis] - origin[axis]) / dir[axis];
+        let (near, far) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+
+        if near > t_enter {
+            t_enter = near;
+            entry_axis = axis;
+        }
+        t_exit = t_exit.min(far);
+    }
+
+    if t_enter > t_exit {
+        None
+    } else {
+        Some((t_enter, entry_axis))
+    }
+}
+
+/// Resource holding where the mouse is, and what it's over
+#[derive(Default)]
+pub struct Cursor {
+    /// Position in pixels from the top left of the window
+    pub screen: Option<Vector2<f64>>,
+
+    pub hover: Option<Pick>,
+}
+
+/// Finds the cell under the cursor, for the frame being drawn
+pub struct PickingSystem;
+
+impl PickingSystem {
+    pub fn new() -> Self {
+        PickingSystem
+    }
+}
+
+impl<'a> System<'a> for PickingSystem {
+    type SystemData = (
+        Read<'a, Isometric>,
+        Read<'a, Grid>,
+        Read<'a, Tilemap>,
+        Read<'a, ViewCutMode>,
+        Read<'a, Viewport>,
+        Write<'a, Cursor>,
+        ReadStorage<'a, IsometricCamera>,
+        ReadStorage<'a, Position>,
+    );
+
+    fn run(
+        &mut self,
+        (projection, grid, tilemap, view_cut, viewport, mut cursor, cameras, positions): Self::SystemData,
+    ) {
+        use specs::Join;
+
+        let maybe_camera = (&cameras, &positions)
+            .join()
+            .find(|(camera, _)| camera.is_current());
+
+        cursor.hover = match (cursor.screen, maybe_camera) {
+            (Some(screen), Some((camera, camera_pos))) => {
+                let offset = (screen - viewport.center()) / camera.zoom();
+                pick(&projection, &grid, &tilemap, &view_cut, camera_pos, &offset)
+            }
+            _ => None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::isometric::Rotation;
+    use crate::view::CutMode;
+
+    /// A floor with a two block column at (2, 2)
+    fn scene() -> (Grid, Tilemap) {
+        let grid = Grid::with_size(4, 4, 4);
+        let mut tilemap = Tilemap::with_size(4, 4, 4);
+        for x in 0..4 {
+            for y in 0..4 {
+                tilemap.set_tile(&GridPosition::new(x, y, 0), Tile::GreyBlock);
+            }
+        }
+        tilemap.set_tile(&GridPosition::new(2, 2, 1), Tile::GreyBlock);
+        tilemap.set_tile(&GridPosition::new(2, 2, 2), Tile::Ladder);
+
+        (grid, tilemap)
+    }
+
+    /// Cell, face and the cell in front of it, under a point of the world
+    fn pick_at(
+        projection: &Isometric,
+        view_cut: &ViewCutMode,
+        camera: &Position,
+        point: (f64, f64, f64),
+    ) -> Option<(GridPosition, Face, GridPosition)> {
+        let (grid, tilemap) = scene();
+        let point = Position::new(point.0, point.1, point.2);
+        let offset = screen_offset(projection, camera, &point);
+        pick(projection, &grid, &tilemap, view_cut, camera, &offset)
+            .map(|p| (p.cell.clone(), p.face, p.front()))
+    }
+
+    #[test]
+    fn test_pick_faces() {
+        let view_cut = ViewCutMode::default();
+        let camera = Position::new(2., 2., 3.);
+        let hit = |cell: (i32, i32, i32), face, front: (i32, i32, i32)| {
+            Some((
+                GridPosition::new(cell.0, cell.1, cell.2),
+                face,
+                GridPosition::new(front.0, front.1, front.2),
+            ))
+        };
+
+        // Picks the same cells whatever the size of the art. Blocks are
+        // drawn centred on their position, so the column's top is at z = 3
+        for projection in [Isometric::default(), Isometric::new(64, 32, 40)].iter() {
+            let pick_at = |x, y, z| pick_at(projection, &view_cut, &camera, (x, y, z));
+
+            assert_eq!(hit((2, 2, 2), Face::Top, (2, 2, 3)), pick_at(2., 2., 3.));
+            assert_eq!(
+                hit((2, 2, 2), Face::Right, (3, 2, 2)),
+                pick_at(2.5, 2., 2.5)
+            );
+            assert_eq!(hit((2, 2, 1), Face::Left, (2, 3, 1)), pick_at(2., 2.5, 1.5));
+            assert_eq!(hit((3, 0, 0), Face::Top, (3, 0, 1)), pick_at(3., 0., 1.));
+
+            // Off the edge of the map
+            assert_eq!(None, pick_at(10., -10., 1.));
+        }
+
+        // A quarter turn shows the sides facing +x and -y instead
+        let mut projection = Isometric::default();
+        projection.set_rotation(Rotation::Deg90);
+        let pick_at = |x, y, z| pick_at(&projection, &view_cut, &camera, (x, y, z));
+        assert_eq!(hit((2, 2, 2), Face::Top, (2, 2, 3)), pick_at(2., 2., 3.));
+        assert_eq!(hit((2, 2, 2), Face::Left, (3, 2, 2)), pick_at(2.5, 2., 2.5));
+        assert_eq!(
+            hit((2, 2, 1), Face::Right, (2, 1, 1)),
+            pick_at(2., 1.5, 1.5)
+        );
+    }
+
+    #[test]
+    fn test_pick_through_cut() {
+        let view_cut = ViewCutMode::new(CutMode::Top);
+        let camera = Position::new(2., 2., 1.);
+
+        // The column is cut away above the camera, leaving the floor behind
+        let projection = Isometric::default();
+        let cell_at = |camera: &Position| {
+            pick_at(&projection, &view_cut, camera, (0., 0., 1.)).map(|(cell, _, _)| cell)
+        };
+        assert_eq!(Some(GridPosition::new(0, 0, 0)), cell_at(&camera));
+
+        // and is in the way when the camera is raised
+        let raised = Position::new(2., 2., 2.);
+        assert_eq!(Some(GridPosition::new(2, 2, 2)), cell_at(&raised));
+    }
+}
@@ -3,7 +3,9 @@ use std::ops::Add;
 use na::Vector3;
 use specs::prelude::*;
 
-#[derive(Component, Debug)]
+use crate::grid::GridPosition;
+
+#[derive(Component, Clone, Debug)]
 #[storage(DenseVecStorage)]
 pub struct Position(Vector3<f64>);
 
@@ -46,6 +48,18 @@ impl Position {
     pub fn to_vector(&self) -> &na::Vector3<f64> {
         &self.0
     }
+
+    /// Grid cell the position falls in
+    ///
+    /// Cells are centered on whole coordinates, so positions round to
+    /// the nearest cell.
+    pub fn to_grid(&self) -> GridPosition {
+        GridPosition::new(
+            self.0.x.round() as i32,
+            self.0.y.round() as i32,
+            self.0.z.round() as i32,
+        )
+    }
 }
 
 impl Add<&Position> for &Position {
@@ -54,3 +68,20 @@ impl Add<&Position> for &Position {
         Position(self.0 + rhs.0)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_grid() {
+        assert_eq!(
+            GridPosition::new(1, 2, 3),
+            Position::new(1.2, 1.5, 2.9).to_grid()
+        );
+        assert_eq!(
+            GridPosition::new(-1, 0, 0),
+            Position::new(-0.6, -0.4, 0.).to_grid()
+        );
+    }
+}
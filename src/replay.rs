@@ -0,0 +1,229 @@
+//! Command Recording and Replay
+//!
+//! A recording is a text file holding every command with the tick it was
+//! applied on, and finally the tick the session ended on:
+//!
+//! ```text
+//! 12 camera -1 -1 0
+//! 40 cut left
+//! 300 end
+//! ```
+//!
+//! As the simulation is deterministic, replaying the commands reproduces
+//! the session exactly, which the state hash can confirm.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write as IoWrite};
+use std::path::Path;
+
+use specs::prelude::*;
+
+use crate::actor::Actor;
+use crate::command::Command;
+use crate::isometric::Isometric;
+use crate::position::Position;
+use crate::view::components::IsometricCamera;
+use crate::view::{CutMode, ViewCutMode};
+
+/// Commands of a recorded session
+#[derive(Debug, Default, PartialEq)]
+pub struct CommandLog {
+    /// Commands by tick, in the order they were issued
+    pub commands: Vec<(u64, Command)>,
+
+    /// Tick the session ended on, when it ended cleanly
+    pub end: Option<u64>,
+}
+
+impl CommandLog {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<CommandLog, String> {
+        let file = File::open(&path)
+            .map_err(|err| format!("Failed to open {}: {}", path.as_ref().display(), err))?;
+        CommandLog::read(BufReader::new(file))
+    }
+
+    pub fn read<R: BufRead>(reader: R) -> Result<CommandLog, String> {
+        let mut log = CommandLog::default();
+
+        for (number, line) in reader.lines().enumerate() {
+            let line = line.map_err(|err| err.to_string())?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |msg: String| format!("Line {}: {}", number + 1, msg);
+            let (first, rest) = match line.find(' ') {
+                Some(index) => (&line[..index], line[index..].trim()),
+                None => (line, ""),
+            };
+
+            let tick = first
+                .parse::<u64>()
+                .map_err(|_| error(format!("Invalid tick: {}", first)))?;
+            if log.commands.last().map(|(t, _)| tick < *t).unwrap_or(false) {
+                return Err(error("Ticks out of order".to_string()));
+            }
+
+            if rest == "end" {
+                log.end = Some(tick);
+            } else {
+                log.commands.push((tick, rest.parse().map_err(error)?));
+            }
+        }
+
+        Ok(log)
+    }
+
+    /// Last tick the replay needs to run to
+    pub fn last_tick(&self) -> u64 {
+        let last_command = self.commands.last().map(|(tick, _)| tick + 1).unwrap_or(0);
+        self.end.unwrap_or(0).max(last_command)
+    }
+
+    /// Commands to apply on the tick
+    pub fn at(&self, tick: u64) -> impl Iterator<Item = &Command> {
+        self.commands
+            .iter()
+            .skip_while(move |(t, _)| *t < tick)
+            .take_while(move |(t, _)| *t == tick)
+            .map(|(_, command)| command)
+    }
+}
+
+/// Writes commands to a recording as they are issued
+///
+/// Lines are flushed straight away, so a recording survives a crash.
+pub struct Recorder<W: IoWrite> {
+    writer: W,
+}
+
+impl Recorder<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Recorder::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: IoWrite> Recorder<W> {
+    pub fn new(writer: W) -> Self {
+        Recorder { writer }
+    }
+
+    pub fn record(&mut self, tick: u64, command: &Command) -> io::Result<()> {
+        writeln!(self.writer, "{} {}", tick, command)?;
+        self.writer.flush()
+    }
+
+    pub fn end(mut self, tick: u64) -> io::Result<W> {
+        writeln!(self.writer, "{} end", tick)?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+/// Hash of the simulation state that player commands and actors change,
+/// for checking that a replay matches the original session.
+///
+/// Uses FNV-1a, which unlike the standard library's hasher is the same on
+/// every platform and version.
+pub fn state_hash(world: &World, tick: u64) -> u64 {
+    use specs::Join;
+
+    let mut hash = Fnv::new();
+    hash.write(tick);
+
+    let entities = world.entities();
+    let actors = world.read_storage::<Actor>();
+    let cameras = world.read_storage::<IsometricCamera>();
+    let positions = world.read_storage::<Position>();
+
+    let write_pos = |hash: &mut Fnv, id: u32, pos: &Position| {
+        hash.write(u64::from(id));
+        for v in pos.to_vector().iter() {
+            hash.write(v.to_bits());
+        }
+    };
+
+    for (e, _actor, pos) in (&entities, &actors, &positions).join() {
+        write_pos(&mut hash, e.id(), pos);
+    }
+    for (e, camera, pos) in (&entities, &cameras, &positions).join() {
+        write_pos(&mut hash, e.id(), pos);
+        hash.write(camera.target_zoom().to_bits());
+    }
+
+    match world.read_resource::<ViewCutMode>().mode() {
+        CutMode::Top => hash.write(0),
+        CutMode::Left => hash.write(1),
+        CutMode::Right => hash.write(2),
+        CutMode::Bottom => hash.write(3),
+        CutMode::Slab(layers) => {
+            hash.write(4);
+            hash.write(u64::from(layers));
+        }
+        CutMode::Plane { normal, offset } => {
+            hash.write(5);
+            for v in normal.iter().chain(Some(&offset)) {
+                hash.write(v.to_bits());
+            }
+        }
+        CutMode::CrossSection => hash.write(6),
+    }
+    hash.write(
+        world
+            .read_resource::<Isometric>()
+            .rotation()
+            .quarter_turns() as u64,
+    );
+
+    hash.finish()
+}
+
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, value: u64) {
+        for byte in value.to_le_bytes().iter() {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_log_round_trip() {
+        let mut recorder = Recorder::new(vec![]);
+        recorder.record(3, &Command::MoveCamera(1, 1, 0)).unwrap();
+        recorder
+            .record(3, &Command::SetCutMode(CutMode::Left))
+            .unwrap();
+        recorder.record(8, &Command::MoveCamera(0, 0, -1)).unwrap();
+        let bytes = recorder.end(20).unwrap();
+
+        let log = CommandLog::read(&bytes[..]).unwrap();
+        assert_eq!(Some(20), log.end);
+        assert_eq!(20, log.last_tick());
+        assert_eq!(2, log.at(3).count());
+        assert_eq!(
+            vec![&Command::MoveCamera(0, 0, -1)],
+            log.at(8).collect::<Vec<_>>()
+        );
+        assert_eq!(0, log.at(5).count());
+
+        assert!(CommandLog::read(&b"5 camera 1 0 0\n2 cut top\n"[..]).is_err());
+        assert!(CommandLog::read(&b"x cut top\n"[..]).is_err());
+    }
+}
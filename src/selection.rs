@@ -0,0 +1,336 @@
+//! Actor Selection
+//!
+//! Clicking an actor selects it, and dragging a box selects every actor
+//! standing in it. Right clicking a block sends the selection to the cell
+//! in front of the clicked face. Orders go out as commands, so they are
+//! recorded with the rest of the player's input, while the selection only
+//! lives in the view.
+
+use std::marker::PhantomData;
+
+use graphics::types::Color;
+use graphics::ImageSize;
+use specs::prelude::*;
+
+use crate::actor::Actor;
+use crate::command::{Command, CommandQueue};
+use crate::grid::Grid;
+use crate::input::{Gesture, Input};
+use crate::isometric::Isometric;
+use crate::picking::{pick, screen_offset, Pick};
+use crate::position::Position;
+use crate::sprite::Sprite;
+use crate::tilemap::Tilemap;
+use crate::view::components::IsometricCamera;
+use crate::view::{ViewCutMode, Viewport};
+
+/// Tint the renderer draws selected actors with
+pub const HIGHLIGHT: Color = [0.5, 1.0, 0.5, 1.0];
+
+/// Pixels the mouse has to move with the button down for a click to count
+/// as a drag
+const DRAG_THRESHOLD: f64 = 4.;
+
+/// Marks an actor the player has selected
+#[derive(Component, Default)]
+#[storage(NullStorage)]
+pub struct Selected;
+
+/// Turns mouse gestures into a selection, and orders for it
+pub struct SelectionSystem<I> {
+    texture: PhantomData<I>,
+}
+
+impl<I> SelectionSystem<I> {
+    pub fn new() -> Self {
+        SelectionSystem {
+            texture: PhantomData,
+        }
+    }
+}
+
+impl<'a, I> System<'a> for SelectionSystem<I>
+where
+    I: ImageSize + Send + Sync + 'static,
+{
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, Isometric>,
+        Read<'a, Grid>,
+        Read<'a, Tilemap>,
+        Read<'a, ViewCutMode>,
+        Read<'a, Viewport>,
+        Write<'a, Input>,
+        Write<'a, CommandQueue>,
+        ReadStorage<'a, Actor>,
+        ReadStorage<'a, IsometricCamera>,
+        ReadStorage<'a, Position>,
+        WriteStorage<'a, Selected>,
+        ReadStorage<'a, Sprite<I>>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            projection,
+            grid,
+            tilemap,
+            view_cut,
+            viewport,
+            mut input,
+            mut queue,
+            actors,
+            cameras,
+            positions,
+            mut selected,
+            sprites,
+        ): Self::SystemData,
+    ) {
+        use specs::Join;
+
+        let gestures = input.take_gestures();
+        let (zoom, camera_pos) = match (&cameras, &positions)
+            .join()
+            .find(|(camera, _)| camera.is_current())
+        {
+            Some((camera, pos)) => (camera.zoom(), pos.clone()),
+            None => return,
+        };
+
+        let center = viewport.center();
+
+        for gesture in gestures {
+            match gesture {
+                Gesture::Select { from, to, add } => {
+                    if !add {
+                        selected.clear();
+                    }
+
+                    let is_click = (to - from).norm() < DRAG_THRESHOLD;
+                    let (from, to) = ((from - center) / zoom, (to - center) / zoom);
+                    if is_click {
+                        // Actors drawn over others are closer to the viewer
+                        let clicked = (&entities, &actors, &positions, &sprites)
+                            .join()
+                            .filter(|(_, _, pos, sprite)| {
+                                let point = to - screen_offset(&projection, &camera_pos, pos);
+                                let [x, y, w, h] = sprite.bounds();
+                                (x..x + w).contains(&point.x) && (y..y + h).contains(&point.y)
+                            })
+                            .max_by(|(_, _, a, _), (_, _, b, _)| {
+                                let depth = |pos: &Position| pos.to_vector().sum();
+                                depth(a).partial_cmp(&depth(b)).unwrap()
+                            })
+                            .map(|(e, _, _, _)| e);
+
+                        if let Some(e) = clicked {
+                            selected.insert(e, Selected).unwrap();
+                        }
+                    } else {
+                        let min = from.inf(&to);
+                        let max = from.sup(&to);
+                        for (e, _actor, pos) in (&entities, &actors, &positions).join() {
+                            let point = screen_offset(&projection, &camera_pos, pos);
+                            if (min.x..=max.x).contains(&point.x)
+                                && (min.y..=max.y).contains(&point.y)
+                            {
+                                selected.insert(e, Selected).unwrap();
+                            }
+                        }
+                    }
+                }
+                Gesture::Order {
+                    at,
+                    queue: waypoint,
+                } => {
+                    let ids = (&entities, &actors, &selected)
+                        .join()
+                        .map(|(e, _, _)| e.id())
+                        .collect::<Vec<_>>();
+                    if ids.is_empty() {
+                        continue;
+                    }
+
+                    let maybe_pick = pick(
+                        &projection,
+                        &grid,
+                        &tilemap,
+                        &view_cut,
+                        &camera_pos,
+                        &((at - center) / zoom),
+                    );
+                    if let Some(target) = maybe_pick.as_ref().map(Pick::front) {
+                        queue.push(if waypoint {
+                            Command::QueueWaypoint(ids, target)
+                        } else {
+                            Command::MoveActors(ids, target)
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::canvas::CpuTexture;
+    use crate::command::CommandSystem;
+    use crate::grid::GridPosition;
+    use crate::pathfinding::components::Pather;
+    use crate::pathfinding::PathGoal;
+    use crate::tilemap::Tile;
+    use image::RgbaImage;
+    use na::Vector2;
+    use std::sync::Arc;
+
+    const SIZE: (u32, u32) = (320, 240);
+
+    /// A floor, with two actors standing on it side by side
+    fn scene() -> (World, Entity, Entity) {
+        let mut world = World::new();
+        let mut tilemap = Tilemap::with_size(8, 8, 4);
+        for x in 0..8 {
+            for y in 0..8 {
+                tilemap.set_tile(&GridPosition::new(x, y, 0), Tile::GreyBlock);
+            }
+        }
+        world.add_resource(Isometric::default());
+        world.add_resource(Grid::with_size(8, 8, 4));
+        world.add_resource(tilemap);
+        world.add_resource(ViewCutMode::default());
+        world.add_resource(Viewport::new(f64::from(SIZE.0), f64::from(SIZE.1)));
+        world.add_resource(Input::default());
+        world.add_resource(CommandQueue::default());
+        world.register::<Actor>();
+        world.register::<IsometricCamera>();
+        world.register::<Pather>();
+        world.register::<Position>();
+        world.register::<Selected>();
+        world.register::<Sprite<CpuTexture>>();
+
+        world
+            .create_entity()
+            .with(IsometricCamera::new(true))
+            .with(Position::new(4., 4., 1.))
+            .build();
+
+        let tex = Arc::new(CpuTexture::from_image(RgbaImage::new(20, 40)));
+        let mut actor = |x, y| {
+            let mut sprite = Sprite::from_texture(tex.clone());
+            sprite.set_anchor(0.5, 0.9);
+            world
+                .create_entity()
+                .with(Actor::new())
+                .with(Pather::new())
+                .with(GridPosition::new(x, y, 1).center())
+                .with(sprite)
+                .build()
+        };
+        let a = actor(2, 4);
+        let b = actor(4, 2);
+
+        (world, a, b)
+    }
+
+    /// Where a point is drawn in the window
+    fn window_pos(point: &Position) -> Vector2<f64> {
+        let camera = Position::new(4., 4., 1.);
+        screen_offset(&Isometric::default(), &camera, point)
+            + Vector2::new(f64::from(SIZE.0), f64::from(SIZE.1)) / 2.
+    }
+
+    fn run(world: &World, gestures: Vec<Gesture>) {
+        {
+            let mut input = world.write_resource::<Input>();
+            for gesture in gestures {
+                input.push_gesture(gesture);
+            }
+        }
+        SelectionSystem::<CpuTexture>::new().run_now(&world.res);
+    }
+
+    fn is_selected(world: &World, e: Entity) -> bool {
+        world.read_storage::<Selected>().contains(e)
+    }
+
+    #[test]
+    fn test_click_and_drag() {
+        let (world, a, b) = scene();
+        let click = |point: &Position, add| {
+            let pos = window_pos(point);
+            Gesture::Select {
+                from: pos,
+                to: pos,
+                add,
+            }
+        };
+
+        // The body is drawn above the actor's feet
+        run(&world, vec![click(&Position::new(2., 4., 1.3), false)]);
+        assert!(is_selected(&world, a));
+        assert!(!is_selected(&world, b));
+
+        // The highlight is only applied when drawing
+        {
+            let sprites = world.read_storage::<Sprite<CpuTexture>>();
+            assert_eq!(&[1., 1., 1., 1.], sprites.get(a).unwrap().color());
+        }
+
+        run(&world, vec![click(&Position::new(4., 2., 1.3), true)]);
+        assert!(is_selected(&world, a));
+        assert!(is_selected(&world, b));
+
+        // Clicking the floor clears the selection
+        run(&world, vec![click(&Position::new(6.5, 6.5, 1.5), false)]);
+        assert!(!is_selected(&world, a));
+        assert!(!is_selected(&world, b));
+
+        // A box around both of them
+        run(
+            &world,
+            vec![Gesture::Select {
+                from: window_pos(&Position::new(1., 5., 0.)),
+                to: window_pos(&Position::new(5., 1., 2.)),
+                add: false,
+            }],
+        );
+        assert!(is_selected(&world, a));
+        assert!(is_selected(&world, b));
+    }
+
+    #[test]
+    fn test_orders() {
+        let (world, a, b) = scene();
+        world
+            .write_storage::<Selected>()
+            .insert(a, Selected)
+            .unwrap();
+
+        // The floor's top face is drawn a cell up
+        let order = |x, y, queue| Gesture::Order {
+            at: window_pos(&Position::new(x, y, 1.)),
+            queue,
+        };
+        run(&world, vec![order(6., 6., false), order(6., 1., true)]);
+        CommandSystem::new().run_now(&world.res);
+
+        let target = GridPosition::new(6, 6, 1);
+        assert_eq!(
+            &[
+                Command::MoveActors(vec![a.id()], target.clone()),
+                Command::QueueWaypoint(vec![a.id()], GridPosition::new(6, 1, 1)),
+            ],
+            world.read_resource::<CommandQueue>().applied()
+        );
+
+        let pathers = world.read_storage::<Pather>();
+        let pather = pathers.get(a).unwrap();
+        assert!(pather.needs_path());
+        assert_eq!(Some(&PathGoal::Cell(target)), pather.goal());
+        assert_eq!(1, pather.waypoints().len());
+        assert_eq!(None, pathers.get(b).unwrap().goal());
+    }
+}
@@ -0,0 +1,246 @@
+//! Spatial Index
+//!
+//! Keeps track of which grid cell every positioned entity is in, so systems
+//! can ask who is standing where without joining over every entity.
+
+use std::collections::HashMap;
+
+use na::Vector3;
+use specs::prelude::*;
+
+use crate::grid::{GridAabb, GridPosition};
+use crate::position::Position;
+use crate::tilemap::TileObj;
+
+/// Lookup from grid cells to the entities inside them
+#[derive(Default)]
+pub struct SpatialIndex {
+    cells: HashMap<GridPosition, Vec<Entity>>,
+
+    /// Last indexed cell and exact position of each entity
+    entities: HashMap<Entity, (GridPosition, Vector3<f64>)>,
+}
+
+impl SpatialIndex {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Records where an entity is, moving it between cells when needed.
+    pub fn update(&mut self, entity: Entity, cell: &GridPosition, pos: &Vector3<f64>) {
+        if let Some((old_cell, old_pos)) = self.entities.get_mut(&entity) {
+            *old_pos = *pos;
+            if old_cell == cell {
+                return;
+            }
+
+            if let Some(occupants) = self.cells.get_mut(old_cell) {
+                occupants.retain(|e| *e != entity);
+                if occupants.is_empty() {
+                    self.cells.remove(old_cell);
+                }
+            }
+            *old_cell = cell.clone();
+        } else {
+            self.entities.insert(entity, (cell.clone(), *pos));
+        }
+
+        self.cells.entry(cell.clone()).or_default().push(entity);
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        if let Some((cell, _)) = self.entities.remove(&entity) {
+            if let Some(occupants) = self.cells.get_mut(&cell) {
+                occupants.retain(|e| *e != entity);
+                if occupants.is_empty() {
+                    self.cells.remove(&cell);
+                }
+            }
+        }
+    }
+
+    /// Where an entity was when it was last indexed
+    pub fn position_of(&self, entity: Entity) -> Option<&Vector3<f64>> {
+        self.entities.get(&entity).map(|(_, pos)| pos)
+    }
+
+    /// Entities in the given cell
+    pub fn at(&self, cell: &GridPosition) -> &[Entity] {
+        self.cells.get(cell).map(|v| v.as_slice()).unwrap_or(&[])
+    }
+
+    /// Entities within a distance of a point, sorted by entity id.
+    pub fn within_radius(&self, center: &Vector3<f64>, radius: f64) -> Vec<Entity> {
+        let min = Position::new(center.x - radius, center.y - radius, center.z - radius);
+        let max = Position::new(center.x + radius, center.y + radius, center.z + radius);
+
+        self.in_aabb(&GridAabb::new(&min.to_grid(), &max.to_grid()))
+            .into_iter()
+            .filter(|e| {
+                self.entities
+                    .get(e)
+                    .map(|(_, pos)| (pos - center).magnitude() <= radius)
+                    .unwrap_or(false)
+            })
+            .collect()
+    }
+
+    /// Entities inside a region, sorted by entity id.
+    pub fn in_aabb(&self, aabb: &GridAabb) -> Vec<Entity> {
+        let mut result = vec![];
+
+        if aabb.volume() < self.cells.len() {
+            for cell in aabb {
+                result.extend_from_slice(self.at(&cell));
+            }
+        } else {
+            // Region is larger than the populated part of the map
+            for (cell, occupants) in &self.cells {
+                if aabb.contains(cell) {
+                    result.extend_from_slice(occupants);
+                }
+            }
+        }
+
+        result.sort();
+        result
+    }
+}
+
+/// Keeps the `SpatialIndex` and `GridPosition` components in sync with `Position`
+///
+/// Entities locked to the tilemap with a `TileObj` are left out. They never
+/// move, and the `Tilemap` already tells what is in each of its cells.
+pub struct SpatialIndexSystem;
+
+impl SpatialIndexSystem {
+    pub fn new() -> Self {
+        SpatialIndexSystem
+    }
+}
+
+impl<'a> System<'a> for SpatialIndexSystem {
+    type SystemData = (
+        Entities<'a>,
+        Write<'a, SpatialIndex>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, TileObj>,
+        WriteStorage<'a, GridPosition>,
+    );
+
+    fn run(
+        &mut self,
+        (entities, mut index, positions, tile_objs, mut grid_positions): Self::SystemData,
+    ) {
+        use specs::Join;
+
+        for (e, pos, ()) in (&entities, &positions, !&tile_objs).join() {
+            let cell = pos.to_grid();
+
+            index.update(e, &cell, pos.to_vector());
+
+            if grid_positions.get(e) != Some(&cell) {
+                grid_positions.insert(e, cell).unwrap();
+            }
+        }
+
+        // Forget entities that were deleted, lost their position or were
+        // locked to the tilemap
+        let stale = index
+            .entities
+            .keys()
+            .filter(|e| positions.get(**e).is_none() || tile_objs.contains(**e))
+            .cloned()
+            .collect::<Vec<_>>();
+        for e in stale {
+            index.remove(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entities(count: usize) -> Vec<Entity> {
+        let mut world = World::new();
+        (0..count).map(|_| world.create_entity().build()).collect()
+    }
+
+    #[test]
+    fn test_update_moves_between_cells() {
+        let e = entities(1);
+        let mut index = SpatialIndex::new();
+        let a = GridPosition::new(1, 1, 1);
+        let b = GridPosition::new(2, 1, 1);
+
+        index.update(e[0], &a, &Vector3::new(1., 1., 1.));
+        assert_eq!(&[e[0]], index.at(&a));
+
+        index.update(e[0], &b, &Vector3::new(2., 1., 1.));
+        assert!(index.at(&a).is_empty());
+        assert_eq!(&[e[0]], index.at(&b));
+        assert_eq!(Some(&Vector3::new(2., 1., 1.)), index.position_of(e[0]));
+
+        index.remove(e[0]);
+        assert!(index.at(&b).is_empty());
+        assert_eq!(None, index.position_of(e[0]));
+    }
+
+    #[test]
+    fn test_queries() {
+        let e = entities(3);
+        let mut index = SpatialIndex::new();
+        index.update(e[0], &GridPosition::new(0, 0, 0), &Vector3::new(0., 0., 0.));
+        index.update(e[1], &GridPosition::new(1, 1, 0), &Vector3::new(1., 1., 0.));
+        index.update(e[2], &GridPosition::new(5, 5, 0), &Vector3::new(5., 5., 0.));
+
+        assert_eq!(
+            vec![e[0], e[1]],
+            index.within_radius(&Vector3::new(0., 0., 0.), 1.5)
+        );
+        assert_eq!(
+            vec![e[0]],
+            index.within_radius(&Vector3::new(0., 0., 0.), 1.)
+        );
+        assert_eq!(
+            vec![e[1], e[2]],
+            index.in_aabb(&GridAabb::new(
+                &GridPosition::new(1, 1, 0),
+                &GridPosition::new(5, 5, 0)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_system_leaves_out_tiles() {
+        let mut world = World::new();
+        world.add_resource(SpatialIndex::new());
+        world.register::<Position>();
+        world.register::<TileObj>();
+        world.register::<GridPosition>();
+
+        let tile_pos = GridPosition::new(1, 1, 0);
+        let tile = world
+            .create_entity()
+            .with(tile_pos.center())
+            .with(TileObj::new(tile_pos.clone()))
+            .build();
+        let actor = world
+            .create_entity()
+            .with(Position::new(1., 1., 0.2))
+            .build();
+
+        SpatialIndexSystem::new().run_now(&world.res);
+
+        assert_eq!(
+            &[actor],
+            world.read_resource::<SpatialIndex>().at(&tile_pos)
+        );
+        assert_eq!(
+            Some(&tile_pos),
+            world.read_storage::<GridPosition>().get(actor)
+        );
+        assert_eq!(None, world.read_storage::<GridPosition>().get(tile));
+    }
+}
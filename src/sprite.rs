@@ -1,17 +1,22 @@
+use std::marker::PhantomData;
 use std::sync::Arc;
 
 use graphics::types::{Color, Matrix2d};
-use graphics::{Graphics, ImageSize, Transformed};
+use graphics::{Context, Graphics, ImageSize, Transformed, Viewport};
 use na::Vector2;
-use opengl_graphics::{GlGraphics, Texture};
+use opengl_graphics::GlGraphics;
 use piston::input::*;
 use specs::prelude::*;
 
+use crate::clock::{Interpolation, PreviousPosition};
 use crate::depthsort::DepthBuffer;
-use crate::isometric::Isometric;
+use crate::grid::Cardinal;
+use crate::isometric::{Isometric, Rotation};
 use crate::position::Position;
-use crate::settings::*;
+use crate::selection::{Selected, HIGHLIGHT};
+use crate::steering::Steering;
 use crate::view::components::IsometricCamera;
+use crate::xray::Ghosted;
 
 #[derive(Component)]
 #[storage(DenseVecStorage)]
@@ -23,6 +28,9 @@ pub struct Sprite<I: ImageSize + Send + Sync + 'static> {
     depth: i32,
     src_rect: Option<[f64; 4]>,
     color: Color,
+
+    /// Colour of the quad drawn in place of the texture when zoomed out
+    lod_color: Color,
     tex: Arc<I>,
 }
 
@@ -39,6 +47,7 @@ where
             depth: 0,
             src_rect: None,
             color: [1., 1., 1., 1.],
+            lod_color: [1., 1., 1., 1.],
             tex,
         }
     }
@@ -83,7 +92,55 @@ where
         self.color = color
     }
 
+    /// Colour of the quad drawn when zoomed out, tinted like the texture
+    pub fn lod_color(&self) -> Color {
+        tint(self.lod_color, &self.color)
+    }
+
+    /// Sets the colour standing in for the texture when zoomed out, which
+    /// is white unless set
+    #[inline(always)]
+    pub fn set_lod_color(&mut self, color: Color) {
+        self.lod_color = color
+    }
+
+    /// Draws only part of the texture, as `[x, y, width, height]`
+    #[inline(always)]
+    pub fn set_src_rect(&mut self, src_rect: Option<[f64; 4]>) {
+        self.src_rect = src_rect
+    }
+
+    /// Rectangle the sprite covers, as `[x, y, width, height]` relative to
+    /// the point it's drawn at
+    pub fn bounds(&self) -> [f64; 4] {
+        let (w, h) = match self.src_rect {
+            Some(rect) => (rect[2], rect[3]),
+            None => {
+                let (w, h) = self.tex.get_size();
+                (f64::from(w), f64::from(h))
+            }
+        };
+        let (w, h) = (w * self.scale.x, h * self.scale.y);
+
+        [-self.anchor.x * w, -self.anchor.y * h, w, h]
+    }
+
+    /// Rectangle the sprite covers when drawn at the point, with its offset
+    pub fn bounds_at(&self, point: &Vector2<f64>) -> [f64; 4] {
+        let [x, y, w, h] = self.bounds();
+        let origin = point + self.translate;
+        [origin.x + x, origin.y + y, w, h]
+    }
+
     pub fn draw<G>(&self, transform: Matrix2d, g: &mut G)
+    where
+        G: Graphics<Texture = I>,
+    {
+        self.draw_tinted(transform, &[1., 1., 1., 1.], g)
+    }
+
+    /// Draws the sprite with its colour tinted further, for this draw only
+    pub fn draw_tinted<G>(&self, transform: Matrix2d, tint_color: &Color, g: &mut G)
     where
         G: Graphics<Texture = I>,
     {
@@ -108,7 +165,7 @@ where
         let ref draw_state: graphics::DrawState = Default::default();
 
         graphics::Image::new()
-            .color(self.color)
+            .color(tint(self.color, tint_color))
             .rect([
                 -anchor[0],
                 -anchor[1],
@@ -118,44 +175,212 @@ where
             .maybe_src_rect(self.src_rect)
             .draw(&*self.tex, draw_state, t, g);
     }
+
+    /// Draws a flat quad over the area the texture would cover
+    pub fn draw_quad<G>(&self, transform: Matrix2d, tint_color: &Color, g: &mut G)
+    where
+        G: Graphics<Texture = I>,
+    {
+        let t = transform.trans(self.translate.x, self.translate.y);
+        graphics::rectangle(tint(self.lod_color(), tint_color), self.bounds(), t, g);
+    }
 }
 
-pub struct SpriteRenderer {
-    gl: GlGraphics,
+/// Multiplies a colour by a tint, channel by channel
+fn tint(mut color: Color, tint: &Color) -> Color {
+    for (c, t) in color.iter_mut().zip(tint.iter()) {
+        *c *= t;
+    }
+    color
+}
+
+/// Tint of the layer a cross section cuts through
+pub const CUT_TINT: Color = [1.0, 0.55, 0.45, 1.0];
+
+/// Zoom below which sprites are drawn as flat quads, as their details
+/// would be lost anyway
+pub const LOD_ZOOM: f64 = 0.5;
+
+/// Sprite with a frame for each way it can face
+///
+/// Frames are source rectangles on the sprite's texture, for facing each
+/// compass direction of the view in the order of `Cardinal::ALL`. The
+/// heading is in the world, so turning the view shows another frame.
+#[derive(Component)]
+#[storage(DenseVecStorage)]
+pub struct Directional {
+    frames: [[f64; 4]; 4],
+    heading: Cardinal,
 }
 
-impl SpriteRenderer {
-    pub fn from_graphics(gl: GlGraphics) -> Self {
-        SpriteRenderer { gl }
+impl Directional {
+    pub fn new(frames: [[f64; 4]; 4]) -> Self {
+        Directional {
+            frames,
+            heading: Cardinal::South,
+        }
+    }
+
+    pub fn heading(&self) -> Cardinal {
+        self.heading
+    }
+
+    pub fn set_heading(&mut self, heading: Cardinal) {
+        self.heading = heading
+    }
+
+    /// Frame showing the heading, as it looks in the turned view
+    pub fn frame(&self, rotation: Rotation) -> [f64; 4] {
+        let facing = rotation.rotate_cardinal(self.heading);
+        let index = Cardinal::ALL.iter().position(|c| *c == facing).unwrap();
+        self.frames[index]
+    }
+}
+
+/// Turns directional sprites the way they're walking, and shows the frame
+/// for that in the current view
+pub struct DirectionalSystem<I> {
+    texture: PhantomData<I>,
+}
+
+impl<I> DirectionalSystem<I> {
+    pub fn new() -> Self {
+        DirectionalSystem {
+            texture: PhantomData,
+        }
     }
 }
 
-impl<'a> System<'a> for SpriteRenderer {
+impl<'a, I> System<'a> for DirectionalSystem<I>
+where
+    I: ImageSize + Send + Sync + 'static,
+{
+    type SystemData = (
+        Read<'a, Isometric>,
+        ReadStorage<'a, Steering>,
+        WriteStorage<'a, Directional>,
+        WriteStorage<'a, Sprite<I>>,
+    );
+
+    fn run(&mut self, (projection, steerings, mut directionals, mut sprites): Self::SystemData) {
+        use specs::Join;
+
+        for (directional, sprite, steering) in
+            (&mut directionals, &mut sprites, steerings.maybe()).join()
+        {
+            // Standing still keeps the last heading
+            let velocity = steering.map(|s| *s.velocity()).unwrap_or_else(na::zero);
+            if velocity.x != 0. || velocity.y != 0. {
+                let heading = if velocity.x.abs() >= velocity.y.abs() {
+                    if velocity.x > 0. {
+                        Cardinal::East
+                    } else {
+                        Cardinal::West
+                    }
+                } else if velocity.y > 0. {
+                    Cardinal::South
+                } else {
+                    Cardinal::North
+                };
+                directional.set_heading(heading);
+            }
+
+            sprite.set_src_rect(Some(directional.frame(projection.rotation())));
+        }
+    }
+}
+
+/// A surface the sprite pipeline can draw frames onto
+pub trait RenderTarget {
+    type Graphics: Graphics;
+
+    fn draw_frame<F>(&mut self, viewport: Viewport, f: F)
+    where
+        F: FnOnce(Context, &mut Self::Graphics);
+}
+
+impl RenderTarget for GlGraphics {
+    type Graphics = GlGraphics;
+
+    fn draw_frame<F>(&mut self, viewport: Viewport, f: F)
+    where
+        F: FnOnce(Context, &mut GlGraphics),
+    {
+        self.draw(viewport, f);
+    }
+}
+
+/// Texture type drawn by a render target
+pub type TargetTexture<T> = <<T as RenderTarget>::Graphics as Graphics>::Texture;
+
+pub struct SpriteRenderer<T> {
+    gl: T,
+
+    /// Draws squares at the centre of the world and of the camera view
+    markers: bool,
+}
+
+impl<T> SpriteRenderer<T> {
+    pub fn from_graphics(gl: T) -> Self {
+        SpriteRenderer { gl, markers: true }
+    }
+
+    pub fn with_markers(self, markers: bool) -> Self {
+        SpriteRenderer { markers, ..self }
+    }
+
+    #[inline(always)]
+    pub fn target(&self) -> &T {
+        &self.gl
+    }
+}
+
+impl<'a, T> System<'a> for SpriteRenderer<T>
+where
+    T: RenderTarget,
+    TargetTexture<T>: Send + Sync + 'static,
+{
     type SystemData = (
         Entities<'a>,
         Read<'a, OnRender>,
         ReadStorage<'a, IsometricCamera>,
-        ReadStorage<'a, Sprite<Texture>>,
+        ReadStorage<'a, Sprite<TargetTexture<T>>>,
         ReadStorage<'a, Position>,
+        ReadStorage<'a, PreviousPosition>,
+        ReadStorage<'a, Selected>,
+        ReadStorage<'a, Ghosted>,
         Read<'a, DepthBuffer>,
+        Read<'a, Interpolation>,
+        Read<'a, Isometric>,
     );
 
     fn run(
         &mut self,
-        (entities, on_render, cameras, sprites, positions, buffer): Self::SystemData,
+        (
+            entities,
+            on_render,
+            cameras,
+            sprites,
+            positions,
+            previous_positions,
+            selected,
+            ghosted,
+            buffer,
+            interpolation,
+            projection,
+        ): Self::SystemData,
     ) {
         use graphics::*;
         use specs::Join;
 
-        let SpriteRenderer { gl, .. } = self;
+        let SpriteRenderer { gl, markers } = self;
+        let markers = *markers;
 
-        let camera_pos_iso = (&cameras, &positions)
+        let (camera_pos_2d, zoom) = (&cameras, &positions)
             .join()
             .find(|(camera, _position)| camera.is_current())
-            .map(|(_camera, position)| Isometric::cart_to_iso(position.to_vector()))
-            .unwrap_or(na::Vector3::new(0., 0., 0.));
-        // let camera_pos_2d = na::Vector2::<f64>::new(camera_pos_iso.x, camera_pos_iso.y + camera_pos_iso.z);
-        let camera_pos_2d = flatten_pos(&camera_pos_iso);
+            .map(|(camera, position)| (projection.to_screen(position.to_vector()), camera.zoom()))
+            .unwrap_or((na::Vector2::new(0., 0.), 1.));
 
         const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
         const BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
@@ -164,7 +389,7 @@ impl<'a> System<'a> for SpriteRenderer {
 
         let square = rectangle::square(0.0, 0.0, 10.0);
 
-        gl.draw(on_render.args().viewport(), |c, gl| {
+        gl.draw_frame(on_render.args().viewport(), |c, gl| {
             // Center of screen
             let (offset_x, offset_y) =
                 (on_render.args().width / 2.0, on_render.args().height / 2.0);
@@ -173,32 +398,63 @@ impl<'a> System<'a> for SpriteRenderer {
             clear(BLACK, gl);
 
             // Positions are inversed, because world is transformed in the reverse direction
-            // of what the camera is doing
+            // of what the camera is doing. Zooming scales around the centre of the screen.
             let transform = c
                 .transform
-                .trans(-(camera_pos_2d.x) + offset_x, -(camera_pos_2d.y) + offset_y);
+                .trans(offset_x, offset_y)
+                .zoom(zoom)
+                .trans(-(camera_pos_2d.x), -(camera_pos_2d.y));
 
             for item in buffer.contents() {
                 let e = entities.entity(item.entity_id());
                 if let Some(sprite) = sprites.get(e) {
                     if let Some(pos) = positions.get(e) {
-                        let iso_pos = Isometric::cart_to_iso(&pos.to_vector());
-                        let screen_pos = flatten_pos(&iso_pos);
-                        sprite.draw(transform.trans(screen_pos.x, screen_pos.y), gl);
+                        // Moving entities are drawn between their last two ticks
+                        let pos = match previous_positions.get(e) {
+                            Some(previous) => interpolation.lerp(&previous.0, pos),
+                            None => pos.clone(),
+                        };
+                        let screen_pos = projection.to_screen(pos.to_vector());
+                        let t = transform.trans(screen_pos.x, screen_pos.y);
+                        let mut tint = if item.is_cut() { CUT_TINT } else { WHITE };
+                        if selected.contains(e) {
+                            tint = self::tint(tint, &HIGHLIGHT);
+                        }
+                        let ghost = ghosted.get(e);
+                        if let Some(ghost) = ghost {
+                            tint = self::tint(tint, &ghost.tint);
+                        }
+                        if zoom < LOD_ZOOM {
+                            sprite.draw_quad(t, &tint, gl);
+                        } else {
+                            sprite.draw_tinted(t, &tint, gl);
+                        }
+
+                        if let Some(outline) = ghost.and_then(|ghost| ghost.outline) {
+                            let t = t.trans(sprite.position().x, sprite.position().y);
+                            Rectangle::new_border(outline, 1. / zoom).draw(
+                                sprite.bounds(),
+                                &Default::default(),
+                                t,
+                                gl,
+                            );
+                        }
                     }
                 }
             }
 
-            // Center of world
-            rectangle(RED, square, transform, gl);
-
-            // Center of camera view
-            rectangle(
-                GREEN,
-                square,
-                transform.trans(camera_pos_2d.x, camera_pos_2d.y),
-                gl,
-            );
+            if markers {
+                // Center of world
+                rectangle(RED, square, transform, gl);
+
+                // Center of camera view
+                rectangle(
+                    GREEN,
+                    square,
+                    transform.trans(camera_pos_2d.x, camera_pos_2d.y),
+                    gl,
+                );
+            }
         });
     }
 }
@@ -211,9 +467,25 @@ impl OnRender {
         OnRender(args)
     }
 
+    /// Render of an offscreen target of the given size in pixels
+    pub fn with_size(width: u32, height: u32) -> Self {
+        OnRender(RenderArgs {
+            ext_dt: 0.,
+            width: f64::from(width),
+            height: f64::from(height),
+            draw_width: width,
+            draw_height: height,
+        })
+    }
+
     fn args(&self) -> &RenderArgs {
         &self.0
     }
+
+    /// Width and height of the rendered area
+    pub fn size(&self) -> (f64, f64) {
+        (self.0.width, self.0.height)
+    }
 }
 
 impl Default for OnRender {
@@ -232,3 +504,24 @@ impl Default for OnRender {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_directional_frames() {
+        let frame = |i: f64| [i * 32., 0., 32., 48.];
+        let mut directional = Directional::new([frame(0.), frame(1.), frame(2.), frame(3.)]);
+
+        // Frames follow Cardinal::ALL: north, east, south, west
+        assert_eq!(frame(2.), directional.frame(Rotation::Deg0));
+        directional.set_heading(Cardinal::East);
+        assert_eq!(frame(1.), directional.frame(Rotation::Deg0));
+
+        // Turning the view turns the heading with the world
+        assert_eq!(frame(2.), directional.frame(Rotation::Deg90));
+        assert_eq!(frame(3.), directional.frame(Rotation::Deg180));
+        assert_eq!(frame(0.), directional.frame(Rotation::Deg270));
+    }
+}
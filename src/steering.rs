@@ -0,0 +1,339 @@
+//! Local Collision Avoidance
+//!
+//! Paths are planned on the grid and know nothing about where other actors
+//! are standing at this moment. Steering adjusts an actor's velocity while it
+//! walks towards its next path node, so crowds spread out, queue up behind
+//! each other and sidestep oncoming traffic instead of stacking on one point.
+//!
+//! Avoidance happens on the horizontal plane. Actors on different levels
+//! don't interact, and vertical movement, like climbing ladders, is left
+//! untouched.
+
+use na::Vector3;
+use specs::prelude::*;
+
+/// Actors further apart vertically than this don't steer around each other
+const LEVEL_TOLERANCE: f64 = 0.5;
+
+/// How far ahead, beyond touching, an actor looks for others to queue behind
+const QUEUE_DISTANCE: f64 = 0.5;
+
+/// How far around itself an actor looks for neighbours to avoid
+pub const AVOID_RANGE: f64 = 1.5;
+
+/// Trade-off between avoiding collisions and keeping to the preferred velocity
+const SAFETY_WEIGHT: f64 = 0.5;
+
+/// How strongly overlapping actors are pushed apart
+const SEPARATION_WEIGHT: f64 = 2.0;
+
+/// Shortest time to collision considered, to keep penalties finite
+const MIN_COLLISION_TIME: f64 = 0.01;
+
+/// Angles, in degrees, at which candidate velocities are sampled around
+/// the preferred velocity
+const SAMPLE_ANGLES: [f64; 7] = [0., 30., -30., 60., -60., 90., -90.];
+
+/// Fractions of the preferred speed at which candidate velocities are sampled
+const SAMPLE_SPEEDS: [f64; 3] = [1., 0.5, 0.25];
+
+/// Local avoidance state of an actor
+#[derive(Component)]
+#[storage(DenseVecStorage)]
+pub struct Steering {
+    /// Personal space, in 3D units
+    radius: f64,
+
+    /// Velocity the actor moved with during the last update
+    velocity: Vector3<f64>,
+}
+
+impl Steering {
+    pub fn new() -> Self {
+        Steering::with_radius(0.3)
+    }
+
+    pub fn with_radius(radius: f64) -> Self {
+        Steering {
+            radius,
+            velocity: Vector3::zeros(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    /// How far around the actor others are steered around
+    #[inline(always)]
+    pub fn range(&self) -> f64 {
+        self.radius + AVOID_RANGE
+    }
+
+    #[inline(always)]
+    pub fn velocity(&self) -> &Vector3<f64> {
+        &self.velocity
+    }
+
+    #[inline(always)]
+    pub fn set_velocity(&mut self, velocity: Vector3<f64>) {
+        self.velocity = velocity;
+    }
+}
+
+/// Snapshot of an actor, as seen by the actors steering around it
+#[derive(Clone, Debug)]
+pub struct Neighbour {
+    pub id: u32,
+    pub pos: Vector3<f64>,
+    pub velocity: Vector3<f64>,
+    pub radius: f64,
+}
+
+/// Indicates whether an actor, other than the given one, is standing on a point.
+pub fn is_occupied(id: u32, point: &Vector3<f64>, neighbours: &[Neighbour]) -> bool {
+    neighbours
+        .iter()
+        .filter(|n| n.id != id)
+        .any(|n| flatten(&(n.pos - point)).magnitude() <= n.radius && on_level(&n.pos, point))
+}
+
+/// Adjusts an actor's preferred velocity to avoid the actors around it.
+///
+/// Neighbours should include everyone within `Steering::range` of the actor.
+pub fn steer(me: &Neighbour, preferred: &Vector3<f64>, neighbours: &[Neighbour]) -> Vector3<f64> {
+    let neighbours = neighbours
+        .iter()
+        .filter(|n| n.id != me.id && on_level(&n.pos, &me.pos))
+        .collect::<Vec<_>>();
+
+    if neighbours.is_empty() {
+        return *preferred;
+    }
+
+    let flat_preferred = queue(me, &flatten(preferred), &neighbours);
+    let mut velocity = avoid(me, &flat_preferred, &neighbours) + separation(me, &neighbours);
+    velocity.z = preferred.z;
+    velocity
+}
+
+/// Slows an actor down when someone is walking, or standing, right in
+/// front of it, so it follows instead of pushing through.
+fn queue(me: &Neighbour, preferred: &Vector3<f64>, neighbours: &[&Neighbour]) -> Vector3<f64> {
+    let speed = preferred.magnitude();
+    if speed <= 0. {
+        return *preferred;
+    }
+    let heading = preferred / speed;
+    let mut allowed = speed;
+
+    for n in neighbours {
+        let offset = flatten(&(n.pos - me.pos));
+        let dist = offset.magnitude();
+        let reach = me.radius + n.radius;
+
+        // Only those roughly ahead of us
+        if dist >= reach + QUEUE_DISTANCE || offset.dot(&heading) < dist * 0.7 {
+            continue;
+        }
+
+        let ahead_speed = n.velocity.dot(&heading).max(0.);
+        let gap = (dist - reach).max(0.) / QUEUE_DISTANCE;
+        allowed = allowed.min(ahead_speed + gap * speed);
+    }
+
+    heading * allowed
+}
+
+/// Picks the sampled velocity that best balances staying close to the
+/// preferred velocity against the time until colliding with a neighbour.
+///
+/// Neighbours are assumed to take half the responsibility for avoiding a
+/// collision, as in Reciprocal Velocity Obstacles, which prevents the
+/// oscillation of both actors dodging the same way.
+fn avoid(me: &Neighbour, preferred: &Vector3<f64>, neighbours: &[&Neighbour]) -> Vector3<f64> {
+    let speed = preferred.magnitude();
+    let mut best = Vector3::zeros();
+    let mut best_penalty = f64::INFINITY;
+
+    let candidates = SAMPLE_ANGLES
+        .iter()
+        .flat_map(|angle| SAMPLE_SPEEDS.iter().map(move |s| (*angle, *s)))
+        .map(|(angle, s)| rotate(preferred, angle.to_radians()) * s)
+        .chain(::std::iter::once(Vector3::zeros()));
+
+    for candidate in candidates {
+        let reciprocal = candidate * 2. - flatten(&me.velocity);
+        let collision = neighbours
+            .iter()
+            .map(|n| time_to_collision(me, n, &reciprocal))
+            .fold(f64::INFINITY, f64::min)
+            .max(MIN_COLLISION_TIME);
+
+        let penalty = SAFETY_WEIGHT / collision + (candidate - preferred).magnitude();
+        if penalty < best_penalty {
+            best_penalty = penalty;
+            best = candidate;
+        }
+    }
+
+    // Sampling never speeds an actor up
+    if best.magnitude() > speed {
+        best = best.normalize() * speed;
+    }
+
+    best
+}
+
+/// Pushes apart actors that have already ended up overlapping.
+fn separation(me: &Neighbour, neighbours: &[&Neighbour]) -> Vector3<f64> {
+    let mut push = Vector3::zeros();
+
+    for n in neighbours {
+        let offset = flatten(&(me.pos - n.pos));
+        let dist = offset.magnitude();
+        let overlap = me.radius + n.radius - dist;
+        if overlap <= 0. {
+            continue;
+        }
+
+        let away = if dist > f64::EPSILON {
+            offset / dist
+        } else if me.id < n.id {
+            // Standing on the exact same spot, break the tie deterministically
+            Vector3::new(1., 0., 0.)
+        } else {
+            Vector3::new(-1., 0., 0.)
+        };
+
+        push += away * overlap * SEPARATION_WEIGHT;
+    }
+
+    push
+}
+
+/// Seconds until two actors touch, given their relative velocity.
+fn time_to_collision(me: &Neighbour, other: &Neighbour, velocity: &Vector3<f64>) -> f64 {
+    let rel_pos = flatten(&(other.pos - me.pos));
+    let rel_vel = velocity - flatten(&other.velocity);
+    let reach = me.radius + other.radius;
+
+    let c = rel_pos.dot(&rel_pos) - reach * reach;
+    let b = rel_pos.dot(&rel_vel);
+
+    if c < 0. {
+        // Already touching, only moving apart is safe
+        return if b > 0. { 0. } else { f64::INFINITY };
+    }
+
+    let a = rel_vel.dot(&rel_vel);
+    let discriminant = b * b - a * c;
+    if a <= f64::EPSILON || discriminant <= 0. {
+        return f64::INFINITY;
+    }
+
+    let t = (b - discriminant.sqrt()) / a;
+    if t < 0. {
+        f64::INFINITY
+    } else {
+        t
+    }
+}
+
+#[inline]
+fn flatten(v: &Vector3<f64>) -> Vector3<f64> {
+    Vector3::new(v.x, v.y, 0.)
+}
+
+#[inline]
+fn on_level(a: &Vector3<f64>, b: &Vector3<f64>) -> bool {
+    (a.z - b.z).abs() < LEVEL_TOLERANCE
+}
+
+#[inline]
+fn rotate(v: &Vector3<f64>, radians: f64) -> Vector3<f64> {
+    let (sin, cos) = radians.sin_cos();
+    Vector3::new(v.x * cos - v.y * sin, v.x * sin + v.y * cos, v.z)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn neighbour(id: u32, x: f64, y: f64, vx: f64, vy: f64) -> Neighbour {
+        Neighbour {
+            id,
+            pos: Vector3::new(x, y, 0.),
+            velocity: Vector3::new(vx, vy, 0.),
+            radius: 0.3,
+        }
+    }
+
+    #[test]
+    fn test_is_occupied() {
+        let crowd = [neighbour(1, 0., 0., 0., 0.), neighbour(3, 4., 4., 0., 0.)];
+
+        assert!(is_occupied(1, &Vector3::new(4., 4., 0.), &crowd));
+        assert!(!is_occupied(3, &Vector3::new(4., 4., 0.), &crowd));
+        assert!(!is_occupied(1, &Vector3::new(2., 2., 0.), &crowd));
+    }
+
+    #[test]
+    fn test_unobstructed_keeps_preferred() {
+        let mut crowd = vec![];
+        let me = neighbour(1, 0., 0., 1., 0.);
+        crowd.push(me.clone());
+        crowd.push(neighbour(2, 5., 5., 0., 0.));
+
+        let preferred = Vector3::new(1., 0., 0.);
+        assert_eq!(preferred, steer(&me, &preferred, &crowd));
+    }
+
+    #[test]
+    fn test_head_on_sidesteps() {
+        let mut crowd = vec![];
+        let me = neighbour(1, 0., 0., 1., 0.);
+        crowd.push(me.clone());
+        crowd.push(neighbour(2, 1.2, 0., -1., 0.));
+
+        let preferred = Vector3::new(1., 0., 0.);
+        let velocity = steer(&me, &preferred, &crowd);
+        assert!(
+            velocity.y.abs() > 0.1,
+            "Expected sidestep, got {:?}",
+            velocity
+        );
+    }
+
+    #[test]
+    fn test_queue_behind_standing() {
+        let mut crowd = vec![];
+        let me = neighbour(1, 0., 0., 1., 0.);
+        crowd.push(me.clone());
+        crowd.push(neighbour(2, 0.65, 0., 0., 0.));
+
+        let preferred = Vector3::new(1., 0., 0.);
+        let velocity = steer(&me, &preferred, &crowd);
+        assert!(
+            velocity.x < 0.5,
+            "Expected to slow down, got {:?}",
+            velocity
+        );
+    }
+
+    #[test]
+    fn test_separation_on_same_spot() {
+        let mut crowd = vec![];
+        let a = neighbour(1, 0., 0., 0., 0.);
+        let b = neighbour(2, 0., 0., 0., 0.);
+        crowd.push(a.clone());
+        crowd.push(b.clone());
+
+        let still = Vector3::zeros();
+        let va = steer(&a, &still, &crowd);
+        let vb = steer(&b, &still, &crowd);
+        assert!(va.x > 0.);
+        assert!(vb.x < 0.);
+    }
+}
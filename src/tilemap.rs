@@ -1,9 +1,23 @@
+use std::collections::{HashMap, VecDeque};
+
 use crate::grid::{grid_index, GridPosition};
 use specs::prelude::*;
 
+/// Number of tile edits remembered for `changes_since`
+const CHANGE_LOG_LEN: usize = 1024;
+
 pub struct Tilemap {
     size: na::Vector3<u32>,
     data: Vec<Tile>,
+
+    /// Incremented on every tile edit
+    revision: u64,
+
+    /// Most recent edits, oldest first
+    changes: VecDeque<Change>,
+
+    /// State of every `Tile::Door`
+    doors: HashMap<GridPosition, Door>,
 }
 
 impl Tilemap {
@@ -11,12 +25,117 @@ impl Tilemap {
         Tilemap {
             size: na::Vector3::new(x, y, z),
             data: (0..(x * y * z)).map(|_| Tile::Empty).collect(),
+            revision: 0,
+            changes: VecDeque::new(),
+            doors: HashMap::new(),
         }
     }
 
     #[inline(always)]
     pub fn set_tile(&mut self, pos: &GridPosition, tile: Tile) {
-        self.data[grid_index(&self.size, pos)] = tile;
+        let door = tile == Tile::Door || self.doors.contains_key(pos);
+        if tile == Tile::Door {
+            self.doors.entry(pos.clone()).or_default();
+        } else {
+            self.doors.remove(pos);
+        }
+
+        // Doors are open ground to anything but the door rules
+        let index = grid_index(&self.size, pos);
+        let is_open = |tile: &Tile| *tile == Tile::Empty || *tile == Tile::Door;
+        let terrain = self.data[index] != tile && !(is_open(&self.data[index]) && is_open(&tile));
+
+        self.data[index] = tile;
+        self.touch(pos, door, terrain);
+    }
+
+    /// Places a door tile.
+    pub fn set_door(&mut self, pos: &GridPosition, door: Door) {
+        self.set_tile(pos, Tile::Door);
+        self.doors.insert(pos.clone(), door);
+    }
+
+    pub fn door(&self, pos: &GridPosition) -> Option<&Door> {
+        self.doors.get(pos)
+    }
+
+    /// Opens, closes or locks the door at the position.
+    ///
+    /// Returns `false` when there is no door.
+    pub fn set_door_state(&mut self, pos: &GridPosition, state: DoorState) -> bool {
+        match self.doors.get_mut(pos) {
+            Some(door) if door.state != state => {
+                door.state = state;
+                self.touch(pos, true, false);
+                true
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Records an edit of the position in the change log
+    fn touch(&mut self, pos: &GridPosition, door: bool, terrain: bool) {
+        self.revision += 1;
+        if self.changes.len() == CHANGE_LOG_LEN {
+            self.changes.pop_front();
+        }
+        self.changes.push_back(Change {
+            revision: self.revision,
+            pos: pos.clone(),
+            door,
+            terrain,
+        });
+    }
+
+    #[inline(always)]
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Positions edited after the given revision, oldest first.
+    ///
+    /// Returns `None` when the change log no longer reaches back that far,
+    /// in which case anything derived from the map must be rebuilt.
+    pub fn changes_since(&self, revision: u64) -> Option<Vec<&GridPosition>> {
+        self.changes_where(revision, |_| true)
+    }
+
+    /// Positions where doors were placed, removed, opened, closed or locked
+    /// after the given revision, oldest first.
+    ///
+    /// Returns `None` when the change log no longer reaches back that far.
+    pub fn door_changes_since(&self, revision: u64) -> Option<Vec<&GridPosition>> {
+        self.changes_where(revision, |change| change.door)
+    }
+
+    /// Positions where solid ground or ladders were placed or removed after
+    /// the given revision, oldest first. Door states and swaps between
+    /// doors and empty cells are left out.
+    ///
+    /// Returns `None` when the change log no longer reaches back that far.
+    pub fn terrain_changes_since(&self, revision: u64) -> Option<Vec<&GridPosition>> {
+        self.changes_where(revision, |change| change.terrain)
+    }
+
+    fn changes_where<F>(&self, revision: u64, filter: F) -> Option<Vec<&GridPosition>>
+    where
+        F: Fn(&Change) -> bool,
+    {
+        if revision >= self.revision {
+            return Some(vec![]);
+        }
+
+        match self.changes.front() {
+            Some(oldest) if oldest.revision <= revision + 1 => Some(
+                self.changes
+                    .iter()
+                    .filter(|change| change.revision > revision && filter(change))
+                    .map(|change| &change.pos)
+                    .collect(),
+            ),
+            _ => None,
+        }
     }
 
     #[inline(always)]
@@ -24,8 +143,22 @@ impl Tilemap {
         self.data.get(grid_index(&self.size, pos))
     }
 
+    #[inline(always)]
+    pub fn in_bounds(&self, pos: &GridPosition) -> bool {
+        pos.x() >= 0
+            && pos.x() < self.size.x as i32
+            && pos.y() >= 0
+            && pos.y() < self.size.y as i32
+            && pos.z() >= 0
+            && pos.z() < self.size.z as i32
+    }
+
+    /// Indicates whether a cell can be walked into. Cells off the map can't.
     pub fn is_passable(&self, pos: &GridPosition) -> bool {
-        // TODO: Should we bounds check?
+        if !self.in_bounds(pos) {
+            return false;
+        }
+
         self.data
             .get(grid_index(&self.size, pos))
             .map(|tile| tile != &Tile::GreyBlock)
@@ -41,6 +174,20 @@ impl Tilemap {
     }
 }
 
+/// An edit recorded in the tilemap's change log
+struct Change {
+    /// Revision the edit produced
+    revision: u64,
+    pos: GridPosition,
+
+    /// Whether it placed, removed or changed a door
+    door: bool,
+
+    /// Whether it changed where things can stand, walk or climb, beyond
+    /// the door rules
+    terrain: bool,
+}
+
 impl Default for Tilemap {
     fn default() -> Tilemap {
         Tilemap::with_size(16, 16, 16)
@@ -52,6 +199,47 @@ pub enum Tile {
     Empty,
     GreyBlock,
     Ladder,
+    Door,
+}
+
+pub type FactionId = u32;
+pub type KeyId = u32;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DoorState {
+    Open,
+
+    /// Anyone can open it, which takes a moment
+    Closed,
+
+    /// Only those granted access can unlock it
+    Locked,
+}
+
+/// Who may unlock a door
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DoorAccess {
+    Anyone,
+    Faction(FactionId),
+    Key(KeyId),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Door {
+    pub state: DoorState,
+    pub access: DoorAccess,
+}
+
+impl Door {
+    pub fn new(state: DoorState, access: DoorAccess) -> Self {
+        Door { state, access }
+    }
+}
+
+impl Default for Door {
+    fn default() -> Door {
+        Door::new(DoorState::Closed, DoorAccess::Anyone)
+    }
 }
 
 /// Marks an entity as locked to the tilemap grid
@@ -59,3 +247,14 @@ pub enum Tile {
 pub struct TileObj {
     pos: GridPosition,
 }
+
+impl TileObj {
+    pub fn new(pos: GridPosition) -> Self {
+        TileObj { pos }
+    }
+
+    #[inline(always)]
+    pub fn pos(&self) -> &GridPosition {
+        &self.pos
+    }
+}
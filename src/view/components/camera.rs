@@ -1,5 +1,17 @@
 use specs::prelude::*;
 
+/// Closest the camera zooms in
+pub const MAX_ZOOM: f64 = 2.;
+
+/// Furthest the camera zooms out
+pub const MIN_ZOOM: f64 = 0.25;
+
+/// Scale of one zoom step
+pub const ZOOM_STEP: f64 = 1.25;
+
+/// How quickly the zoom eases towards its target, per second
+const ZOOM_RATE: f64 = 12.;
+
 /// Isometric camera
 ///
 /// It's 3D position is it's lookat position
@@ -7,15 +19,105 @@ use specs::prelude::*;
 #[storage(DenseVecStorage)]
 pub struct IsometricCamera {
     current: bool,
+
+    /// Scale the world is drawn at, easing towards the target
+    zoom: f64,
+    target_zoom: f64,
 }
 
 impl IsometricCamera {
     pub fn new(current: bool) -> Self {
-        IsometricCamera { current }
+        IsometricCamera {
+            current,
+            zoom: 1.,
+            target_zoom: 1.,
+        }
     }
 
     #[inline(always)]
     pub fn is_current(&self) -> bool {
         self.current
     }
+
+    #[inline(always)]
+    pub fn zoom(&self) -> f64 {
+        self.zoom
+    }
+
+    pub fn target_zoom(&self) -> f64 {
+        self.target_zoom
+    }
+
+    /// Sets the zoom to ease towards, within the zoom limits
+    pub fn set_target_zoom(&mut self, zoom: f64) {
+        self.target_zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+
+    /// Sets the zoom straight away, without easing
+    pub fn set_zoom(&mut self, zoom: f64) {
+        self.set_target_zoom(zoom);
+        self.zoom = self.target_zoom;
+    }
+
+    /// Zooms in, or out for negative steps
+    pub fn zoom_by(&mut self, steps: i32) {
+        self.set_target_zoom(self.target_zoom * ZOOM_STEP.powi(steps));
+    }
+
+    /// Eases the zoom towards its target over the time step
+    pub fn update_zoom(&mut self, dt: f64) {
+        let t = 1. - (-ZOOM_RATE * dt).exp();
+        self.zoom += (self.target_zoom - self.zoom) * t;
+    }
+}
+
+/// Eases cameras towards their target zoom
+pub struct CameraZoomSystem;
+
+impl CameraZoomSystem {
+    pub fn new() -> Self {
+        CameraZoomSystem
+    }
+}
+
+impl<'a> System<'a> for CameraZoomSystem {
+    type SystemData = (
+        Read<'a, crate::common::DeltaTime>,
+        WriteStorage<'a, IsometricCamera>,
+    );
+
+    fn run(&mut self, (dt, mut cameras): Self::SystemData) {
+        use specs::Join;
+
+        for camera in (&mut cameras).join() {
+            camera.update_zoom(dt.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_zoom_eases_to_target() {
+        let mut camera = IsometricCamera::new(true);
+        camera.zoom_by(2);
+        assert_eq!(1., camera.zoom());
+        assert_eq!(ZOOM_STEP * ZOOM_STEP, camera.target_zoom());
+
+        let mut last = camera.zoom();
+        for _ in 0..120 {
+            camera.update_zoom(1. / 60.);
+            assert!(camera.zoom() > last && camera.zoom() <= camera.target_zoom());
+            last = camera.zoom();
+        }
+        assert!((camera.target_zoom() - camera.zoom()).abs() < 1e-6);
+
+        // Limited at both ends
+        camera.zoom_by(100);
+        assert_eq!(MAX_ZOOM, camera.target_zoom());
+        camera.set_zoom(0.);
+        assert_eq!(MIN_ZOOM, camera.zoom());
+    }
 }
@@ -1,32 +1,75 @@
+use crate::isometric::Rotation;
 use crate::position::Position;
 
 pub struct ViewCutMode {
     mode: CutMode,
+
+    /// Turn of the view, which the Left and Right planes follow
+    rotation: Rotation,
     in_vector: na::Vector3<f64>,
 }
 
 impl ViewCutMode {
+    /// Cuts the view by the mode, or from the top when the mode can't cut
+    /// anything.
     pub fn new(mode: CutMode) -> Self {
-        let in_vector = mode.vector();
-        ViewCutMode { mode, in_vector }
+        let mut view_cut = ViewCutMode {
+            mode: CutMode::Top,
+            rotation: Rotation::Deg0,
+            in_vector: CutMode::Top.vector(),
+        };
+        view_cut.set_mode(mode);
+        view_cut
     }
 
     /// Given a 3D position, determine whether it's inside the
     /// cut view.
     #[inline(always)]
     pub fn is_outside(&self, cut_point: &Position, pos: &Position) -> bool {
+        let depth = self.depth(cut_point, pos);
+        match self.mode {
+            CutMode::Slab(layers) => depth < 0.0 || depth > f64::from(layers),
+            CutMode::Plane { offset, .. } => depth < offset,
+            _ => depth < 0.0,
+        }
+    }
+
+    /// Indicates whether a position in view lies on the cut, in the layer
+    /// a cross section tints.
+    pub fn is_on_cut(&self, cut_point: &Position, pos: &Position) -> bool {
+        self.mode == CutMode::CrossSection && (0.0..1.0).contains(&self.depth(cut_point, pos))
+    }
+
+    /// How far past the cut a position is, into the kept part of the view
+    #[inline(always)]
+    fn depth(&self, cut_point: &Position, pos: &Position) -> f64 {
         let camera_subtracted = pos.to_vector() - cut_point.to_vector();
-        self.in_vector.dot(&camera_subtracted) < 0.0
+        self.in_vector.dot(&camera_subtracted)
     }
 
     pub fn mode(&self) -> CutMode {
         self.mode.clone()
     }
 
+    /// Switches to the mode, unless it can't cut anything, like a plane
+    /// without a usable normal.
     pub fn set_mode(&mut self, mode: CutMode) {
-        self.in_vector = mode.vector();
+        if !mode.is_valid() {
+            return;
+        }
+
+        self.in_vector = match mode {
+            // Arbitrary planes are in the world, and stay put as the view turns
+            CutMode::Plane { ref normal, .. } => normal.normalize(),
+            _ => self.rotation.inverse().rotate(&mode.vector()),
+        };
         self.mode = mode;
     }
+
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.rotation = rotation;
+        self.set_mode(self.mode.clone());
+    }
 }
 
 impl Default for ViewCutMode {
@@ -35,21 +78,152 @@ impl Default for ViewCutMode {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum CutMode {
-    Top = 0,
+    Top,
     Left,
     Right,
+
+    /// Hides everything below the camera, to look up at cave ceilings
+    Bottom,
+
+    /// Keeps the layers from the camera down to the given number of layers
+    /// below it
+    Slab(u32),
+
+    /// Keeps the side of a plane the normal points into, with the normal
+    /// in world coordinates. The plane goes through the camera, moved by
+    /// the offset in cells along the normal.
+    Plane {
+        normal: na::Vector3<f64>,
+        offset: f64,
+    },
+
+    /// Cuts like `Top`, tinting the layer the cut goes through
+    CrossSection,
 }
 
 impl CutMode {
+    /// Indicates whether the mode describes a cut, which a plane doesn't
+    /// when its normal is too short to scale or not finite.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            CutMode::Plane { normal, offset } => {
+                offset.is_finite()
+                    && normal
+                        .try_normalize(f64::EPSILON)
+                        .is_some_and(|n| n.iter().all(|v| v.is_finite()))
+            }
+            _ => true,
+        }
+    }
+
+    /// Direction into the kept part of the view, in view coordinates
     fn vector(&self) -> na::Vector3<f64> {
         use CutMode::*;
 
         match self {
-            Top => na::Vector3::new(0., 0., -1.),
+            Top | Slab(_) | CrossSection => na::Vector3::new(0., 0., -1.),
             Left => na::Vector3::new(0., -1., 0.),
             Right => na::Vector3::new(-1., 0., 0.),
+            Bottom => na::Vector3::new(0., 0., 1.),
+            Plane { normal, .. } => *normal,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rotated_planes() {
+        let camera = Position::new(5., 5., 5.);
+        let behind_x = Position::new(6., 5., 5.);
+        let behind_y = Position::new(5., 6., 5.);
+
+        let mut view_cut = ViewCutMode::new(CutMode::Left);
+        assert!(!view_cut.is_outside(&camera, &behind_x));
+        assert!(view_cut.is_outside(&camera, &behind_y));
+
+        // A quarter turn puts the world's x axis on the left of the view
+        view_cut.set_rotation(Rotation::Deg90);
+        assert!(view_cut.is_outside(&camera, &behind_x));
+        assert!(!view_cut.is_outside(&camera, &behind_y));
+
+        // and the world's y axis, reversed, on the right
+        view_cut.set_mode(CutMode::Right);
+        assert!(!view_cut.is_outside(&camera, &behind_x));
+        assert!(!view_cut.is_outside(&camera, &behind_y));
+        assert!(view_cut.is_outside(&camera, &Position::new(5., 4., 5.)));
+
+        // Top doesn't turn
+        view_cut.set_mode(CutMode::Top);
+        assert!(view_cut.is_outside(&camera, &Position::new(5., 5., 6.)));
+    }
+
+    #[test]
+    fn test_vertical_cuts() {
+        let camera = Position::new(5., 5., 5.);
+        let at_height = |z| Position::new(5., 5., z);
+
+        let bottom = ViewCutMode::new(CutMode::Bottom);
+        assert!(bottom.is_outside(&camera, &at_height(4.)));
+        assert!(!bottom.is_outside(&camera, &at_height(5.)));
+        assert!(!bottom.is_outside(&camera, &at_height(9.)));
+
+        let slab = ViewCutMode::new(CutMode::Slab(2));
+        assert!(slab.is_outside(&camera, &at_height(6.)));
+        assert!(!slab.is_outside(&camera, &at_height(5.)));
+        assert!(!slab.is_outside(&camera, &at_height(3.)));
+        assert!(slab.is_outside(&camera, &at_height(2.)));
+
+        // Turning the view doesn't move horizontal cuts
+        let mut section = ViewCutMode::new(CutMode::CrossSection);
+        section.set_rotation(Rotation::Deg270);
+        assert!(section.is_outside(&camera, &at_height(5.5)));
+        assert!(!section.is_outside(&camera, &at_height(4.)));
+        assert!(section.is_on_cut(&camera, &at_height(5.)));
+        assert!(section.is_on_cut(&camera, &at_height(4.5)));
+        assert!(!section.is_on_cut(&camera, &at_height(4.)));
+        assert!(!ViewCutMode::new(CutMode::Top).is_on_cut(&camera, &at_height(5.)));
+    }
+
+    #[test]
+    fn test_arbitrary_plane() {
+        let camera = Position::new(5., 5., 5.);
+
+        // A diagonal wall, two cells along its normal from the camera
+        let mut plane = ViewCutMode::new(CutMode::Plane {
+            normal: na::Vector3::new(1., 1., 0.),
+            offset: 2.,
+        });
+        assert!(plane.is_outside(&camera, &camera));
+        assert!(plane.is_outside(&camera, &Position::new(6., 6., 5.)));
+        assert!(!plane.is_outside(&camera, &Position::new(7., 6., 0.)));
+        assert!(!plane.is_outside(&camera, &Position::new(9., 4., 5.)));
+
+        // It stays put in the world as the view turns
+        plane.set_rotation(Rotation::Deg90);
+        assert!(!plane.is_outside(&camera, &Position::new(7., 6., 0.)));
+        assert!(plane.is_outside(&camera, &Position::new(6., 6., 5.)));
+
+        // Planes without a normal are ignored
+        let before = plane.mode();
+        plane.set_mode(CutMode::Plane {
+            normal: na::zero(),
+            offset: 0.,
+        });
+        assert_eq!(before, plane.mode());
+        assert!(!plane.is_outside(&camera, &Position::new(7., 6., 0.)));
+
+        // and cut from the top when there is nothing to keep
+        let fallback = ViewCutMode::new(CutMode::Plane {
+            normal: na::zero(),
+            offset: 0.,
+        });
+        assert_eq!(CutMode::Top, fallback.mode());
+        assert!(fallback.is_outside(&camera, &Position::new(5., 5., 6.)));
+        assert!(!fallback.is_outside(&camera, &Position::new(5., 5., 4.)));
+    }
+}
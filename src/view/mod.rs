@@ -4,5 +4,7 @@
 
 pub mod components;
 mod cut_mode;
+mod viewport;
 
 pub use cut_mode::*;
+pub use viewport::*;
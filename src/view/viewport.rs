@@ -0,0 +1,80 @@
+use na::Vector2;
+
+/// Size of the window the view is drawn into, in pixels
+///
+/// Kept up to date from resize events, so culling and picking follow the
+/// real window.
+pub struct Viewport {
+    width: f64,
+    height: f64,
+}
+
+impl Viewport {
+    pub fn new(width: f64, height: f64) -> Self {
+        Viewport { width, height }
+    }
+
+    pub fn size(&self) -> (f64, f64) {
+        (self.width, self.height)
+    }
+
+    pub fn resize(&mut self, width: f64, height: f64) {
+        self.width = width;
+        self.height = height;
+    }
+
+    /// Centre of the window, which the camera's position is drawn at
+    pub fn center(&self) -> Vector2<f64> {
+        Vector2::new(self.width / 2., self.height / 2.)
+    }
+
+    /// Part of the projected world in view, as `[x, y, width, height]`,
+    /// when the camera looks at the given screen point with the zoom
+    pub fn visible_area(&self, look_at: &Vector2<f64>, zoom: f64) -> [f64; 4] {
+        let (width, height) = (self.width / zoom, self.height / zoom);
+        [
+            look_at.x - width / 2.,
+            look_at.y - height / 2.,
+            width,
+            height,
+        ]
+    }
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Viewport::new(0., 0.)
+    }
+}
+
+/// Indicates whether two `[x, y, width, height]` rectangles overlap
+pub fn overlaps(a: &[f64; 4], b: &[f64; 4]) -> bool {
+    a[0] < b[0] + b[2] && b[0] < a[0] + a[2] && a[1] < b[1] + b[3] && b[1] < a[1] + a[3]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_visible_area() {
+        let mut viewport = Viewport::new(640., 480.);
+        let look_at = Vector2::new(100., 50.);
+        assert_eq!(
+            [-220., -190., 640., 480.],
+            viewport.visible_area(&look_at, 1.)
+        );
+
+        // Zooming out shows twice as much around the same point
+        assert_eq!(
+            [-540., -430., 1280., 960.],
+            viewport.visible_area(&look_at, 0.5)
+        );
+
+        viewport.resize(200., 100.);
+        let area = viewport.visible_area(&look_at, 1.);
+        assert!(overlaps(&area, &[190., 90., 20., 20.]));
+        assert!(!overlaps(&area, &[200., 90., 20., 20.]));
+        assert!(!overlaps(&area, &[0., -40., 20., 40.]));
+    }
+}
@@ -0,0 +1,259 @@
+//! X-ray View
+//!
+//! Actors walking behind a column would disappear in the depth order. Any
+//! sprite drawn over a selected or important actor is ghosted instead, by
+//! lowering its alpha as it is drawn, so the actor shows through without
+//! changing the cut mode.
+
+use std::marker::PhantomData;
+
+use graphics::types::Color;
+use graphics::ImageSize;
+use specs::prelude::*;
+
+use crate::actor::Actor;
+use crate::depthsort::DepthBuffer;
+use crate::isometric::Isometric;
+use crate::position::Position;
+use crate::selection::Selected;
+use crate::sprite::Sprite;
+use crate::view::overlaps;
+
+/// Alpha of sprites ghosted over an actor
+const GHOST_ALPHA: f32 = 0.35;
+
+/// Alpha of ghosted sprites when they are outlined, which leaves
+/// little more than the outline
+const OUTLINED_ALPHA: f32 = 0.1;
+
+/// Colour of the outline around ghosted sprites
+const OUTLINE: Color = [1.0, 1.0, 1.0, 0.8];
+
+/// Marks a sprite drawn over an actor kept in sight, with how the
+/// renderer draws it
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+#[storage(DenseVecStorage)]
+pub struct Ghosted {
+    /// Tint the sprite is drawn with, on top of its own colour
+    pub tint: Color,
+
+    /// Colour of a border drawn around the sprite, if any
+    pub outline: Option<Color>,
+}
+
+/// Marks an actor that is kept in sight, whether selected or not
+#[derive(Component, Default)]
+#[storage(NullStorage)]
+pub struct Important;
+
+/// How sprites in front of actors are drawn
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum XRayStyle {
+    /// Drawn see-through
+    #[default]
+    Ghost,
+
+    /// Drawn faintly, with an outline of the area they cover
+    Outline,
+}
+
+/// Ghosts the sprites drawn over selected and important actors
+pub struct XRaySystem<I> {
+    texture: PhantomData<I>,
+}
+
+impl<I> XRaySystem<I> {
+    pub fn new() -> Self {
+        XRaySystem {
+            texture: PhantomData,
+        }
+    }
+}
+
+impl<'a, I> System<'a> for XRaySystem<I>
+where
+    I: ImageSize + Send + Sync + 'static,
+{
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, Isometric>,
+        Read<'a, DepthBuffer>,
+        Read<'a, XRayStyle>,
+        ReadStorage<'a, Actor>,
+        ReadStorage<'a, Important>,
+        ReadStorage<'a, Selected>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Sprite<I>>,
+        WriteStorage<'a, Ghosted>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            projection,
+            buffer,
+            style,
+            actors,
+            important,
+            selected,
+            positions,
+            sprites,
+            mut ghosted,
+        ): Self::SystemData,
+    ) {
+        let contents = buffer.contents();
+        let bounds = |e: Entity| {
+            let pos = positions.get(e)?;
+            let sprite = sprites.get(e)?;
+            Some((
+                pos,
+                sprite.bounds_at(&projection.to_screen(pos.to_vector())),
+            ))
+        };
+
+        // Actors to keep in sight, with where they are in the draw order
+        let targets = contents
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| {
+                let e = entities.entity(item.entity_id());
+                let kept = important.contains(e) || selected.contains(e);
+                if !actors.contains(e) || !kept {
+                    return None;
+                }
+                bounds(e).map(|(pos, rect)| (i, item.depth(), pos.z().floor(), rect))
+            })
+            .collect::<Vec<_>>();
+
+        ghosted.clear();
+        for (i, item) in contents.iter().enumerate() {
+            let e = entities.entity(item.entity_id());
+            let occludes = match bounds(e) {
+                Some((pos, rect)) => targets.iter().any(|(j, depth, z, target)| {
+                    // Only what is closer to the viewer, and not below the
+                    // actor's feet, can hide it
+                    i > *j && item.depth() > *depth && pos.z() >= *z && overlaps(&rect, target)
+                }),
+                None => false,
+            };
+
+            if occludes {
+                let ghost = match *style {
+                    XRayStyle::Ghost => Ghosted {
+                        tint: [1.0, 1.0, 1.0, GHOST_ALPHA],
+                        outline: None,
+                    },
+                    XRayStyle::Outline => Ghosted {
+                        tint: [1.0, 1.0, 1.0, OUTLINED_ALPHA],
+                        outline: Some(OUTLINE),
+                    },
+                };
+                ghosted.insert(e, ghost).unwrap();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::canvas::CpuTexture;
+    use crate::depthsort::IsometricSorter;
+    use crate::grid::GridPosition;
+    use crate::view::components::IsometricCamera;
+    use crate::view::{ViewCutMode, Viewport};
+    use image::RgbaImage;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_ghosts_sprites_in_front() {
+        let mut world = World::new();
+        world.add_resource(Isometric::default());
+        world.add_resource(ViewCutMode::default());
+        world.add_resource(Viewport::new(640., 480.));
+        world.add_resource(DepthBuffer::new());
+        world.add_resource(XRayStyle::default());
+        world.register::<Actor>();
+        world.register::<Ghosted>();
+        world.register::<Important>();
+        world.register::<Selected>();
+        world.register::<IsometricCamera>();
+        world.register::<Position>();
+        world.register::<Sprite<CpuTexture>>();
+
+        world
+            .create_entity()
+            .with(IsometricCamera::new(true))
+            .with(Position::new(2., 2., 2.))
+            .build();
+
+        let (anchor_x, anchor_y) = Isometric::default().block_anchor();
+        let block_tex = Arc::new(CpuTexture::from_image(RgbaImage::new(80, 90)));
+        let mut block = |x, y, z| {
+            let mut sprite = Sprite::from_texture(block_tex.clone());
+            sprite.set_anchor(anchor_x, anchor_y);
+            world
+                .create_entity()
+                .with(GridPosition::new(x, y, z).center())
+                .with(sprite)
+                .build()
+        };
+        let floor_behind = block(1, 1, 0);
+        let floor_in_front = block(2, 2, 0);
+        let column = [block(2, 2, 1), block(2, 2, 2)];
+        let wall_behind = block(0, 1, 1);
+
+        let mut sprite =
+            Sprite::from_texture(Arc::new(CpuTexture::from_image(RgbaImage::new(30, 60))));
+        sprite.set_anchor(0.5, 0.9);
+        let actor = world
+            .create_entity()
+            .with(Actor::new())
+            .with(GridPosition::new(1, 1, 1).center())
+            .with(sprite)
+            .build();
+
+        let alpha = |world: &mut World, e| {
+            IsometricSorter::<CpuTexture>::with_size(8, 8, 8).run_now(&world.res);
+            XRaySystem::<CpuTexture>::new().run_now(&world.res);
+            world
+                .read_storage::<Ghosted>()
+                .get(e)
+                .map(|ghost| ghost.tint[3])
+                .unwrap_or(1.0)
+        };
+
+        // Nothing to keep in sight yet
+        assert_eq!(1.0, alpha(&mut world, column[0]));
+
+        world
+            .write_storage::<Important>()
+            .insert(actor, Important)
+            .unwrap();
+        for e in column.iter() {
+            assert_eq!(GHOST_ALPHA, alpha(&mut world, *e));
+        }
+        for e in [floor_behind, floor_in_front, wall_behind, actor].iter() {
+            assert_eq!(1.0, alpha(&mut world, *e));
+        }
+
+        // Outlines only show on ghosted sprites
+        world.add_resource(XRayStyle::Outline);
+        assert_eq!(OUTLINED_ALPHA, alpha(&mut world, column[1]));
+        {
+            let ghosted = world.read_storage::<Ghosted>();
+            assert_eq!(Some(OUTLINE), ghosted.get(column[1]).unwrap().outline);
+            assert!(!ghosted.contains(wall_behind));
+
+            // Sprites keep their own colour
+            let sprites = world.read_storage::<Sprite<CpuTexture>>();
+            assert_eq!(&[1., 1., 1., 1.], sprites.get(column[1]).unwrap().color());
+        }
+
+        // Without the actor, the column is drawn solid again
+        world.delete_entity(actor).unwrap();
+        world.maintain();
+        assert_eq!(1.0, alpha(&mut world, column[0]));
+    }
+}